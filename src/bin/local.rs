@@ -40,9 +40,10 @@ use raytracer::SceneBuilder;
 fn main() {
     ThreadPoolBuilder::new().num_threads(8).build_global().unwrap();
     for i in 0..100 {
-        let builder = SceneBuilder { time: i };
+        let builder = SceneBuilder::new(i);
         let mut renderer = Renderer::new(builder.scene());
         renderer.render();
+        println!("{:?}", renderer.stats());
         let dir = Path::new("output/local").join(format!("{}", i));
         fs::create_dir_all(&dir).unwrap();
         for (name, image) in renderer.images() {
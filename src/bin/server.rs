@@ -13,7 +13,7 @@ use raytracer::render::renderer::Renderer;
 use raytracer::SceneBuilder;
 
 fn render(time: usize) -> Response<Body> {
-    let builder = SceneBuilder { time };
+    let builder = SceneBuilder::new(time);
     let mut renderer = Renderer::new(builder.scene());
     renderer.render();
     let mut content = vec![];
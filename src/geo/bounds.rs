@@ -4,7 +4,7 @@ use arrayvec::ArrayVec;
 use itertools::Itertools;
 use rand::prelude::Distribution;
 use rand::Rng;
-use crate::math::scalar::Scalar;
+use crate::math::scalar::{gamma, Scalar};
 use crate::geo::ray::Ray;
 use crate::math::vec::Vec3;
 use crate::util::itertools2::Itertools2;
@@ -22,6 +22,9 @@ pub struct Interval<T> {
 
 impl<T: Scalar> Bounds<T> {
     pub fn new(min: Vec3<T>, max: Vec3<T>) -> Self { Bounds { min, max } }
+    pub fn full() -> Self {
+        Bounds { min: Vec3::broadcast(f64::NEG_INFINITY.into()), max: Vec3::broadcast(f64::INFINITY.into()) }
+    }
     pub fn empty() -> Self {
         Bounds { min: Vec3::broadcast(f64::INFINITY.into()), max: Vec3::broadcast(f64::NEG_INFINITY.into()) }
     }
@@ -31,6 +34,28 @@ impl<T: Scalar> Bounds<T> {
             max: self.max.zip(other.max).map(|(x, y)| T::maximum(x, y)),
         }
     }
+    // Empty (with min > max on some axis) when the two are disjoint.
+    pub fn intersect(&self, other: &Self) -> Bounds<T> {
+        Bounds {
+            min: self.min.zip(other.min).map(|(x, y)| T::maximum(x, y)),
+            max: self.max.zip(other.max).map(|(x, y)| T::minimum(x, y)),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|i| self.min[i] > self.max[i])
+    }
+    pub fn is_finite(&self) -> bool {
+        (0..3).all(|i| self.min[i].is_finite() && self.max[i].is_finite())
+    }
+    pub fn corners(&self) -> [Vec3<T>; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+            Vec3::new(
+                if i & 4 == 0 { self.min.x() } else { self.max.x() },
+                if i & 2 == 0 { self.min.y() } else { self.max.y() },
+                if i & 1 == 0 { self.min.z() } else { self.max.z() },
+            )
+        })
+    }
     pub fn min(&self) -> Vec3<T> { self.min }
     pub fn max(&self) -> Vec3<T> { self.max }
     pub fn min_mut(&mut self) -> &mut Vec3<T> { &mut self.min }
@@ -53,19 +78,6 @@ impl<T: Scalar> Bounds<T> {
         }
         total.sqrt()
     }
-    // pub fn corners(&self) -> [Vec3<T>; 8] {
-    //     [
-    //         Vec3::new(self.min.x(), self.min.y(), self.min.z()),
-    //         Vec3::new(self.min.x(), self.min.y(), self.max.z()),
-    //         Vec3::new(self.min.x(), self.max.y(), self.min.z()),
-    //         Vec3::new(self.min.x(), self.max.y(), self.max.z()),
-    //         Vec3::new(self.max.x(), self.min.y(), self.min.z()),
-    //         Vec3::new(self.max.x(), self.min.y(), self.max.z()),
-    //         Vec3::new(self.max.x(), self.max.y(), self.min.z()),
-    //         Vec3::new(self.max.x(), self.max.y(), self.max.z()),
-    //     ]
-    // }
-
     pub fn range(&self, index: usize) -> Range<T> { self.min[index]..self.max[index] }
     pub fn dim(&self, index: usize) -> T {
         self.max[index] - self.min[index]
@@ -77,6 +89,9 @@ impl<T: Scalar> Bounds<T> {
     pub fn cast<T2: From<T>>(&self) -> Bounds<T2> {
         Bounds { min: self.min.cast(), max: self.max.cast() }
     }
+    pub fn convert<T2: Scalar>(&self) -> Bounds<T2> {
+        Bounds { min: self.min.map(|x| T2::from(x.into_const())), max: self.max.map(|x| T2::from(x.into_const())) }
+    }
     pub fn raycast(&self, ray: &Ray<T>) -> Option<Interval<T>> {
         let mut interval = Interval::full();
         for i in 0..3 {
@@ -88,6 +103,38 @@ impl<T: Scalar> Bounds<T> {
         }
         Some(interval)
     }
+    // Like raycast, but multiplying by a precomputed inverse direction. The far end is widened
+    // to make up for rounding differently from division, so that grazing hits are kept. It is
+    // moved by its magnitude, since scaling a negative time would pull it in instead.
+    pub fn raycast_inverse(&self, orig: Vec3<T>, inv_dir: Vec3<T>) -> Option<Interval<T>> {
+        let slack = T::from(2.0 * gamma::<T>(3));
+        let mut interval = Interval::full();
+        for i in 0..3 {
+            let a = (self.min[i] - orig[i]) * inv_dir[i];
+            let b = (self.max[i] - orig[i]) * inv_dir[i];
+            if a.is_finite() && b.is_finite() {
+                let far = a.maximum(b);
+                interval = interval.intersect(&Interval::new(a.minimum(b), far + far.abs() * slack))?;
+            }
+        }
+        Some(interval)
+    }
+}
+
+impl Bounds<f64> {
+    // Converts to another precision, rounding outwards so that the result still contains self.
+    pub fn round_out<T: Scalar>(&self) -> Bounds<T> {
+        fn round<T: Scalar>(x: f64, direction: f64) -> T {
+            let mut result = T::from(x);
+            let mut step = x.abs() * f64::EPSILON;
+            while (result.into_const() - x) * direction < 0.0 {
+                result = T::from(x + direction * step);
+                step *= 2.0;
+            }
+            result
+        }
+        Bounds { min: self.min.map(|x| round(x, -1.0)), max: self.max.map(|x| round(x, 1.0)) }
+    }
 }
 
 impl<T: Scalar> Interval<T> {
@@ -97,6 +144,8 @@ impl<T: Scalar> Interval<T> {
     pub fn full() -> Self {
         Interval { min: f64::NEG_INFINITY.into(), max: f64::INFINITY.into() }
     }
+    pub fn min(&self) -> T { self.min }
+    pub fn max(&self) -> T { self.max }
     pub fn intersect(&self, other: &Self) -> Option<Self> {
         let min = self.min.maximum(other.min);
         let max = self.max.minimum(other.max);
@@ -125,4 +174,14 @@ impl Distribution<Vec3<f64>> for Bounds<f64> {
             rng.gen_range(self.range(1)),
             rng.gen_range(self.range(2)))
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_round_out() {
+    use crate::math::scalar::F32;
+    let bounds = Bounds::new(Vec3::new(0.1, -0.3, 1e-9), Vec3::new(0.7, 1.0 / 3.0, 12345.678));
+    let rounded = bounds.round_out::<F32>();
+    for i in 0..3 {
+        assert!(rounded.min()[i].into_const() <= bounds.min()[i]);
+        assert!(rounded.max()[i].into_const() >= bounds.max()[i]);
+    }
+}
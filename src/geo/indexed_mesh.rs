@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::mem;
+use crate::geo::bounds::Bounds;
+use crate::geo::ray::Ray;
+use crate::geo::triangle::{Sidedness, Triangle};
+use crate::geo::winding::vertex_key;
+use crate::math::scalar::Scalar;
+use crate::math::vec::{Vec2, Vec3};
+use crate::render::material::Material;
+use crate::render::object::{Manifold, RaycastPoint};
+
+/// Triangles as indices into a shared vertex buffer. The optional attribute streams are indexed
+/// like the positions.
+#[derive(Clone, Debug, Default)]
+pub struct IndexedMesh {
+    positions: Vec<Vec3<f64>>,
+    indices: Vec<[u32; 3]>,
+    normals: Option<Vec<Vec3<f64>>>,
+    uvs: Option<Vec<Vec2<f64>>>,
+}
+
+impl IndexedMesh {
+    pub fn new(positions: Vec<Vec3<f64>>, indices: Vec<[u32; 3]>) -> Self {
+        assert!(indices.iter().flatten().all(|&i| (i as usize) < positions.len()));
+        IndexedMesh { positions, indices, normals: None, uvs: None }
+    }
+    // Merges vertices at bitwise equal positions.
+    pub fn from_triangles(tris: impl IntoIterator<Item=Triangle<f64>>) -> Self {
+        let mut positions = vec![];
+        let mut lookup = HashMap::new();
+        let indices = tris.into_iter().map(|tri| {
+            tri.vertices().map(|v| {
+                *lookup.entry(vertex_key(v)).or_insert_with(|| {
+                    positions.push(v);
+                    (positions.len() - 1) as u32
+                })
+            })
+        }).collect();
+        IndexedMesh::new(positions, indices)
+    }
+    pub fn with_normals(self, normals: Vec<Vec3<f64>>) -> Self {
+        assert_eq!(normals.len(), self.positions.len());
+        IndexedMesh { normals: Some(normals), ..self }
+    }
+    pub fn with_uvs(self, uvs: Vec<Vec2<f64>>) -> Self {
+        assert_eq!(uvs.len(), self.positions.len());
+        IndexedMesh { uvs: Some(uvs), ..self }
+    }
+    // The same triangles over moved vertices. Vertex normals, if any, are recomputed for them.
+    pub fn with_positions(&self, positions: Vec<Vec3<f64>>) -> Self {
+        assert_eq!(positions.len(), self.positions.len());
+        let mesh = IndexedMesh { positions, indices: self.indices.clone(), normals: None, uvs: self.uvs.clone() };
+        match &self.normals {
+            None => mesh,
+            Some(normals) => {
+                let normals = mesh.vertex_normals(normals);
+                mesh.with_normals(normals)
+            }
+        }
+    }
+    // Averages the normals of the faces around each vertex, weighted by area. Vertices without a
+    // face of any area keep their old normal.
+    fn vertex_normals(&self, old: &[Vec3<f64>]) -> Vec<Vec3<f64>> {
+        let mut sums = vec![Vec3::broadcast(0.0); self.positions.len()];
+        for &[a, b, c] in &self.indices {
+            let [pa, pb, pc] = [a, b, c].map(|i| self.positions[i as usize]);
+            // Twice the area along the normal.
+            let cross = (pb - pa).cross(pc - pa);
+            for i in [a, b, c] {
+                sums[i as usize] += cross;
+            }
+        }
+        sums.into_iter().zip(old).map(|(sum, &old)| if sum == Vec3::broadcast(0.0) { old } else { sum.normalize() }).collect()
+    }
+    pub fn positions(&self) -> &[Vec3<f64>] { &self.positions }
+    pub fn indices(&self) -> &[[u32; 3]] { &self.indices }
+    pub fn triangle_count(&self) -> usize { self.indices.len() }
+    pub fn triangle(&self, index: u32) -> Triangle<f64> {
+        Triangle::new(self.indices[index as usize].map(|i| self.positions[i as usize]))
+    }
+    pub fn triangle_bounds(&self, index: u32) -> Bounds<f64> {
+        self.triangle(index).bounds()
+    }
+    pub fn uv(&self, index: u32, barycenter: Vec3<f64>) -> Option<Vec2<f64>> {
+        let uvs = self.uvs.as_ref()?;
+        Some((0..3).map(|i| uvs[self.indices[index as usize][i] as usize] * barycenter[i]).sum())
+    }
+    pub fn memory_size(&self) -> usize {
+        self.positions.len() * mem::size_of::<Vec3<f64>>()
+            + self.indices.len() * mem::size_of::<[u32; 3]>()
+            + self.normals.as_ref().map_or(0, |x| x.len() * mem::size_of::<Vec3<f64>>())
+            + self.uvs.as_ref().map_or(0, |x| x.len() * mem::size_of::<Vec2<f64>>())
+    }
+    // The shading normal is interpolated from the vertex normals when there are any.
+    pub fn raycast<T: Scalar>(&self, index: u32, ray: &Ray<T>, sidedness: Sidedness, manifold: bool) -> Option<RaycastPoint<T>> {
+        let point = self.triangle(index).cast::<T>().raycast(ray, sidedness, manifold)?;
+        let inter_normal = match &self.normals {
+            None => point.geo_normal(),
+            Some(normals) => {
+                let [i0, i1, i2] = self.indices[index as usize].map(|i| normals[i as usize].cast::<T>());
+                let b = point.barycenter();
+                (i0 * b[0] + i1 * b[1] + i2 * b[2]).normalize()
+            }
+        };
+        Some(RaycastPoint {
+            time: point.time(),
+            position: point.position(),
+            inter_normal,
+            geo_normal: point.geo_normal(),
+            error: point.error(),
+            manifold: Manifold::empty(),
+            manifold_point: point.manifold_point(),
+            material: Material::nan(),
+            // A ray along an edge could hit either neighbour.
+            ambiguous: !manifold && point.barycenter().into_iter().any(|x| x.overlaps(T::from(0.0))),
+        })
+    }
+}
+
+#[test]
+fn test_shared_vertices() {
+    let v = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+    let mesh = IndexedMesh::from_triangles([
+        Triangle::new([v[0], v[1], v[2]]),
+        Triangle::new([v[0], v[2], v[3]]),
+    ]).with_uvs(vec![Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0), Vec2::new(0.0, 1.0)]);
+    assert_eq!(mesh.positions().len(), 4);
+    assert_eq!(mesh.indices(), &[[0, 1, 2], [0, 2, 3]]);
+    let ray = Ray::new(Vec3::new(0.25, 0.75, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(mesh.raycast(0, &ray, Sidedness::Front, false).is_none());
+    let hit = mesh.raycast(1, &ray, Sidedness::Front, false).unwrap();
+    let uv = mesh.uv(1, Vec3::new(hit.manifold_point.x(), hit.manifold_point.y(), 1.0 - hit.manifold_point.x() - hit.manifold_point.y())).unwrap();
+    assert!(uv.distance(Vec2::new(0.25, 0.75)) < 1e-12);
+}
+
+#[test]
+fn test_hit_error_bound() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    let mut rng = SmallRng::seed_from_u64(5);
+    // Far from the origin and tilted, so that the hit positions round noticeably.
+    let offset = Vec3::new(1000.0, -300.0, 70.0);
+    let mesh = IndexedMesh::new(vec![offset + Vec3::new(0.0, 0.0, 0.3), offset + Vec3::new(1.0, 0.1, 0.0), offset + Vec3::new(0.2, 1.0, -0.4)], vec![[0, 1, 2]]);
+    let tri = mesh.triangle(0);
+    let normal = tri.normal().normalize();
+    let mut hits = 0;
+    for _ in 0..1000 {
+        let target = offset + Vec3::new(rng.gen_range(0.0..0.5), rng.gen_range(0.0..0.5), 0.0);
+        let orig = target + Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 3.0);
+        let ray = Ray::new(orig, (target - orig).normalize());
+        if let Some(hit) = mesh.raycast(0, &ray, Sidedness::Both, false) {
+            hits += 1;
+            // The difference to a nearby vertex is exact, so the plane distance rounds only slightly.
+            let distance = (hit.position - tri.vertices()[0]).dot(normal).abs();
+            assert!(distance <= hit.error.dot(normal.map(f64::abs)), "{} {:?}", distance, hit.error);
+        }
+    }
+    assert!(hits > 100);
+}
+
+#[test]
+fn test_moved_normals() {
+    use crate::mesh::uv_sphere;
+    let sphere = uv_sphere(1.0, 16, 32);
+    // Squashed into an ellipsoid, whose normals tilt towards the short axis.
+    let squashed = sphere.with_positions(sphere.positions().iter().map(|p| Vec3::new(p.x(), p.y() * 0.5, p.z())).collect());
+    let normals = squashed.normals.as_ref().unwrap();
+    for (p, n) in squashed.positions().iter().zip(normals) {
+        let exact = Vec3::new(p.x(), p.y() * 4.0, p.z()).normalize();
+        assert!(n.dot(exact) > 0.99, "{:?} {:?}", n, exact);
+    }
+    assert!(sphere.with_positions(sphere.positions().to_vec()).normals.is_some());
+    let flat = IndexedMesh::from_triangles([Triangle::new([Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)])]);
+    assert!(flat.with_positions(flat.positions().to_vec()).normals.is_none());
+}
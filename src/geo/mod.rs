@@ -10,3 +10,6 @@ pub mod triangle;
 // mod sphere;
 pub mod view;
 pub mod axis_plane;
+pub mod winding;
+pub mod indexed_mesh;
+pub mod vertex_animation;
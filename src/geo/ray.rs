@@ -7,17 +7,27 @@ pub struct Ray<T> {
     dir: Vec3<T>,
 }
 
-const EPSILON: f64 = 1e-5;
-
 impl<T: Scalar> Ray<T> {
     pub fn new(orig: Vec3<T>, dir: Vec3<T>) -> Self {
         Ray { orig, dir }
     }
-    pub fn new_bounce(orig: Vec3<T>, dir: Vec3<T>) -> Self {
-        Self::new(orig + dir * T::from(EPSILON), dir)
+    // Starts a secondary ray at a surface point whose position is only known to within error
+    // (per component). The origin is pushed along the geometric normal just past that error, onto
+    // the side dir leaves towards, so the ray cannot hit the surface it starts from.
+    pub fn new_bounce(orig: Vec3<T>, error: Vec3<f64>, normal: Vec3<T>, dir: Vec3<T>) -> Self {
+        let abs_normal = normal.map(|x| x.into_const().abs());
+        // The margin covers the rounding of the addition below.
+        let margin = orig.map(|x| x.into_const().abs() * 2.0 * T::unit_roundoff());
+        let mut offset = normal * T::from(abs_normal.dot(error + margin));
+        if dir.dot(normal) < T::from(0.0) {
+            offset = -offset;
+        }
+        Self::new(orig + offset, dir)
     }
     pub fn orig(&self) -> Vec3<T> { self.orig }
     pub fn dir(&self) -> Vec3<T> { self.dir }
+    // Traversals compute this once per ray and pass it to Bounds::raycast_inverse.
+    pub fn inv_dir(&self) -> Vec3<T> { self.dir.map(|x| T::from(1.0) / x) }
     pub fn pos(&self, time: T) -> Vec3<T> {
         self.orig + self.dir * time
     }
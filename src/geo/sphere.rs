@@ -5,15 +5,16 @@ use crate::geo::ray::Ray;
 use roots::find_roots_quadratic;
 use crate::geo::color::Color;
 use crate::math::mat::Mat2;
-use crate::math::scalar::{Der, Scalar};
+use crate::math::scalar::{Der, gamma, HyperDer, Scalar};
 use crate::render::object::{Manifold, RaycastPoint};
 use crate::math::vec::{Vec2, Vec3};
 use crate::render::material::Material;
+use crate::render::param::Param;
 
 #[derive(Debug)]
 pub struct Sphere {
-    orig: Vec3<f64>,
-    rad: f64,
+    orig: Vec3<Param>,
+    rad: Param,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -38,6 +39,12 @@ impl ZenithY<f64> {
     pub fn as_input(&self) -> ZenithY<Der<2>> {
         ZenithY(self.0.as_input())
     }
+    pub fn as_input_at<B: Scalar>(&self) -> ZenithY<Der<2, B>> {
+        ZenithY(self.0.as_input_at())
+    }
+    pub fn as_hyper_input(&self) -> ZenithY<HyperDer<2>> {
+        ZenithY(self.0.as_hyper_input())
+    }
 }
 
 pub struct Fibonacci<T>(Vec2<T>);
@@ -65,10 +72,15 @@ impl FibonacciProjection {
 
 impl Sphere {
     pub fn new(orig: Vec3<f64>, rad: f64) -> Self {
+        Sphere { orig: orig.map(Param::new), rad: Param::new(rad) }
+    }
+    pub fn with_params(orig: Vec3<Param>, rad: Param) -> Self {
         Sphere { orig, rad }
     }
-    pub fn orig(&self) -> Vec3<f64> { self.orig }
-    pub fn rad(&self) -> f64 { self.rad }
+    pub fn orig(&self) -> Vec3<f64> { self.orig.map(|x| x.value) }
+    pub fn rad(&self) -> f64 { self.rad.value }
+    pub fn orig_param<T: Scalar>(&self) -> Vec3<T> { self.orig.map(|x| x.get()) }
+    pub fn rad_param<T: Scalar>(&self) -> T { self.rad.get() }
     pub fn fibonacci_sphere(count: usize, rng: &mut impl Rng) -> Vec<ZenithY<f64>> {
         let proj = FibonacciProjection::new(count);
         (0..count).map(|i| {
@@ -81,28 +93,34 @@ impl Sphere {
     pub fn raycast<T: Scalar>(&self, ray: &Ray<T>) -> Option<RaycastPoint<T>> {
         let e = ray.orig();
         let d = ray.dir();
-        let o: Vec3<T> = self.orig().cast();
-        let r = self.rad();
-        let a = d.dot(d);
+        let o: Vec3<T> = self.orig_param();
+        let r: T = self.rad_param();
+        let a = d.square_length();
         let b = T::from(2.0) * (e - o).dot(d);
-        let c = (e - o).dot(e - o) - T::from(self.rad * self.rad);
+        let c = (e - o).square_length() - r.square();
         let roots = find_roots_quadratic(a, b, c);
+        // Either root could be the closest one in front of the origin.
+        let ambiguous = roots.as_ref().iter().any(|x| x.overlaps(T::from(0.0)))
+            || matches!(roots.as_ref(), [x, y] if x.overlaps(*y));
         roots
             .as_ref().iter()
             .filter(|x| **x >= T::from(0.0))
             .min_by(|x, y| T::real_cmp(**x, **y))
             .map(|t| {
-                let position = ray.pos(*t);
-                let disp = position - o;
+                let disp = ray.pos(*t) - o;
                 let norm = disp.normalize();
+                // Reprojecting onto the surface bounds the error independently of t.
+                let position = o + norm * r;
                 RaycastPoint {
                     time: *t,
                     position,
                     inter_normal: norm,
                     geo_normal: norm,
+                    error: position.map(|x| x.into_const().abs() * gamma::<T>(5)),
                     manifold: Manifold::empty(),
                     manifold_point: Vec2::new(norm.x(), norm.y()),
                     material: Material::nan(),
+                    ambiguous,
                 }
             })
     }
@@ -120,4 +138,27 @@ fn test_projection() {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_radius_derivative() {
+    let sphere = Sphere::with_params(Vec3::new(0.0, 0.0, -3.0).map(Param::new), Param::var(1.0, 0));
+    let ray = Ray::new(Vec3::<Der<1>>::default(), Vec3::new(0.0, 0.0, -1.0).cast());
+    let hit = sphere.raycast(&ray).unwrap();
+    assert_eq!(hit.time.v, 2.0);
+    assert!((hit.time.d[0] + 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_bounce_offset() {
+    let center = Vec3::new(1000.0, 2000.0, 3000.0);
+    let sphere = Sphere::new(center, 0.002);
+    let orig = center + Vec3::new(0.0013, 0.0007, 1.0);
+    let ray = Ray::new(orig, (center - orig).normalize());
+    let hit = sphere.raycast(&ray).unwrap();
+    let outward = Ray::new_bounce(hit.position, hit.error, hit.geo_normal, hit.geo_normal);
+    assert!(sphere.raycast(&outward).is_none());
+    let inward = Ray::new_bounce(hit.position, hit.error, hit.geo_normal, ray.dir());
+    let exit = sphere.raycast(&inward).unwrap();
+    assert!(exit.time > 0.001);
+}
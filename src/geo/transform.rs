@@ -1,7 +1,7 @@
 use std::ops::{Mul, Neg};
 use crate::geo::color::Color;
 use crate::math::mat::{Mat3, Mat4};
-use crate::math::scalar::Scalar;
+use crate::math::scalar::{gamma, Scalar};
 use crate::geo::ray::Ray;
 use crate::math::vec::Vec3;
 
@@ -78,6 +78,15 @@ impl<T: Scalar> Transform<T> {
     pub fn forward_norm(&self, norm: Vec3<T>) -> Vec3<T> {
         (self.forward_norm * norm).normalize()
     }
+    pub fn forward_pos(&self, pos: Vec3<T>) -> Vec3<T> {
+        self.forward.transform_position(pos)
+    }
+    // Error bound of forward_pos(pos), given a bound on the error of pos.
+    pub fn forward_pos_error(&self, pos: Vec3<T>, error: Vec3<f64>) -> Vec3<f64> {
+        let abs = self.forward.map(|x| x.into_const().abs());
+        let abs_pos = pos.map(|x| x.into_const().abs());
+        abs.transform_tangent(error) * (gamma::<T>(3) + 1.0) + abs.transform_position(abs_pos) * gamma::<T>(3)
+    }
 }
 
 impl Default for Transform<f64> {
@@ -1,29 +1,50 @@
 use crate::geo::bounds::Bounds;
 use crate::geo::ray::Ray;
 use ordered_float::NotNan;
-use crate::math::scalar::Scalar;
+use crate::math::scalar::{gamma, Scalar};
 use crate::math::vec::{Vec2, Vec3};
 
 pub struct Triangle<T> {
     vertices: [Vec3<T>; 3],
 }
 
+// Which faces a ray can hit, by the sign of the ray direction against the winding normal.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Sidedness {
+    // Only faces wound counterclockwise as seen from the ray origin.
+    Front,
+    Back,
+    Both,
+}
+
+impl Sidedness {
+    pub fn culls<T: Scalar>(self, facing: T) -> bool {
+        match self {
+            Sidedness::Front => facing > T::from(0.0),
+            Sidedness::Back => facing < T::from(0.0),
+            Sidedness::Both => false,
+        }
+    }
+}
+
 pub struct TrianglePoint<T> {
     time: T,
     barycenter: Vec3<T>,
     position: Vec3<T>,
     manifold_point: Vec2<T>,
     geo_normal: Vec3<T>,
+    error: Vec3<f64>,
 }
 
 impl<T: Copy> TrianglePoint<T> {
-    pub fn new(time: T, barycenter: Vec3<T>, position: Vec3<T>, manifold_point: Vec2<T>, geo_normal: Vec3<T>) -> Self {
+    pub fn new(time: T, barycenter: Vec3<T>, position: Vec3<T>, manifold_point: Vec2<T>, geo_normal: Vec3<T>, error: Vec3<f64>) -> Self {
         TrianglePoint {
             time,
             barycenter,
             position,
             manifold_point,
             geo_normal,
+            error,
         }
     }
     pub fn time(&self) -> T { self.time }
@@ -31,6 +52,7 @@ impl<T: Copy> TrianglePoint<T> {
     pub fn position(&self) -> Vec3<T> { self.position }
     pub fn manifold_point(&self) -> Vec2<T> { self.manifold_point }
     pub fn geo_normal(&self) -> Vec3<T> { self.geo_normal }
+    pub fn error(&self) -> Vec3<f64> { self.error }
 }
 
 impl<T: Scalar> Triangle<T> {
@@ -51,33 +73,126 @@ impl<T: Scalar> Triangle<T> {
         Triangle { vertices: self.vertices.map(|x| x.cast()) }
     }
 
-    pub fn raycast(&self, ray: &Ray<T>, manifold: bool) -> Option<TrianglePoint<T>> {
+    // Watertight intersection (Woop, Benthin and Wald 2013): the ray is permuted and sheared so
+    // that it runs along +z from the origin, and the edge functions are evaluated in 2D. Shared
+    // edges then produce bitwise identical edge functions on both sides, so no ray slips through.
+    // With manifold, the triangle's plane is extended and barycentric coordinates may be negative,
+    // and no face is culled.
+    pub fn raycast(&self, ray: &Ray<T>, sidedness: Sidedness, manifold: bool) -> Option<TrianglePoint<T>> {
         let [v0, v1, v2] = self.vertices;
         let nt = (v1 - v0).cross(v2 - v1);
-        let denom = nt.dot(nt);
-        if nt.dot(ray.dir()) > 0.0.into() && !manifold {
+        if !manifold && sidedness.culls(nt.dot(ray.dir())) {
             return None;
         }
-        let e = ray.orig();
+        let (kx, ky, kz) = permutation(ray.dir().map(|x| x.into_const()));
         let d = ray.dir();
-        let t = (v0 - e).dot(nt) / d.dot(nt);
-        if t < 0.0.into() && !manifold {
-            return None;
-        }
-        let q = ray.pos(t);
-        let mut barycenter = Vec3::default();
-        for i in 0..3 {
-            let (va, vb) = (self.vertices[i], self.vertices[(i + 1) % 3]);
-            let c = (vb - va).cross(q - va).dot(nt) / denom;
-            if c < 0.0.into() && !manifold {
+        let shear = (d[kx] / d[kz], d[ky] / d[kz], T::from(1.0) / d[kz]);
+        let [a, b, c] = self.vertices.map(|v| {
+            let v = v - ray.orig();
+            Vec3::new(v[kx] - shear.0 * v[kz], v[ky] - shear.1 * v[kz], shear.2 * v[kz])
+        });
+        let u = c.x() * b.y() - c.y() * b.x();
+        let v = a.x() * c.y() - a.y() * c.x();
+        let w = b.x() * a.y() - b.y() * a.x();
+        if !manifold {
+            // Compares the values themselves, since intervals and derivatives compare otherwise.
+            let (su, sv, sw) = if u.into_const() == 0.0 || v.into_const() == 0.0 || w.into_const() == 0.0 {
+                // On an edge within rounding: decide from an accurately rounded f64 evaluation so
+                // that neighbouring triangles agree.
+                self.edge_signs(ray)
+            } else {
+                (u.into_const(), v.into_const(), w.into_const())
+            };
+            if (su < 0.0 || sv < 0.0 || sw < 0.0) && (su > 0.0 || sv > 0.0 || sw > 0.0) {
                 return None;
             }
-            barycenter[(i + 2) % 3] = c;
         }
-        let error = (v0 * barycenter[0]
-            + v1 * barycenter[1]
-            + v2 * barycenter[2] - q).length();
+        let det = u + v + w;
+        if det == 0.0.into() {
+            return None;
+        }
+        let t = (u * a.z() + v * b.z() + w * c.z()) / det;
+        if t < 0.0.into() && !manifold {
+            return None;
+        }
+        let barycenter = Vec3::new(u / det, v / det, w / det);
+        // Interpolating the vertices rather than evaluating the ray keeps the error small.
+        let q = v0 * barycenter[0] + v1 * barycenter[1] + v2 * barycenter[2];
+        let error = (0..3)
+            .map(|i| (self.vertices[i] * barycenter[i]).map(|x| x.into_const().abs()))
+            .sum::<Vec3<f64>>() * gamma::<T>(7);
         let manifold_point = Vec2::new(barycenter.x(), barycenter.y());
-        Some(TrianglePoint::new(t, barycenter, q, manifold_point, nt.normalize()))
+        Some(TrianglePoint::new(t, barycenter, q, manifold_point, nt.normalize(), error))
+    }
+    fn edge_signs(&self, ray: &Ray<T>) -> (f64, f64, f64) {
+        let d = ray.dir().map(|x| x.into_const());
+        let orig = ray.orig().map(|x| x.into_const());
+        let (kx, ky, kz) = permutation(d);
+        let (sx, sy) = (d[kx] / d[kz], d[ky] / d[kz]);
+        let [a, b, c] = self.vertices.map(|v| {
+            let v = v.map(|x| x.into_const()) - orig;
+            Vec2::new(v[kx] - sx * v[kz], v[ky] - sy * v[kz])
+        });
+        (
+            difference_of_products(c.x(), b.y(), c.y(), b.x()),
+            difference_of_products(a.x(), c.y(), a.y(), c.x()),
+            difference_of_products(b.x(), a.y(), b.y(), a.x()),
+        )
+    }
+}
+
+// Axes (kx, ky, kz) with kz the dominant axis of dir, swapping kx and ky to preserve winding.
+fn permutation(dir: Vec3<f64>) -> (usize, usize, usize) {
+    let abs = dir.map(f64::abs);
+    let kz = if abs.x() > abs.y() {
+        if abs.x() > abs.z() { 0 } else { 2 }
+    } else {
+        if abs.y() > abs.z() { 1 } else { 2 }
+    };
+    let (kx, ky) = ((kz + 1) % 3, (kz + 2) % 3);
+    if dir[kz] < 0.0 { (ky, kx, kz) } else { (kx, ky, kz) }
+}
+
+// a * b - c * d, accurate to within 1.5 ulp (Kahan).
+fn difference_of_products(a: f64, b: f64, c: f64, d: f64) -> f64 {
+    let cd = c * d;
+    let error = (-c).mul_add(d, cd);
+    a.mul_add(b, -cd) + error
+}
+
+#[test]
+fn test_watertight() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    // Two triangles sharing the diagonal of a square; rays aimed at the diagonal must hit exactly one.
+    let corners = [
+        Vec3::new(0.1, 0.3, 0.0), Vec3::new(1.7, 0.2, 0.0),
+        Vec3::new(1.9, 1.3, 0.0), Vec3::new(0.3, 1.1, 0.0)];
+    let first = Triangle::new([corners[0], corners[1], corners[2]]);
+    let second = Triangle::new([corners[0], corners[2], corners[3]]);
+    let mut rng = SmallRng::seed_from_u64(1234);
+    for _ in 0..10000 {
+        let along: f64 = rng.gen_range(0.0..1.0);
+        let target = corners[0] * (1.0 - along) + corners[2] * along;
+        let orig = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(1.0..2.0));
+        let ray = Ray::new(orig, (target - orig).normalize());
+        let hits = [&first, &second].iter().filter(|tri| tri.raycast(&ray, Sidedness::Front, false).is_some()).count();
+        assert_eq!(hits, 1);
     }
+    let ray = Ray::new(Vec3::new(5.0, 5.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(first.raycast(&ray, Sidedness::Front, false).is_none());
+    let point = first.raycast(&ray, Sidedness::Front, true).unwrap();
+    assert!((point.barycenter().into_iter().sum::<f64>() - 1.0).abs() < 1e-12);
+    assert!(point.barycenter().into_iter().any(|x| x < 0.0));
+}
+
+#[test]
+fn test_sidedness() {
+    let tri = Triangle::new([Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)]);
+    let down = Ray::new(Vec3::new(0.2, 0.2, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    let up = Ray::new(Vec3::new(0.2, 0.2, -1.0), Vec3::new(0.0, 0.0, 1.0));
+    let hits = |sidedness| (tri.raycast(&down, sidedness, false).is_some(), tri.raycast(&up, sidedness, false).is_some());
+    assert_eq!(hits(Sidedness::Front), (true, false));
+    assert_eq!(hits(Sidedness::Back), (false, true));
+    assert_eq!(hits(Sidedness::Both), (true, true));
 }
@@ -0,0 +1,41 @@
+use crate::geo::indexed_mesh::IndexedMesh;
+use crate::math::vec::Vec3;
+
+// Moves the vertices of a mesh with fixed topology from frame to frame.
+#[derive(Clone, Debug)]
+pub enum VertexAnimation {
+    // All positions for every frame.
+    Frames(Vec<Vec<Vec3<f64>>>),
+    // Offsets from the rest positions, blended with per-frame weights (one per target).
+    Morph {
+        targets: Vec<Vec<Vec3<f64>>>,
+        weights: Vec<Vec<f64>>,
+    },
+}
+
+impl VertexAnimation {
+    pub fn frame_count(&self) -> usize {
+        match self {
+            VertexAnimation::Frames(frames) => frames.len(),
+            VertexAnimation::Morph { weights, .. } => weights.len(),
+        }
+    }
+    pub fn positions(&self, rest: &[Vec3<f64>], frame: usize) -> Vec<Vec3<f64>> {
+        match self {
+            VertexAnimation::Frames(frames) => frames[frame].clone(),
+            VertexAnimation::Morph { targets, weights } => {
+                assert_eq!(weights[frame].len(), targets.len());
+                let mut positions = rest.to_vec();
+                for (target, &weight) in targets.iter().zip(weights[frame].iter()) {
+                    for (position, offset) in positions.iter_mut().zip(target.iter()) {
+                        *position += *offset * weight;
+                    }
+                }
+                positions
+            }
+        }
+    }
+    pub fn mesh_at(&self, rest: &IndexedMesh, frame: usize) -> IndexedMesh {
+        rest.with_positions(self.positions(rest.positions(), frame))
+    }
+}
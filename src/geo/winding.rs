@@ -0,0 +1,128 @@
+use std::collections::{HashMap, VecDeque};
+use crate::geo::triangle::Triangle;
+use crate::math::vec::Vec3;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct WindingReport {
+    pub components: usize,
+    // Faces flipped to agree with their neighbours or to face outwards.
+    pub flipped: usize,
+    // Edges used by only one face; components with any of these are open.
+    pub boundary_edges: usize,
+    // Edges shared by more than two faces, across which winding is not propagated.
+    pub non_manifold_edges: usize,
+    // Components such as a Moebius strip that admit no consistent winding.
+    pub non_orientable: usize,
+}
+
+pub(crate) type VertexKey = [u64; 3];
+
+pub(crate) fn vertex_key(v: Vec3<f64>) -> VertexKey {
+    // Adding 0.0 merges -0.0 into 0.0.
+    [v.x(), v.y(), v.z()].map(|x| (x + 0.0).to_bits())
+}
+
+fn flip(tri: &Triangle<f64>) -> Triangle<f64> {
+    let [v0, v1, v2] = *tri.vertices();
+    Triangle::new([v0, v2, v1])
+}
+
+// Reorients the faces of each connected component so that neighbours traverse their shared edge in
+// opposite directions, then flips closed components with negative signed volume so that normals
+// point outwards. Faces are connected through vertices at bitwise equal positions.
+pub fn orient_consistently(tris: &mut [Triangle<f64>]) -> WindingReport {
+    let mut report = WindingReport::default();
+    // Undirected edge -> faces using it, with whether each traverses it from the smaller key.
+    let mut edges: HashMap<(VertexKey, VertexKey), Vec<(usize, bool)>> = HashMap::new();
+    for (index, tri) in tris.iter().enumerate() {
+        let keys = tri.vertices().map(vertex_key);
+        for i in 0..3 {
+            let (a, b) = (keys[i], keys[(i + 1) % 3]);
+            if a == b {
+                continue;
+            }
+            let key = if a < b { (a, b) } else { (b, a) };
+            edges.entry(key).or_default().push((index, a < b));
+        }
+    }
+    let mut neighbours = vec![vec![]; tris.len()];
+    for faces in edges.values() {
+        match faces.len() {
+            1 => report.boundary_edges += 1,
+            2 => {
+                let [(f1, d1), (f2, d2)] = [faces[0], faces[1]];
+                // Consistent when the two faces run along the edge in opposite directions.
+                let same = d1 == d2;
+                neighbours[f1].push((f2, same));
+                neighbours[f2].push((f1, same));
+            }
+            _ => report.non_manifold_edges += 1,
+        }
+    }
+    let mut flipped: Vec<Option<bool>> = vec![None; tris.len()];
+    for seed in 0..tris.len() {
+        if flipped[seed].is_some() {
+            continue;
+        }
+        report.components += 1;
+        flipped[seed] = Some(false);
+        let mut component = vec![seed];
+        let mut orientable = true;
+        let mut queue = VecDeque::from([seed]);
+        while let Some(face) = queue.pop_front() {
+            let face_flipped = flipped[face].unwrap();
+            for &(other, same) in &neighbours[face] {
+                let wanted = face_flipped ^ same;
+                match flipped[other] {
+                    None => {
+                        flipped[other] = Some(wanted);
+                        component.push(other);
+                        queue.push_back(other);
+                    }
+                    Some(actual) => orientable &= actual == wanted,
+                }
+            }
+        }
+        if !orientable {
+            report.non_orientable += 1;
+        }
+        let closed = orientable && component.iter().all(|&face| neighbours[face].len() == 3);
+        if closed {
+            let volume: f64 = component.iter().map(|&face| {
+                let [v0, v1, v2] = *tris[face].vertices();
+                let volume = v0.dot(v1.cross(v2));
+                if flipped[face].unwrap() { -volume } else { volume }
+            }).sum();
+            if volume < 0.0 {
+                for &face in &component {
+                    flipped[face] = flipped[face].map(|x| !x);
+                }
+            }
+        }
+        for &face in &component {
+            if flipped[face].unwrap() {
+                tris[face] = flip(&tris[face]);
+                report.flipped += 1;
+            }
+        }
+    }
+    report
+}
+
+#[test]
+fn test_orient_tetrahedron() {
+    let v = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)];
+    // Two of the faces are wound inwards.
+    let mut tris = vec![
+        Triangle::new([v[0], v[2], v[1]]),
+        Triangle::new([v[0], v[1], v[3]]),
+        Triangle::new([v[0], v[2], v[3]]),
+        Triangle::new([v[1], v[3], v[2]]),
+    ];
+    let report = orient_consistently(&mut tris);
+    assert_eq!(report, WindingReport { components: 1, flipped: 2, ..WindingReport::default() });
+    let centroid = v.into_iter().sum::<Vec3<f64>>() / 4.0;
+    for tri in &tris {
+        assert!(tri.normal().dot(tri.vertices()[0] - centroid) > 0.0);
+    }
+}
@@ -18,6 +18,7 @@ use std::sync::Arc;
 use crate::geo::color::Color;
 use crate::geo::sphere::Sphere;
 use crate::geo::transform::{Transform, TransformBuilder};
+use crate::geo::triangle::Sidedness;
 use crate::geo::view::View;
 use crate::math::mat::Mat4;
 use crate::math::vec::{Vec2, Vec3};
@@ -25,12 +26,15 @@ use crate::mesh::{bunny, cow, pinecone, sphere};
 use crate::render::any_object::AnyObject;
 use crate::render::material::Material;
 use crate::render::mesh_object::MeshObject;
+use crate::render::param::Param;
 use crate::render::plane_object::PlaneObject;
-use crate::render::renderer::{Light, Renderer, Scene};
+use crate::render::renderer::{Light, Precision, Renderer, Scene};
 use crate::render::scene_object::SceneObject;
 use crate::render::sphere_object::SphereObject;
 use crate::render::transform_object::TransformObject;
 use crate::tree::bvh::Bvh;
+use crate::tree::bvh_refit::AnimatedMesh;
+use crate::tree::bvh_wide::WideBvh;
 
 pub mod util;
 pub mod math;
@@ -41,16 +45,36 @@ pub mod tree;
 
 pub struct SceneBuilder {
     pub time: usize,
+    // Both the storage of the mesh BVHs and the arithmetic rays are traced with.
+    pub precision: Precision,
+    // Traces double precision meshes through four-wide BVH nodes.
+    pub wide: bool,
 }
 
 impl SceneBuilder {
+    pub fn new(time: usize) -> Self {
+        SceneBuilder { time, precision: Precision::Double, wide: false }
+    }
     pub fn material(&self) -> Material {
-        Material { diffuse: Color::new(1.0, 1.0, 1.0) * 0.0, dielectric: Some((1.0, 1.5)) }
+        Material { diffuse: Color::new(1.0, 1.0, 1.0) * 0.0, dielectric: Some((1.0.into(), 1.5.into())), medium: None, conductor: None, thin_film: None }
     }
     pub fn make_mesh(&self, mesh: Arc<Bvh>, transform: Transform<f64>) -> TransformObject<AnyObject> {
-        TransformObject::new(
-            transform,
-            AnyObject::Model(MeshObject::new(mesh, self.material())))
+        // Rays refracted into a dielectric leave through back faces.
+        let material = self.material();
+        let sidedness = if material.dielectric.is_some() { Sidedness::Both } else { Sidedness::Front };
+        let object = match self.precision {
+            Precision::Double if self.wide => {
+                let wide = WideBvh::new(mesh.primitives().clone(), &mesh.tree());
+                AnyObject::ModelWide(MeshObject::new(Arc::new(wide), material).with_sidedness(sidedness))
+            }
+            Precision::Double => AnyObject::Model(MeshObject::new(mesh, material).with_sidedness(sidedness)),
+            Precision::Single => AnyObject::ModelF32(MeshObject::new(Arc::new(mesh.to_precision()), material).with_sidedness(sidedness)),
+        };
+        TransformObject::new(transform, object)
+    }
+    // The animated mesh at this builder's time, which moves it along for the next frame's scene.
+    pub fn animated_mesh(&self, mesh: &mut AnimatedMesh, transform: Transform<f64>) -> TransformObject<AnyObject> {
+        self.make_mesh(mesh.at(self.time), transform)
     }
     pub fn sphere_mesh(&self) -> TransformObject<AnyObject> {
         self.make_mesh(sphere(), TransformBuilder::new().scale(0.002).build())
@@ -66,12 +90,18 @@ impl SceneBuilder {
         self.make_mesh(pinecone(), TransformBuilder::new().scale(0.065).translate(-1.75, 0.0, 0.0).rotate(()).build())
     }
     pub fn sphere(&self) -> TransformObject<AnyObject> {
+        self.sphere_with_ior(1.5.into())
+    }
+    pub fn sphere_with_ior(&self, ior: Param) -> TransformObject<AnyObject> {
         TransformObject::new(Transform::default(), AnyObject::Sphere(SphereObject::new(
             Sphere::new(Vec3::new(0.0, -0.2, 0.0),
                         0.2),
             Material {
                 diffuse: default(),
-                dielectric: Some((1.0, 1.5)),
+                dielectric: Some((1.0.into(), ior)),
+                medium: None,
+                conductor: None,
+                thin_film: None,
             },
         )))
     }
@@ -80,8 +110,8 @@ impl SceneBuilder {
             Vec3::new(0.0, -0.5, 0.0),
             Vec3::new(0.0, 0.0, -1.0),
             Vec3::new(-1.0, 0.0, 0.0),
-            Material { diffuse: Color::new(1.0, 1.0, 1.0), dielectric: None },
-            Material { diffuse: Color::new(0.1, 0.1, 0.1), dielectric: None },
+            Material { diffuse: Color::new(1.0, 1.0, 1.0), dielectric: None, medium: None, conductor: None, thin_film: None },
+            Material { diffuse: Color::new(0.1, 0.1, 0.1), dielectric: None, medium: None, conductor: None, thin_film: None },
         )))
     }
     pub fn view(&self) -> View {
@@ -116,6 +146,27 @@ impl SceneBuilder {
             photon_samples: 3,
             newton_steps: 5,
             newton_epsilon: 0.00001,
+            medium: None,
+            volumes: vec![],
+            volume_samples: 4,
+            volume_photon_samples: 50,
+            polarized: false,
+            spectral: false,
+            precision: self.precision,
+            verify: false,
+            max_specular_depth: 4,
+            specular_roulette: None,
+            seed: None,
+        }
+    }
+    // The scene with the sphere's index of refraction as differentiable parameter 0.
+    pub fn ior_scene(&self, ior: f64) -> Scene<SceneObject> {
+        Scene {
+            scene_object: SceneObject::new(vec![
+                self.sphere_with_ior(Param::var(ior, 0)),
+                self.plane(),
+            ]),
+            ..self.scene()
         }
     }
 }
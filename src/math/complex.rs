@@ -0,0 +1,73 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self { Complex { re, im } }
+    pub fn i() -> Self { Complex::new(0.0, 1.0) }
+    pub fn conj(self) -> Self { Complex::new(self.re, -self.im) }
+    pub fn norm_sqr(self) -> f64 { self.re * self.re + self.im * self.im }
+    pub fn abs(self) -> f64 { self.re.hypot(self.im) }
+    // Principal square root, with a non-negative real part.
+    pub fn sqrt(self) -> Self {
+        let r = self.abs();
+        let re = ((r + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((r - self.re) / 2.0).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+    pub fn exp(self) -> Self {
+        let (sin, cos) = self.im.sin_cos();
+        Complex::new(cos, sin) * self.re.exp()
+    }
+}
+
+impl From<f64> for Complex {
+    fn from(re: f64) -> Self { Complex::new(re, 0.0) }
+}
+
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output { Complex::new(self.re + rhs.re, self.im + rhs.im) }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output { Complex::new(self.re - rhs.re, self.im - rhs.im) }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        Complex::new(self.re * rhs.re - self.im * rhs.im, self.re * rhs.im + self.im * rhs.re)
+    }
+}
+
+impl Mul<f64> for Complex {
+    type Output = Self;
+    fn mul(self, rhs: f64) -> Self::Output { Complex::new(self.re * rhs, self.im * rhs) }
+}
+
+impl Div for Complex {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self::Output {
+        (self * rhs.conj()) * (1.0 / rhs.norm_sqr())
+    }
+}
+
+impl Neg for Complex {
+    type Output = Self;
+    fn neg(self) -> Self::Output { Complex::new(-self.re, -self.im) }
+}
+
+#[test]
+fn test_complex() {
+    let x = Complex::new(3.0, -4.0);
+    let r = x.sqrt();
+    assert!((r * r - x).abs() < 1e-12);
+    assert!(((x / x) - Complex::from(1.0)).abs() < 1e-12);
+    assert!((Complex::new(0.0, std::f64::consts::PI).exp() + Complex::from(1.0)).abs() < 1e-12);
+}
@@ -0,0 +1,305 @@
+use std::cmp::Ordering;
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::iter::{Product, Sum};
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Rem, Sub, SubAssign};
+use roots::FloatType;
+use crate::math::scalar::Scalar;
+
+/// A rigorous enclosure [lo, hi] of a real number. Every operation rounds outwards by one ulp.
+///
+/// Comparisons must still pick a branch, so they decide on the midpoints. Whether a comparison
+/// could have gone either way under rounding is up to the caller to ask, with `Scalar::overlaps`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct IntervalScalar {
+    lo: f64,
+    hi: f64,
+}
+
+fn next_up(x: f64) -> f64 {
+    if x.is_nan() || x == f64::INFINITY {
+        x
+    } else if x == 0.0 {
+        f64::from_bits(1)
+    } else if x > 0.0 {
+        f64::from_bits(x.to_bits() + 1)
+    } else {
+        f64::from_bits(x.to_bits() - 1)
+    }
+}
+
+fn next_down(x: f64) -> f64 {
+    -next_up(-x)
+}
+
+// Minimum and maximum treating NaN (from 0 * inf) as missing.
+fn min4(xs: [f64; 4]) -> f64 { xs.into_iter().fold(f64::INFINITY, f64::min) }
+
+fn max4(xs: [f64; 4]) -> f64 { xs.into_iter().fold(f64::NEG_INFINITY, f64::max) }
+
+impl IntervalScalar {
+    pub fn new(lo: f64, hi: f64) -> Self {
+        IntervalScalar { lo, hi }
+    }
+    pub fn full() -> Self {
+        IntervalScalar::new(f64::NEG_INFINITY, f64::INFINITY)
+    }
+    pub fn lo(&self) -> f64 { self.lo }
+    pub fn hi(&self) -> f64 { self.hi }
+    pub fn width(&self) -> f64 { self.hi - self.lo }
+    pub fn mid(&self) -> f64 {
+        if self.lo == self.hi {
+            self.lo
+        } else if self.lo.is_finite() && self.hi.is_finite() {
+            self.lo / 2.0 + self.hi / 2.0
+        } else {
+            // Keeps the ordering of half-infinite intervals sensible.
+            if self.lo.is_finite() { f64::INFINITY } else if self.hi.is_finite() { f64::NEG_INFINITY } else { 0.0 }
+        }
+    }
+    pub fn contains(&self, x: f64) -> bool {
+        self.lo <= x && x <= self.hi
+    }
+    fn rounded(lo: f64, hi: f64) -> Self {
+        IntervalScalar::new(next_down(lo), next_up(hi))
+    }
+    // Applies a monotonically increasing function.
+    fn increasing(self, f: impl Fn(f64) -> f64) -> Self {
+        Self::rounded(f(self.lo), f(self.hi))
+    }
+    fn decreasing(self, f: impl Fn(f64) -> f64) -> Self {
+        Self::rounded(f(self.hi), f(self.lo))
+    }
+    fn recip(self) -> Self {
+        if self.contains(0.0) {
+            Self::full()
+        } else {
+            self.decreasing(|x| 1.0 / x)
+        }
+    }
+}
+
+impl From<f64> for IntervalScalar {
+    fn from(x: f64) -> Self { IntervalScalar::new(x, x) }
+}
+
+impl From<i16> for IntervalScalar {
+    fn from(x: i16) -> Self { IntervalScalar::from(x as f64) }
+}
+
+impl From<IntervalScalar> for f64 {
+    fn from(x: IntervalScalar) -> Self { x.mid() }
+}
+
+impl PartialEq for IntervalScalar {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for IntervalScalar {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.mid().partial_cmp(&other.mid())
+    }
+}
+
+impl Add for IntervalScalar {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output { Self::rounded(self.lo + rhs.lo, self.hi + rhs.hi) }
+}
+
+impl Sub for IntervalScalar {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output { Self::rounded(self.lo - rhs.hi, self.hi - rhs.lo) }
+}
+
+impl Mul for IntervalScalar {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        let products = [self.lo * rhs.lo, self.lo * rhs.hi, self.hi * rhs.lo, self.hi * rhs.hi];
+        Self::rounded(min4(products), max4(products))
+    }
+}
+
+impl Div for IntervalScalar {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self::Output { self * rhs.recip() }
+}
+
+impl Rem for IntervalScalar {
+    type Output = Self;
+    fn rem(self, rhs: Self) -> Self::Output {
+        let quotient = self / rhs;
+        if quotient.lo.trunc() == quotient.hi.trunc() {
+            self - rhs * IntervalScalar::from(quotient.lo.trunc())
+        } else {
+            let bound = rhs.lo.abs().max(rhs.hi.abs());
+            IntervalScalar::new(-bound, bound)
+        }
+    }
+}
+
+impl Neg for IntervalScalar {
+    type Output = Self;
+    fn neg(self) -> Self::Output { IntervalScalar::new(-self.hi, -self.lo) }
+}
+
+impl AddAssign for IntervalScalar {
+    fn add_assign(&mut self, rhs: Self) { *self = *self + rhs; }
+}
+
+impl SubAssign for IntervalScalar {
+    fn sub_assign(&mut self, rhs: Self) { *self = *self - rhs; }
+}
+
+impl MulAssign for IntervalScalar {
+    fn mul_assign(&mut self, rhs: Self) { *self = *self * rhs; }
+}
+
+impl DivAssign for IntervalScalar {
+    fn div_assign(&mut self, rhs: Self) { *self = *self / rhs; }
+}
+
+impl Sum for IntervalScalar {
+    fn sum<I: Iterator<Item=Self>>(iter: I) -> Self { iter.fold(Self::from(0.0), Self::add) }
+}
+
+impl Product for IntervalScalar {
+    fn product<I: Iterator<Item=Self>>(iter: I) -> Self { iter.fold(Self::from(1.0), Self::mul) }
+}
+
+impl roots::FloatType for IntervalScalar {
+    fn zero() -> Self { Self::from(0.0) }
+    fn one() -> Self { Self::from(1.0) }
+    fn one_third() -> Self { Self::rounded(1.0 / 3.0, 1.0 / 3.0) }
+    fn pi() -> Self { Self::rounded(PI, PI) }
+    fn two_third_pi() -> Self { Self::rounded(2.0 * PI / 3.0, 2.0 * PI / 3.0) }
+    fn sqrt(self) -> Self {
+        if self.hi < 0.0 {
+            IntervalScalar::new(f64::NAN, f64::NAN)
+        } else {
+            Self::rounded(self.lo.max(0.0).sqrt(), self.hi.sqrt()).maximum(Self::from(0.0))
+        }
+    }
+    fn atan(self) -> Self { self.increasing(f64::atan) }
+    fn acos(self) -> Self { self.decreasing(|x| x.clamp(-1.0, 1.0).acos()) }
+    fn sin(self) -> Self {
+        if self.width() >= 2.0 * PI || !self.width().is_finite() {
+            return IntervalScalar::new(-1.0, 1.0);
+        }
+        // Includes the extrema at pi/2 + 2k pi and -pi/2 + 2k pi that fall inside.
+        let has_extremum = |at: f64| {
+            let k = ((self.lo - at) / (2.0 * PI)).ceil();
+            at + k * 2.0 * PI <= self.hi
+        };
+        let (a, b) = (self.lo.sin(), self.hi.sin());
+        let hi = if has_extremum(FRAC_PI_2) { 1.0 } else { next_up(a.max(b)).min(1.0) };
+        let lo = if has_extremum(-FRAC_PI_2) { -1.0 } else { next_down(a.min(b)).max(-1.0) };
+        IntervalScalar::new(lo, hi)
+    }
+    fn cos(self) -> Self {
+        (self + Self::rounded(FRAC_PI_2, FRAC_PI_2)).sin()
+    }
+    fn abs(self) -> Self {
+        if self.lo >= 0.0 {
+            self
+        } else if self.hi <= 0.0 {
+            -self
+        } else {
+            IntervalScalar::new(0.0, (-self.lo).max(self.hi))
+        }
+    }
+    fn powf(self, n: Self) -> Self {
+        if self.lo > 0.0 {
+            (n * self.ln()).exp()
+        } else if n.lo == n.hi && n.lo == n.lo.trunc() && n.lo >= 0.0 {
+            (0..n.lo as usize).map(|_| self).product()
+        } else {
+            Self::full()
+        }
+    }
+}
+
+impl Scalar for IntervalScalar {
+    fn minimum(self, other: Self) -> Self {
+        IntervalScalar::new(self.lo.min(other.lo), self.hi.min(other.hi))
+    }
+    fn maximum(self, other: Self) -> Self {
+        IntervalScalar::new(self.lo.max(other.lo), self.hi.max(other.hi))
+    }
+    fn is_finite(self) -> bool { self.lo.is_finite() && self.hi.is_finite() }
+    fn real_eq(self, other: Self) -> bool { self.lo.real_eq(other.lo) && self.hi.real_eq(other.hi) }
+    fn real_cmp(self, other: Self) -> Ordering {
+        self.mid().real_cmp(other.mid())
+    }
+    fn not_nan(self) -> bool { self.lo.not_nan() && self.hi.not_nan() }
+    fn into_const(self) -> f64 { self.mid() }
+    fn unit_roundoff() -> f64 { f64::unit_roundoff() }
+    fn is_plain() -> bool { false }
+    fn param(v: f64, index: usize) -> Self { Self::from(v) }
+    fn exp(self) -> Self { self.increasing(f64::exp) }
+    fn ln(self) -> Self { self.increasing(|x| x.max(0.0).ln()) }
+    fn tan(self) -> Self {
+        // Monotone between consecutive asymptotes at pi/2 + k pi.
+        let k = ((self.lo - FRAC_PI_2) / PI).ceil();
+        if FRAC_PI_2 + k * PI <= self.hi || !self.is_finite() {
+            Self::full()
+        } else {
+            self.increasing(f64::tan)
+        }
+    }
+    fn asin(self) -> Self { self.increasing(|x| x.clamp(-1.0, 1.0).asin()) }
+    fn atan2(self, x: Self) -> Self {
+        if x.lo > 0.0 {
+            (self / x).atan()
+        } else {
+            Self::rounded(-PI, PI)
+        }
+    }
+    fn sinh(self) -> Self { self.increasing(f64::sinh) }
+    fn cosh(self) -> Self {
+        let hi = self.lo.cosh().max(self.hi.cosh());
+        let lo = if self.contains(0.0) { 1.0 } else { self.lo.cosh().min(self.hi.cosh()) };
+        Self::rounded(lo, hi)
+    }
+    fn tanh(self) -> Self { self.increasing(f64::tanh) }
+    fn square(self) -> Self {
+        let abs = self.abs();
+        Self::rounded(abs.lo * abs.lo, abs.hi * abs.hi).maximum(Self::from(0.0))
+    }
+    fn overlaps(self, other: Self) -> bool {
+        let certain = self.hi < other.lo || other.hi < self.lo || (self.lo == self.hi && other.lo == other.hi);
+        !certain
+    }
+}
+
+#[test]
+fn test_enclosure() {
+    let tenth = IntervalScalar::from(0.1);
+    let sum: IntervalScalar = (0..10).map(|_| tenth).sum();
+    assert!(sum.contains(1.0) && sum.lo() < sum.hi());
+    let x = IntervalScalar::new(-0.5, 3.5);
+    assert!(x.sin().contains(1.0) && x.sin().contains(-0.5f64.sin()));
+    assert!(x.cos().contains(-1.0) && !x.cos().contains(-1.0 - 1e-9));
+    assert!(x.square().contains(0.0) && x.square().contains(12.25) && x.square().lo() >= 0.0);
+    assert!(IntervalScalar::from(1.0) < IntervalScalar::from(2.0));
+    assert!(!IntervalScalar::from(1.0).overlaps(IntervalScalar::from(2.0)));
+    assert!(x > IntervalScalar::from(1.0));
+    assert!(x.overlaps(IntervalScalar::from(1.0)));
+}
+
+#[test]
+fn test_ambiguous_hits() {
+    use crate::geo::indexed_mesh::IndexedMesh;
+    use crate::geo::ray::Ray;
+    use crate::geo::triangle::{Sidedness, Triangle};
+    use crate::math::vec::Vec3;
+    let v = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+    let mesh = IndexedMesh::from_triangles([Triangle::new([v[0], v[1], v[2]]), Triangle::new([v[0], v[2], v[3]])]);
+    let hit = |x: f64, y: f64| {
+        let ray = Ray::new(Vec3::new(x, y, 1.0).cast::<IntervalScalar>(), Vec3::new(0.1, 0.2, -1.0).normalize().cast());
+        (0..2).filter_map(|i| mesh.raycast(i, &ray, Sidedness::Front, false)).any(|x| x.ambiguous)
+    };
+    // Aimed at the shared diagonal, and well inside one triangle.
+    assert!(hit(0.4, 0.3));
+    assert!(!hit(0.6, 0.1));
+}
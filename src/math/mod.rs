@@ -118,3 +118,5 @@ pub mod scalar;
 // mod trispline;
 pub mod vec;
 pub mod scalar_key;
+pub mod complex;
+pub mod interval_scalar;
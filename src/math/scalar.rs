@@ -3,7 +3,9 @@ use std::default::default;
 use std::fmt::Debug;
 use std::iter::{Product, Sum};
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Rem, Sub, SubAssign};
+use crate::math::mat::Matrix;
 use crate::math::vec::Vector;
+use roots::FloatType;
 
 pub trait Scalar
 : From<f64>
@@ -31,6 +33,31 @@ pub trait Scalar
     fn real_cmp(self, other: Self) -> Ordering;
     fn not_nan(self) -> bool;
     fn into_const(self) -> f64;
+    // Half the machine epsilon of the underlying floating point type.
+    fn unit_roundoff() -> f64;
+    // Whether values are bare floats, carrying no derivatives or error bounds, so that code may
+    // compute on them as f64 instead.
+    fn is_plain() -> bool;
+    // The value v, seeded as the derivative with respect to the given input where supported.
+    fn param(v: f64, index: usize) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn tan(self) -> Self;
+    fn asin(self) -> Self;
+    fn atan2(self, x: Self) -> Self;
+    fn sinh(self) -> Self;
+    fn cosh(self) -> Self;
+    fn tanh(self) -> Self;
+    // Unlike self * self, never below zero for types that bound a range of values.
+    fn square(self) -> Self { self * self }
+    // Whether rounding could have ordered self and other either way. Only intervals can tell.
+    fn overlaps(self, other: Self) -> bool { false }
+}
+
+// Bound on the relative error accumulated by n floating point operations in T (Higham's gamma_n).
+pub fn gamma<T: Scalar>(n: u32) -> f64 {
+    let nu = n as f64 * T::unit_roundoff();
+    nu / (1.0 - nu)
 }
 
 impl Scalar for f64 {
@@ -62,32 +89,155 @@ impl Scalar for f64 {
     fn not_nan(self) -> bool { !self.is_nan() }
 
     fn into_const(self) -> f64 { self }
+    fn unit_roundoff() -> f64 { f64::EPSILON / 2.0 }
+    fn is_plain() -> bool { true }
+    fn param(v: f64, index: usize) -> Self { v }
+    fn exp(self) -> Self { f64::exp(self) }
+    fn ln(self) -> Self { f64::ln(self) }
+    fn tan(self) -> Self { f64::tan(self) }
+    fn asin(self) -> Self { f64::asin(self) }
+    fn atan2(self, x: Self) -> Self { f64::atan2(self, x) }
+    fn sinh(self) -> Self { f64::sinh(self) }
+    fn cosh(self) -> Self { f64::cosh(self) }
+    fn tanh(self) -> Self { f64::tanh(self) }
 }
 
-pub struct DerX {
-    pub v: f64,
-    pub d: f64,
+/// Single precision scalar. This is a newtype because `f32` cannot implement `From<f64>`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct F32(pub f32);
+
+impl From<f64> for F32 {
+    fn from(x: f64) -> Self { F32(x as f32) }
+}
+
+impl From<i16> for F32 {
+    fn from(x: i16) -> Self { F32(x as f32) }
+}
+
+impl From<F32> for f64 {
+    fn from(x: F32) -> Self { x.0 as f64 }
+}
+
+impl Add for F32 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output { F32(self.0 + rhs.0) }
+}
+
+impl Sub for F32 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output { F32(self.0 - rhs.0) }
+}
+
+impl Mul for F32 {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output { F32(self.0 * rhs.0) }
+}
+
+impl Div for F32 {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self::Output { F32(self.0 / rhs.0) }
+}
+
+impl Rem for F32 {
+    type Output = Self;
+    fn rem(self, rhs: Self) -> Self::Output { F32(self.0 % rhs.0) }
+}
+
+impl Neg for F32 {
+    type Output = Self;
+    fn neg(self) -> Self::Output { F32(-self.0) }
+}
+
+impl AddAssign for F32 {
+    fn add_assign(&mut self, rhs: Self) { self.0 += rhs.0; }
+}
+
+impl SubAssign for F32 {
+    fn sub_assign(&mut self, rhs: Self) { self.0 -= rhs.0; }
+}
+
+impl MulAssign for F32 {
+    fn mul_assign(&mut self, rhs: Self) { self.0 *= rhs.0; }
+}
+
+impl DivAssign for F32 {
+    fn div_assign(&mut self, rhs: Self) { self.0 /= rhs.0; }
+}
+
+impl Sum for F32 {
+    fn sum<I: Iterator<Item=Self>>(iter: I) -> Self { iter.fold(Self::from(0.0), Self::add) }
+}
+
+impl Product for F32 {
+    fn product<I: Iterator<Item=Self>>(iter: I) -> Self { iter.fold(Self::from(1.0), Self::mul) }
+}
+
+impl roots::FloatType for F32 {
+    fn zero() -> Self { F32(0.0) }
+    fn one() -> Self { F32(1.0) }
+    fn one_third() -> Self { F32(1.0 / 3.0) }
+    fn pi() -> Self { F32(std::f32::consts::PI) }
+    fn two_third_pi() -> Self { F32(2.0 * std::f32::consts::FRAC_PI_3) }
+    fn sqrt(self) -> Self { F32(self.0.sqrt()) }
+    fn atan(self) -> Self { F32(self.0.atan()) }
+    fn acos(self) -> Self { F32(self.0.acos()) }
+    fn sin(self) -> Self { F32(self.0.sin()) }
+    fn cos(self) -> Self { F32(self.0.cos()) }
+    fn abs(self) -> Self { F32(self.0.abs()) }
+    fn powf(self, n: Self) -> Self { F32(self.0.powf(n.0)) }
+}
+
+impl Scalar for F32 {
+    fn minimum(self, other: Self) -> Self { F32(f32::minimum(self.0, other.0)) }
+    fn maximum(self, other: Self) -> Self { F32(f32::maximum(self.0, other.0)) }
+    fn is_finite(self) -> bool { self.0.is_finite() }
+    fn real_eq(self, other: Self) -> bool { (self.0 as f64).real_eq(other.0 as f64) }
+    fn real_cmp(self, other: Self) -> Ordering { (self.0 as f64).real_cmp(other.0 as f64) }
+    fn not_nan(self) -> bool { !self.0.is_nan() }
+    fn into_const(self) -> f64 { self.0 as f64 }
+    fn unit_roundoff() -> f64 { f32::EPSILON as f64 / 2.0 }
+    fn is_plain() -> bool { true }
+    fn param(v: f64, index: usize) -> Self { F32::from(v) }
+    fn exp(self) -> Self { F32(self.0.exp()) }
+    fn ln(self) -> Self { F32(self.0.ln()) }
+    fn tan(self) -> Self { F32(self.0.tan()) }
+    fn asin(self) -> Self { F32(self.0.asin()) }
+    fn atan2(self, x: Self) -> Self { F32(self.0.atan2(x.0)) }
+    fn sinh(self) -> Self { F32(self.0.sinh()) }
+    fn cosh(self) -> Self { F32(self.0.cosh()) }
+    fn tanh(self) -> Self { F32(self.0.tanh()) }
 }
 
+pub struct DerX<B = f64> {
+    pub v: B,
+    pub d: B,
+}
+
+// Forward mode dual number over N inputs, computed at the precision of the base scalar B.
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
-pub struct Der<const N: usize> {
-    pub v: f64,
-    pub d: Vector<N, f64>,
+pub struct Der<const N: usize, B = f64> {
+    pub v: B,
+    pub d: Vector<N, B>,
 }
 
-impl<const N: usize> Der<N> {
-    pub fn var(v: f64, n: usize) -> Self {
-        let mut d: Vector<N, _> = default();
-        d[n] = 1.0;
+pub type DerF32<const N: usize> = Der<N, F32>;
+
+impl<const N: usize, B: Scalar> Der<N, B> {
+    pub fn var(v: B, n: usize) -> Self {
+        let mut d: Vector<N, B> = default();
+        d[n] = B::from(1.0);
         Der { v, d }
     }
-    fn oper1(self, v: impl FnOnce(f64) -> f64, mut d: impl FnMut(f64) -> f64) -> Self {
+    pub fn to_f64(self) -> Der<N> {
+        Der { v: self.v.into_const(), d: self.d.map(|d| d.into_const()) }
+    }
+    fn oper1(self, v: impl FnOnce(B) -> B, mut d: impl FnMut(B) -> B) -> Self {
         Der {
             v: v(self.v),
             d: self.d.map(|d1| d1 * d(self.v)),
         }
     }
-    fn oper2(self, rhs: Self, v: impl FnOnce(f64, f64) -> f64, mut d: impl FnMut(DerX, DerX) -> f64) -> Self {
+    fn oper2(self, rhs: Self, v: impl FnOnce(B, B) -> B, mut d: impl FnMut(DerX<B>, DerX<B>) -> B) -> Self {
         Der {
             v: v(self.v, rhs.v),
             d: self.d.zip(rhs.d).map(|(d1, d2)| d(DerX { v: self.v, d: d1 }, DerX { v: rhs.v, d: d2 })),
@@ -95,32 +245,32 @@ impl<const N: usize> Der<N> {
     }
 }
 
-impl<const N: usize> Default for Der<N> {
+impl<const N: usize, B: Scalar> Default for Der<N, B> {
     fn default() -> Self {
         Der { v: default(), d: default() }
     }
 }
 
-impl<const N: usize> Add for Der<N> {
+impl<const N: usize, B: Scalar> Add for Der<N, B> {
     type Output = Self;
     fn add(self, rhs: Self) -> Self::Output {
         self.oper2(rhs, |x, y| x + y, |x, y| x.d + y.d)
     }
 }
 
-impl<const N: usize> Sub for Der<N> {
+impl<const N: usize, B: Scalar> Sub for Der<N, B> {
     type Output = Self;
     fn sub(self, rhs: Self) -> Self::Output { self.oper2(rhs, |x, y| x - y, |x, y| x.d - y.d) }
 }
 
-impl<const N: usize> Mul for Der<N> {
+impl<const N: usize, B: Scalar> Mul for Der<N, B> {
     type Output = Self;
     fn mul(self, rhs: Self) -> Self::Output {
         self.oper2(rhs, |x, y| x * y, |x, y| x.v * y.d + x.d * y.v)
     }
 }
 
-impl<const N: usize> Div for Der<N> {
+impl<const N: usize, B: Scalar> Div for Der<N, B> {
     type Output = Self;
     fn div(self, rhs: Self) -> Self::Output {
         self.oper2(rhs, |x, y| x / y, |x, y| (y.v * x.d - x.v * y.d) / (y.v * y.v))
@@ -142,7 +292,7 @@ impl<const N: usize> Div for Der<N> {
 //     fn add(self, rhs: Der<N>) -> Self::Output { (*self).add(rhs) }
 // }
 
-impl<const N: usize> AddAssign for Der<N> {
+impl<const N: usize, B: Scalar> AddAssign for Der<N, B> {
     fn add_assign(&mut self, rhs: Self) { *self = (*self) + rhs; }
 }
 
@@ -165,7 +315,7 @@ impl<const N: usize> AddAssign for Der<N> {
 //     fn sub(self, rhs: Der<N>) -> Self::Output { (*self).sub(rhs) }
 // }
 
-impl<const N: usize> SubAssign for Der<N> {
+impl<const N: usize, B: Scalar> SubAssign for Der<N, B> {
     fn sub_assign(&mut self, rhs: Self) { *self = (*self) - rhs; }
 }
 
@@ -188,7 +338,7 @@ impl<const N: usize> SubAssign for Der<N> {
 //     fn mul(self, rhs: Der<N>) -> Self::Output { (*self).mul(rhs) }
 // }
 
-impl<const N: usize> MulAssign for Der<N> {
+impl<const N: usize, B: Scalar> MulAssign for Der<N, B> {
     fn mul_assign(&mut self, rhs: Self) { *self = (*self) * rhs; }
 }
 
@@ -211,7 +361,7 @@ impl<const N: usize> MulAssign for Der<N> {
 //     fn div(self, rhs: Der<N>) -> Self::Output { (*self).div(rhs) }
 // }
 
-impl<const N: usize> DivAssign for Der<N> {
+impl<const N: usize, B: Scalar> DivAssign for Der<N, B> {
     fn div_assign(&mut self, rhs: Self) { *self = (*self) / rhs; }
 }
 
@@ -219,41 +369,41 @@ impl<const N: usize> DivAssign for Der<N> {
 //     fn div_assign(&mut self, rhs: &Self) { *self = (*self) / rhs; }
 // }
 
-impl<const N: usize> From<f64> for Der<N> {
-    fn from(v: f64) -> Self { Der { v, d: default() } }
+impl<const N: usize, B: Scalar> From<f64> for Der<N, B> {
+    fn from(v: f64) -> Self { Der { v: B::from(v), d: default() } }
 }
 
-impl<const N: usize> roots::FloatType for Der<N> {
+impl<const N: usize, B: Scalar> roots::FloatType for Der<N, B> {
     fn zero() -> Self {
-        Der { v: 0.0, d: Vector::broadcast(0.0) }
+        Der::from(0.0)
     }
 
     fn one() -> Self {
-        todo!()
+        Der::from(1.0)
     }
 
     fn one_third() -> Self {
-        todo!()
+        Der::from(1.0 / 3.0)
     }
 
     fn pi() -> Self {
-        todo!()
+        Der::from(std::f64::consts::PI)
     }
 
     fn two_third_pi() -> Self {
-        todo!()
+        Der::from(2.0 * std::f64::consts::FRAC_PI_3)
     }
 
     fn sqrt(self) -> Self {
-        self.oper1(|x| x.sqrt(), |x| 1.0 / (2.0 * x.sqrt()))
+        self.oper1(|x| x.sqrt(), |x| B::from(0.5) / x.sqrt())
     }
 
     fn atan(self) -> Self {
-        todo!()
+        self.oper1(|x| x.atan(), |x| B::from(1.0) / (B::from(1.0) + x * x))
     }
 
     fn acos(self) -> Self {
-        todo!()
+        self.oper1(|x| x.acos(), |x| B::from(-1.0) / (B::from(1.0) - x * x).sqrt())
     }
 
     fn sin(self) -> Self {
@@ -265,7 +415,7 @@ impl<const N: usize> roots::FloatType for Der<N> {
     }
 
     fn abs(self) -> Self {
-        if self.v < 0.0 {
+        if self.v < B::from(0.0) {
             -self
         } else {
             self
@@ -273,35 +423,40 @@ impl<const N: usize> roots::FloatType for Der<N> {
     }
 
     fn powf(self, n: Self) -> Self {
-        todo!()
+        if n.d == default() {
+            let n = n.v;
+            self.oper1(|x| x.powf(n), |x| n * x.powf(n - B::from(1.0)))
+        } else {
+            (n * self.ln()).exp()
+        }
     }
 }
 
-impl<const N: usize> From<i16> for Der<N> {
+impl<const N: usize, B: Scalar> From<i16> for Der<N, B> {
     fn from(x: i16) -> Self {
         Self::from(x as f64)
     }
 }
 
-impl<const N: usize> Neg for Der<N> {
+impl<const N: usize, B: Scalar> Neg for Der<N, B> {
     type Output = Self;
     fn neg(self) -> Self::Output {
-        self.oper1(|x| -x, |x| -1.0)
+        self.oper1(|x| -x, |x| B::from(-1.0))
     }
 }
 
-impl<const N: usize> Rem for Der<N> {
+impl<const N: usize, B: Scalar> Rem for Der<N, B> {
     type Output = Self;
     fn rem(self, rhs: Self) -> Self::Output {
-        Der { v: self.v.rem(rhs.v), d: self.d }
+        self - rhs * Der::from((self.v.into_const() / rhs.v.into_const()).trunc())
     }
 }
 
-impl<const N: usize> Scalar for Der<N> {
+impl<const N: usize, B: Scalar> Scalar for Der<N, B> {
     fn minimum(self, other: Self) -> Self {
-        if self.v.is_nan() {
+        if !self.v.not_nan() {
             self
-        } else if other.v.is_nan() {
+        } else if !other.v.not_nan() {
             other
         } else if self.v <= other.v {
             self
@@ -310,9 +465,9 @@ impl<const N: usize> Scalar for Der<N> {
         }
     }
     fn maximum(self, other: Self) -> Self {
-        if self.v.is_nan() {
+        if !self.v.not_nan() {
             self
-        } else if other.v.is_nan() {
+        } else if !other.v.not_nan() {
             other
         } else if self.v <= other.v {
             other
@@ -338,19 +493,299 @@ impl<const N: usize> Scalar for Der<N> {
         self.v.not_nan() && self.d.into_iter().all(|x| x.not_nan())
     }
 
-    fn into_const(self) -> f64 { self.v }
+    fn into_const(self) -> f64 { self.v.into_const() }
+    fn unit_roundoff() -> f64 { B::unit_roundoff() }
+    fn is_plain() -> bool { false }
+
+    fn param(v: f64, index: usize) -> Self {
+        if index < N { Der::var(B::from(v), index) } else { Der::from(v) }
+    }
+
+    fn exp(self) -> Self {
+        self.oper1(|x| x.exp(), |x| x.exp())
+    }
+
+    fn ln(self) -> Self {
+        self.oper1(|x| x.ln(), |x| B::from(1.0) / x)
+    }
+
+    fn tan(self) -> Self {
+        self.oper1(|x| x.tan(), |x| B::from(1.0) / (x.cos() * x.cos()))
+    }
+
+    fn asin(self) -> Self {
+        self.oper1(|x| x.asin(), |x| B::from(1.0) / (B::from(1.0) - x * x).sqrt())
+    }
+
+    fn atan2(self, x: Self) -> Self {
+        self.oper2(x, |y, x| y.atan2(x), |y, x| (x.v * y.d - y.v * x.d) / (x.v * x.v + y.v * y.v))
+    }
+
+    fn sinh(self) -> Self {
+        self.oper1(|x| x.sinh(), |x| x.cosh())
+    }
+
+    fn cosh(self) -> Self {
+        self.oper1(|x| x.cosh(), |x| x.sinh())
+    }
+
+    fn tanh(self) -> Self {
+        self.oper1(|x| x.tanh(), |x| B::from(1.0) - x.tanh() * x.tanh())
+    }
+}
+
+impl<const N: usize, B: Scalar> Sum for Der<N, B> {
+    fn sum<I: Iterator<Item=Self>>(iter: I) -> Self { iter.fold(Self::from(0.0), Self::add) }
+}
+
+impl<const N: usize, B: Scalar> Product for Der<N, B> {
+    fn product<I: Iterator<Item=Self>>(iter: I) -> Self { iter.fold(Self::from(1.0), Self::mul) }
+}
+
+impl<const N: usize, B: Scalar> From<Der<N, B>> for f64 {
+    fn from(x: Der<N, B>) -> Self { x.v.into_const() }
+}
+
+/// Second order dual number carrying the gradient and Hessian with respect to N inputs.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct HyperDer<const N: usize> {
+    pub v: f64,
+    pub d: Vector<N, f64>,
+    pub h: Matrix<N, f64>,
+}
+
+fn outer<const N: usize>(x: Vector<N, f64>, y: Vector<N, f64>) -> Matrix<N, f64> {
+    Matrix::from_row_vector(x.map(|x| y * x))
+}
+
+impl<const N: usize> HyperDer<N> {
+    pub fn var(v: f64, n: usize) -> Self {
+        let mut d: Vector<N, _> = default();
+        d[n] = 1.0;
+        HyperDer { v, d, h: default() }
+    }
+    // Chain rule for a function with value v, derivative d and second derivative dd at self.v.
+    fn oper1(self, v: f64, d: f64, dd: f64) -> Self {
+        HyperDer {
+            v,
+            d: self.d * d,
+            h: self.h * d + outer(self.d, self.d) * dd,
+        }
+    }
+    pub fn first_order(self) -> Der<N> {
+        Der { v: self.v, d: self.d }
+    }
+    pub fn recip(self) -> Self {
+        let r = 1.0 / self.v;
+        self.oper1(r, -r * r, 2.0 * r * r * r)
+    }
+}
+
+impl<const N: usize> Default for HyperDer<N> {
+    fn default() -> Self {
+        HyperDer { v: default(), d: default(), h: default() }
+    }
+}
+
+impl<const N: usize> From<f64> for HyperDer<N> {
+    fn from(v: f64) -> Self { HyperDer { v, d: default(), h: default() } }
+}
+
+impl<const N: usize> From<i16> for HyperDer<N> {
+    fn from(x: i16) -> Self { Self::from(x as f64) }
+}
+
+impl<const N: usize> From<HyperDer<N>> for f64 {
+    fn from(x: HyperDer<N>) -> Self { x.v }
+}
+
+impl<const N: usize> Add for HyperDer<N> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        HyperDer { v: self.v + rhs.v, d: self.d + rhs.d, h: self.h + rhs.h }
+    }
+}
+
+impl<const N: usize> Sub for HyperDer<N> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output {
+        HyperDer { v: self.v - rhs.v, d: self.d - rhs.d, h: self.h - rhs.h }
+    }
+}
+
+impl<const N: usize> Mul for HyperDer<N> {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        HyperDer {
+            v: self.v * rhs.v,
+            d: self.d * rhs.v + rhs.d * self.v,
+            h: self.h * rhs.v + rhs.h * self.v + outer(self.d, rhs.d) + outer(rhs.d, self.d),
+        }
+    }
+}
+
+impl<const N: usize> Div for HyperDer<N> {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self::Output { self * rhs.recip() }
+}
+
+impl<const N: usize> Rem for HyperDer<N> {
+    type Output = Self;
+    fn rem(self, rhs: Self) -> Self::Output {
+        self - rhs * Self::from((self.v / rhs.v).trunc())
+    }
+}
+
+impl<const N: usize> Neg for HyperDer<N> {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        HyperDer { v: -self.v, d: self.d * -1.0, h: self.h * -1.0 }
+    }
+}
+
+impl<const N: usize> AddAssign for HyperDer<N> {
+    fn add_assign(&mut self, rhs: Self) { *self = (*self) + rhs; }
+}
+
+impl<const N: usize> SubAssign for HyperDer<N> {
+    fn sub_assign(&mut self, rhs: Self) { *self = (*self) - rhs; }
 }
 
-impl<const N: usize> Sum for Der<N> {
+impl<const N: usize> MulAssign for HyperDer<N> {
+    fn mul_assign(&mut self, rhs: Self) { *self = (*self) * rhs; }
+}
+
+impl<const N: usize> DivAssign for HyperDer<N> {
+    fn div_assign(&mut self, rhs: Self) { *self = (*self) / rhs; }
+}
+
+impl<const N: usize> Sum for HyperDer<N> {
     fn sum<I: Iterator<Item=Self>>(iter: I) -> Self { iter.fold(Self::from(0.0), Self::add) }
 }
 
-impl<const N: usize> Product for Der<N> {
+impl<const N: usize> Product for HyperDer<N> {
     fn product<I: Iterator<Item=Self>>(iter: I) -> Self { iter.fold(Self::from(1.0), Self::mul) }
 }
 
-impl<const N: usize> From<Der<N>> for f64 {
-    fn from(x: Der<N>) -> Self { x.v }
+impl<const N: usize> roots::FloatType for HyperDer<N> {
+    fn zero() -> Self { Self::from(0.0) }
+    fn one() -> Self { Self::from(1.0) }
+    fn one_third() -> Self { Self::from(1.0 / 3.0) }
+    fn pi() -> Self { Self::from(std::f64::consts::PI) }
+    fn two_third_pi() -> Self { Self::from(2.0 * std::f64::consts::FRAC_PI_3) }
+    fn sqrt(self) -> Self {
+        let s = self.v.sqrt();
+        self.oper1(s, 0.5 / s, -0.25 / (s * self.v))
+    }
+    fn atan(self) -> Self {
+        let q = 1.0 / (1.0 + self.v * self.v);
+        self.oper1(self.v.atan(), q, -2.0 * self.v * q * q)
+    }
+    fn acos(self) -> Self {
+        let q = 1.0 - self.v * self.v;
+        self.oper1(self.v.acos(), -1.0 / q.sqrt(), -self.v / (q * q.sqrt()))
+    }
+    fn sin(self) -> Self {
+        let (s, c) = self.v.sin_cos();
+        self.oper1(s, c, -s)
+    }
+    fn cos(self) -> Self {
+        let (s, c) = self.v.sin_cos();
+        self.oper1(c, -s, -c)
+    }
+    fn abs(self) -> Self {
+        if self.v < 0.0 {
+            -self
+        } else {
+            self
+        }
+    }
+    fn powf(self, n: Self) -> Self {
+        if n.d == default() && n.h == default() {
+            let n = n.v;
+            self.oper1(self.v.powf(n), n * self.v.powf(n - 1.0), n * (n - 1.0) * self.v.powf(n - 2.0))
+        } else {
+            (n * self.ln()).exp()
+        }
+    }
+}
+
+impl<const N: usize> Scalar for HyperDer<N> {
+    fn minimum(self, other: Self) -> Self {
+        if self.v.is_nan() {
+            self
+        } else if other.v.is_nan() {
+            other
+        } else if self.v <= other.v {
+            self
+        } else {
+            other
+        }
+    }
+    fn maximum(self, other: Self) -> Self {
+        if self.v.is_nan() {
+            self
+        } else if other.v.is_nan() {
+            other
+        } else if self.v <= other.v {
+            other
+        } else {
+            self
+        }
+    }
+    fn is_finite(self) -> bool { self.v.is_finite() }
+    fn real_eq(self, other: Self) -> bool { self.v.real_eq(other.v) }
+    fn real_cmp(self, other: Self) -> Ordering { self.v.real_cmp(other.v) }
+    fn not_nan(self) -> bool {
+        self.v.not_nan()
+            && self.d.into_iter().all(|x| x.not_nan())
+            && self.h.into_row_vector().into_iter().flatten().all(|x| x.not_nan())
+    }
+    fn into_const(self) -> f64 { self.v }
+    fn unit_roundoff() -> f64 { f64::unit_roundoff() }
+    fn is_plain() -> bool { false }
+    fn param(v: f64, index: usize) -> Self {
+        if index < N { HyperDer::var(v, index) } else { HyperDer::from(v) }
+    }
+    fn exp(self) -> Self {
+        let e = self.v.exp();
+        self.oper1(e, e, e)
+    }
+    fn ln(self) -> Self {
+        self.oper1(self.v.ln(), 1.0 / self.v, -1.0 / (self.v * self.v))
+    }
+    fn tan(self) -> Self {
+        let t = self.v.tan();
+        let sec2 = 1.0 + t * t;
+        self.oper1(t, sec2, 2.0 * t * sec2)
+    }
+    fn asin(self) -> Self {
+        let q = 1.0 - self.v * self.v;
+        self.oper1(self.v.asin(), 1.0 / q.sqrt(), self.v / (q * q.sqrt()))
+    }
+    // atan2 differs from atan(y / x) or -atan(x / y) by a constant, so either gives the derivatives.
+    fn atan2(self, x: Self) -> Self {
+        let v = self.v.atan2(x.v);
+        let angle = if x.v.abs() >= self.v.abs() {
+            (self / x).atan()
+        } else {
+            -(x / self).atan()
+        };
+        HyperDer { v, d: angle.d, h: angle.h }
+    }
+    fn sinh(self) -> Self {
+        let (s, c) = (self.v.sinh(), self.v.cosh());
+        self.oper1(s, c, s)
+    }
+    fn cosh(self) -> Self {
+        let (s, c) = (self.v.sinh(), self.v.cosh());
+        self.oper1(c, s, c)
+    }
+    fn tanh(self) -> Self {
+        let t = self.v.tanh();
+        let sech2 = 1.0 - t * t;
+        self.oper1(t, sech2, -2.0 * t * sech2)
+    }
 }
 
 type Der1 = Der<1>;
@@ -364,4 +799,70 @@ fn test() {
     let c = Der2::from(3.0);
     let f = x + y + c;
     assert_eq!(f, Der { v: 6.0, d: [1.0, 1.0].into() });
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_der_functions() {
+    let fs: [fn(Der1) -> Der1; 12] = [
+        |x| x.exp(), |x| x.ln(), |x| x.tan(), |x| x.asin(), |x| x.acos(), |x| x.atan(),
+        |x| x.atan2(Der1::from(-0.3)), |x| x.sinh(), |x| x.cosh(), |x| x.tanh(),
+        |x| x.powf(Der1::from(2.5)), |x| Der1::from(1.7).powf(x),
+    ];
+    let eps = 1e-6;
+    for f in fs {
+        let expected = (f(Der1::from(0.4 + eps)).v - f(Der1::from(0.4 - eps)).v) / (2.0 * eps);
+        assert!((f(Der1::var(0.4, 0)).d[0] - expected).abs() < 1e-6);
+    }
+    let x = Der1::var(7.5, 0) % Der1::from(2.0);
+    assert_eq!((x.v, x.d[0]), (1.5, 1.0));
+    assert_eq!(Der1::var(8.0, 0).cbrt().v, 2.0);
+}
+
+#[test]
+fn test_hyper() {
+    let x = HyperDer::<2>::var(2.0, 0);
+    let y = HyperDer::<2>::var(3.0, 1);
+    let f = x * x * y;
+    assert_eq!(f.v, 12.0);
+    assert_eq!(f.d, [12.0, 4.0].into());
+    assert_eq!(f.h, Matrix::from_row_arrays([[6.0, 4.0], [4.0, 0.0]]));
+    // Compare against central differences of the first order derivatives.
+    let g = |x: HyperDer<2>, y: HyperDer<2>| (x / y).sin() * y.atan2(x) + x.sqrt().exp();
+    let eps = 1e-6;
+    let value = g(x, y);
+    let dx = (g(x + HyperDer::from(eps), y).d - g(x - HyperDer::from(eps), y).d) / (2.0 * eps);
+    let dy = (g(x, y + HyperDer::from(eps)).d - g(x, y - HyperDer::from(eps)).d) / (2.0 * eps);
+    for i in 0..2 {
+        assert!((value.h[(0, i)] - dx[i]).abs() < 1e-6);
+        assert!((value.h[(1, i)] - dy[i]).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_f32() {
+    let x = DerF32::<2>::var(F32(0.5), 0);
+    let y = DerF32::<2>::var(F32(2.0), 1);
+    let f = (x * y).sin() + y.sqrt();
+    assert!((f.v.into_const() - (1.0f64.sin() + 2.0f64.sqrt())).abs() < 1e-6);
+    assert!((f.d[0].into_const() - 2.0 * 1.0f64.cos()).abs() < 1e-6);
+    assert!((f.d[1].into_const() - (0.5 * 1.0f64.cos() + 0.5 / 2.0f64.sqrt())).abs() < 1e-6);
+}
+
+// The elementary functions are Scalar methods for every implementation, so generic code gets the
+// second order ones of HyperDer and not only those of Der.
+#[test]
+fn test_hyper_functions() {
+    fn elementary<T: Scalar>(x: T) -> [T; 8] {
+        [x.exp(), x.ln(), x.tan(), x.asin(), x.atan2(T::from(-0.3)), x.sinh(), x.cosh(), x.tanh()]
+    }
+    let eps = 1e-6;
+    let x = HyperDer::<1>::var(0.4, 0);
+    let first = elementary(Der1::var(0.4, 0));
+    let above = elementary(Der1::var(0.4 + eps, 0));
+    let below = elementary(Der1::var(0.4 - eps, 0));
+    for (i, f) in elementary(x).into_iter().enumerate() {
+        assert!((f.v - first[i].v).abs() < 1e-12);
+        assert!((f.d[0] - first[i].d[0]).abs() < 1e-12);
+        assert!((f.h[(0, 0)] - (above[i].d[0] - below[i].d[0]) / (2.0 * eps)).abs() < 1e-6);
+    }
+}
@@ -4,7 +4,7 @@ use std::iter::Sum;
 use std::ops::{Add, AddAssign, Div, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign};
 use arrayvec::ArrayVec;
 use crate::math::mat::Matrix;
-use crate::math::scalar::{Der, Scalar};
+use crate::math::scalar::{Der, HyperDer, Scalar};
 
 #[derive(Copy, Clone, PartialEq, PartialOrd)]
 pub struct Vector<const N: usize, T>([T; N]);
@@ -24,9 +24,20 @@ impl<const N: usize, T> Vector<N, T> {
 
 impl<const N: usize> Vector<N, f64> {
     pub fn as_input(self) -> Vector<N, Der<N>> {
+        self.as_input_at()
+    }
+    // As as_input, with the derivatives computed at the precision of B.
+    pub fn as_input_at<B: Scalar>(self) -> Vector<N, Der<N, B>> {
+        let mut result = Vector::default();
+        for i in 0..N {
+            result[i] = Der::var(B::from(self[i]), i)
+        }
+        result
+    }
+    pub fn as_hyper_input(self) -> Vector<N, HyperDer<N>> {
         let mut result = Vector::default();
         for i in 0..N {
-            result[i] = Der::var(self[i], i)
+            result[i] = HyperDer::var(self[i], i)
         }
         result
     }
@@ -37,7 +48,7 @@ impl<const N: usize, T> Vector<N, T> where T: Scalar {
         self.zip(other).into_iter().map(|(x, y)| x * y).sum()
     }
     pub fn square_length(self) -> T {
-        self.dot(self)
+        self.into_iter().map(|x| x.square()).sum()
     }
     pub fn length(self) -> T { self.square_length().sqrt() }
     pub fn normalize(self) -> Self {
@@ -69,6 +80,15 @@ impl<const N: usize> Vector<N, Der<N>> {
     }
 }
 
+impl<const N: usize, const M: usize> Vector<M, HyperDer<N>> {
+    pub fn first_order(&self) -> Vector<M, Der<N>> {
+        self.map(|x| x.first_order())
+    }
+    pub fn hessians(&self) -> Vector<M, Matrix<N, f64>> {
+        self.map(|x| x.h)
+    }
+}
+
 impl<const N: usize, const M: usize> Vector<M, Der<N>> {
     pub fn der(&self, n: usize) -> Vector<M, f64> {
         Vector::from(self.0.map(|x| x.d[n]))
@@ -0,0 +1,157 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use parking_lot::{const_mutex, Mutex};
+use crate::geo::indexed_mesh::IndexedMesh;
+use crate::geo::triangle::Triangle;
+use crate::geo::winding::{orient_consistently, WindingReport};
+use crate::math::vec::Vec3;
+use crate::tree::bvh::{Bvh, BvhForest};
+use crate::tree::bvh_build::BvhStrategy;
+use crate::tree::bvh_sah::SahConfig;
+
+const MODEL_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/models");
+
+// Built meshes by name with the report of their loading, so that each is loaded once per process.
+static CACHE: Mutex<Vec<(&'static str, Arc<Bvh>, WindingReport)>> = const_mutex(vec![]);
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Reads the vertex positions and faces of a Wavefront OBJ file, fanning polygons into triangles.
+// Everything else, including texture coordinates and normals, is skipped.
+pub fn read_obj(r: impl BufRead) -> io::Result<Vec<Triangle<f64>>> {
+    let mut positions = vec![];
+    let mut tris = vec![];
+    for line in r.lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let mut p = [0.0; 3];
+                for x in &mut p {
+                    *x = words.next().and_then(|w| w.parse().ok())
+                        .ok_or_else(|| invalid(format!("bad vertex {:?}", line)))?;
+                }
+                positions.push(Vec3::from(p));
+            }
+            Some("f") => {
+                // Indices start at 1, and negative ones count back from the latest vertex.
+                let face = words.map(|w| {
+                    let index: i64 = w.split('/').next().unwrap().parse().map_err(|_| invalid(format!("bad face {:?}", line)))?;
+                    let index = if index < 0 { positions.len() as i64 + index } else { index - 1 };
+                    positions.get(index as usize).copied().ok_or_else(|| invalid(format!("face index out of range {:?}", line)))
+                }).collect::<io::Result<Vec<_>>>()?;
+                if face.len() < 3 {
+                    return Err(invalid(format!("face with fewer than three vertices {:?}", line)));
+                }
+                for i in 1..face.len() - 1 {
+                    tris.push(Triangle::new([face[0], face[i], face[i + 1]]));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(tris)
+}
+
+// Loads an OBJ file and winds its faces consistently outwards.
+pub fn load_obj(path: impl AsRef<Path>) -> io::Result<(IndexedMesh, WindingReport)> {
+    let mut tris = read_obj(BufReader::new(File::open(path)?))?;
+    let report = orient_consistently(&mut tris);
+    Ok((IndexedMesh::from_triangles(tris), report))
+}
+
+// A latitude-longitude sphere around the origin with exact vertex normals.
+pub fn uv_sphere(radius: f64, rings: u32, segments: u32) -> IndexedMesh {
+    let mut normals = vec![Vec3::new(0.0, 1.0, 0.0)];
+    for ring in 1..rings {
+        let theta = PI * ring as f64 / rings as f64;
+        for segment in 0..segments {
+            let phi = 2.0 * PI * segment as f64 / segments as f64;
+            normals.push(Vec3::new(theta.sin() * phi.cos(), theta.cos(), -theta.sin() * phi.sin()));
+        }
+    }
+    normals.push(Vec3::new(0.0, -1.0, 0.0));
+    let bottom = normals.len() as u32 - 1;
+    let vertex = |ring: u32, segment: u32| 1 + (ring - 1) * segments + segment % segments;
+    let mut indices = vec![];
+    for segment in 0..segments {
+        indices.push([0, vertex(1, segment), vertex(1, segment + 1)]);
+        for ring in 1..rings - 1 {
+            let (a, b) = (vertex(ring, segment), vertex(ring, segment + 1));
+            let (c, d) = (vertex(ring + 1, segment), vertex(ring + 1, segment + 1));
+            indices.push([a, c, d]);
+            indices.push([a, d, b]);
+        }
+        indices.push([vertex(rings - 1, segment), bottom, vertex(rings - 1, segment + 1)]);
+    }
+    let positions = normals.iter().map(|&n| n * radius).collect();
+    IndexedMesh::new(positions, indices).with_normals(normals)
+}
+
+fn cached(name: &'static str, load: impl FnOnce() -> (IndexedMesh, WindingReport)) -> (Arc<Bvh>, WindingReport) {
+    let mut cache = CACHE.lock();
+    if let Some((_, bvh, report)) = cache.iter().find(|(n, _, _)| *n == name) {
+        return (bvh.clone(), *report);
+    }
+    let (mesh, report) = load();
+    let mesh = Arc::new(mesh);
+    let tree = BvhForest::new(&*mesh).build(&*mesh, &BvhStrategy::Sah(SahConfig::default()));
+    let bvh = Arc::new(Bvh::new(mesh, &tree));
+    cache.push((name, bvh.clone(), report));
+    (bvh, report)
+}
+
+// The model from models/<name>.obj, with the report of how its faces were rewound on loading.
+pub fn model(name: &'static str) -> (Arc<Bvh>, WindingReport) {
+    cached(name, || {
+        let path = format!("{}/{}.obj", MODEL_DIR, name);
+        load_obj(&path).unwrap_or_else(|e| panic!("cannot load {}: {}", path, e))
+    })
+}
+
+pub fn bunny() -> Arc<Bvh> { model("bunny").0 }
+pub fn cow() -> Arc<Bvh> { model("cow").0 }
+pub fn pinecone() -> Arc<Bvh> { model("pinecone").0 }
+// Sized like the models, so that the scene scales it down to a radius of 0.2. It is generated
+// wound outwards, so its report is empty.
+pub fn sphere() -> Arc<Bvh> { cached("sphere", || (uv_sphere(100.0, 32, 64), WindingReport::default())).0 }
+
+#[test]
+fn test_read_obj() {
+    let obj = "# a quad and a triangle\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1 4//1\nf -4 -2 -1\n";
+    let tris = read_obj(obj.as_bytes()).unwrap();
+    assert_eq!(tris.len(), 3);
+    assert_eq!(tris[1].vertices(), &[Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)]);
+    assert_eq!(tris[2].vertices(), &[Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)]);
+    assert!(read_obj("v 0 0 0\nf 1 2 3\n".as_bytes()).is_err());
+}
+
+#[test]
+fn test_sphere_closed_outwards() {
+    let mesh = uv_sphere(1.0, 8, 16);
+    let mut tris: Vec<_> = (0..mesh.triangle_count() as u32).map(|i| mesh.triangle(i)).collect();
+    let report = orient_consistently(&mut tris);
+    assert_eq!(report, WindingReport { components: 1, ..WindingReport::default() });
+}
+
+#[test]
+fn test_load_obj_orients() {
+    // A tetrahedron with its first face wound inwards.
+    let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 2 3\nf 1 2 4\nf 1 4 3\nf 2 3 4\n";
+    let path = std::env::temp_dir().join(format!("test_load_obj_orients_{}.obj", std::process::id()));
+    std::fs::write(&path, obj).unwrap();
+    let (mesh, report) = load_obj(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(report, WindingReport { components: 1, flipped: 1, ..WindingReport::default() });
+    let centroid = Vec3::new(0.25, 0.25, 0.25);
+    for i in 0..4 {
+        let tri = mesh.triangle(i);
+        assert!(tri.normal().dot(tri.vertices()[0] - centroid) > 0.0);
+    }
+}
@@ -1,14 +1,19 @@
-use crate::math::scalar::Scalar;
+use crate::math::scalar::{F32, Scalar};
+use crate::geo::bounds::Bounds;
 use crate::geo::ray::Ray;
 use crate::render::mesh_object::MeshObject;
 use crate::render::object::{Manifold, Object, RaycastPoint};
 use crate::geo::sphere::Sphere;
 use crate::render::plane_object::PlaneObject;
 use crate::render::sphere_object::SphereObject;
+use crate::tree::bvh::Bvh;
+use crate::tree::bvh_wide::WideBvh;
 
 pub enum AnyObject {
     Sphere(SphereObject),
     Model(MeshObject),
+    ModelF32(MeshObject<Bvh<F32>>),
+    ModelWide(MeshObject<WideBvh<4>>),
     Plane(PlaneObject),
 }
 
@@ -17,7 +22,27 @@ impl Object for AnyObject {
         match self {
             AnyObject::Sphere(x) => x.raycast(ray, manifold),
             AnyObject::Model(x) => x.raycast(ray, manifold),
+            AnyObject::ModelF32(x) => x.raycast(ray, manifold),
+            AnyObject::ModelWide(x) => x.raycast(ray, manifold),
             AnyObject::Plane(x) => x.raycast(ray, manifold),
         }
     }
+    fn occluded(&self, ray: &Ray<f64>, max_time: f64, ignore_transmissive: bool) -> bool {
+        match self {
+            AnyObject::Sphere(x) => x.occluded(ray, max_time, ignore_transmissive),
+            AnyObject::Model(x) => x.occluded(ray, max_time, ignore_transmissive),
+            AnyObject::ModelF32(x) => x.occluded(ray, max_time, ignore_transmissive),
+            AnyObject::ModelWide(x) => x.occluded(ray, max_time, ignore_transmissive),
+            AnyObject::Plane(x) => x.occluded(ray, max_time, ignore_transmissive),
+        }
+    }
+    fn bounds(&self) -> Bounds<f64> {
+        match self {
+            AnyObject::Sphere(x) => x.bounds(),
+            AnyObject::Model(x) => x.bounds(),
+            AnyObject::ModelF32(x) => x.bounds(),
+            AnyObject::ModelWide(x) => x.bounds(),
+            AnyObject::Plane(x) => x.bounds(),
+        }
+    }
 }
\ No newline at end of file
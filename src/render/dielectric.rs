@@ -1,6 +1,7 @@
 use rand::{Rng, thread_rng};
 use crate::math::scalar::{Der, Scalar};
 use crate::math::vec::Vec3;
+use crate::render::polarization::{fresnel_mueller, Mueller, retarder_mueller};
 
 #[derive(Debug)]
 pub struct Dielectric<T> {
@@ -8,6 +9,28 @@ pub struct Dielectric<T> {
     pub reflect: Vec3<T>,
     pub refract: Option<Vec3<T>>,
     pub debug: T,
+    pub cos_theta_i: T,
+    pub n_i: T,
+    pub n_t: T,
+    // Whether total internal reflection could have been decided either way under rounding.
+    pub ambiguous: bool,
+    polarization: DielectricPolarization<T>,
+}
+
+// Amplitude coefficients for the s (perp) and p (par) components. Under total internal reflection
+// only the retardance between them is kept.
+#[derive(Debug)]
+enum DielectricPolarization<T> {
+    Partial {
+        r_par: T,
+        r_perp: T,
+        t_par: T,
+        t_perp: T,
+        transmit_scale: T,
+    },
+    Total {
+        retardance: f64,
+    },
 }
 
 impl<T: Scalar> Dielectric<T> {
@@ -34,9 +57,15 @@ impl<T: Scalar> Dielectric<T> {
         let sin2_theta_t = eta * eta * sin2_theta_i;
         let refract;
         let reflectance;
+        let polarization;
         if sin2_theta_t >= T::from(1.0) {
             refract = None;
             reflectance = T::from(1.0);
+            let n = (n_t / n_i).into_const();
+            let cos = cos_theta_i.into_const();
+            let sin2 = sin2_theta_i.into_const();
+            let retardance = 2.0 * (cos * (sin2 - n * n).max(0.0).sqrt() / sin2).atan();
+            polarization = DielectricPolarization::Total { retardance };
         } else {
             let cos_theta_t = (T::from(1.0) - sin2_theta_t).sqrt();
             refract = Some(inc * eta + norm * (eta * cos_theta_i - cos_theta_t));
@@ -45,12 +74,49 @@ impl<T: Scalar> Dielectric<T> {
             let r_perp = ((n_i * cos_theta_i) - (n_t * cos_theta_t)) /
                 ((n_i * cos_theta_i) + (n_t * cos_theta_t));
             reflectance = (r_par * r_par + r_perp * r_perp) / T::from(2.0);
+            let t_par = T::from(2.0) * n_i * cos_theta_i /
+                ((n_t * cos_theta_i) + (n_i * cos_theta_t));
+            let t_perp = T::from(2.0) * n_i * cos_theta_i /
+                ((n_i * cos_theta_i) + (n_t * cos_theta_t));
+            let transmit_scale = (n_t * cos_theta_t) / (n_i * cos_theta_i);
+            polarization = DielectricPolarization::Partial { r_par, r_perp, t_par, t_perp, transmit_scale };
         }
         let reflect = inc - norm * T::from(2.0) * inc.dot(norm);
-        Dielectric { reflectance, reflect, refract, debug: dot }
+        let ambiguous = sin2_theta_t.overlaps(T::from(1.0));
+        Dielectric { reflectance, reflect, refract, debug: dot, cos_theta_i, n_i, n_t, ambiguous, polarization }
+    }
+    // Mueller matrices in the plane of incidence, with the s axis along inc x normal.
+    pub fn reflect_mueller(&self) -> Mueller {
+        match self.polarization {
+            DielectricPolarization::Partial { r_par, r_perp, .. } =>
+                fresnel_mueller(r_perp.into_const(), r_par.into_const(), 1.0),
+            DielectricPolarization::Total { retardance } => retarder_mueller(retardance),
+        }
+    }
+    pub fn refract_mueller(&self) -> Mueller {
+        match self.polarization {
+            DielectricPolarization::Partial { t_par, t_perp, transmit_scale, .. } =>
+                fresnel_mueller(t_perp.into_const(), t_par.into_const(), transmit_scale.into_const()),
+            DielectricPolarization::Total { .. } => Mueller::default(),
+        }
     }
 }
 
+#[test]
+fn test_dielectric_mueller_energy() {
+    let inc = Vec3::new(0.6, -0.8, 0.0);
+    let norm = Vec3::new(0.0, 1.0, 0.0);
+    let diel = Dielectric::new(inc, norm, 1.0, 1.5);
+    let reflect = diel.reflect_mueller();
+    let refract = diel.refract_mueller();
+    assert!((reflect[(0, 0)] - diel.reflectance).abs() < 1e-12);
+    assert!((reflect[(0, 0)] + refract[(0, 0)] - 1.0).abs() < 1e-12);
+    let brewster = Vec3::new(1.5f64.atan().sin(), -1.5f64.atan().cos(), 0.0);
+    let diel = Dielectric::new(brewster, norm, 1.0, 1.5);
+    let reflect = diel.reflect_mueller();
+    assert!((reflect[(0, 0)] - reflect[(0, 1)]).abs() < 1e-12);
+}
+
 #[test]
 fn test_dielectric() {
     for x in 0..10 {
@@ -0,0 +1,295 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+use rand::Rng;
+use crate::geo::bounds::Bounds;
+use crate::geo::ray::Ray;
+use crate::geo::transform::Transform;
+use crate::math::vec::Vec3;
+use crate::render::medium::HenyeyGreenstein;
+
+/// Voxel densities covering the unit cube, with voxel centers at `(i + 0.5) / size`.
+#[derive(Debug)]
+pub struct DensityGrid {
+    size: [usize; 3],
+    density: Vec<f32>,
+}
+
+/// Conservative per-block maximum of a `DensityGrid`, used as the tracking majorant. Blocks are
+/// `block` voxels wide, so when the grid size is not a multiple of it the last block is partial.
+#[derive(Debug)]
+pub struct MajorantGrid {
+    size: [usize; 3],
+    // Blocks per unit length along each axis.
+    scale: [f64; 3],
+    majorant: Vec<f64>,
+}
+
+/// A heterogeneous medium whose density grid is placed in the scene by `transform`. The
+/// coefficients are cross-sections per unit of density.
+pub struct GridMedium {
+    transform: Transform<f64>,
+    grid: Arc<DensityGrid>,
+    majorant: MajorantGrid,
+    pub absorption: f64,
+    pub scattering: f64,
+    pub phase: HenyeyGreenstein,
+}
+
+const MAJORANT_BLOCK: usize = 8;
+
+impl DensityGrid {
+    pub fn new(size: [usize; 3], density: Vec<f32>) -> Self {
+        assert_eq!(size[0] * size[1] * size[2], density.len());
+        DensityGrid { size, density }
+    }
+    // The raw format is three little-endian u32 dimensions followed by one little-endian f32
+    // density per voxel, with x varying fastest and z slowest.
+    pub fn read(mut r: impl Read) -> io::Result<Self> {
+        let mut word = [0u8; 4];
+        let mut size = [0usize; 3];
+        for i in 0..3 {
+            r.read_exact(&mut word)?;
+            size[i] = u32::from_le_bytes(word) as usize;
+        }
+        let mut density = Vec::with_capacity(size[0] * size[1] * size[2]);
+        for _ in 0..size[0] * size[1] * size[2] {
+            r.read_exact(&mut word)?;
+            density.push(f32::from_le_bytes(word));
+        }
+        Ok(DensityGrid::new(size, density))
+    }
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+    pub fn size(&self) -> [usize; 3] { self.size }
+    pub fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.density[x + self.size[0] * (y + self.size[1] * z)] as f64
+    }
+    // Trilinear interpolation of the voxel centers, clamped at the border.
+    pub fn density(&self, p: Vec3<f64>) -> f64 {
+        let mut lo = [0usize; 3];
+        let mut hi = [0usize; 3];
+        let mut frac = [0.0; 3];
+        for i in 0..3 {
+            let x = (p[i] * self.size[i] as f64 - 0.5).clamp(0.0, (self.size[i] - 1) as f64);
+            lo[i] = x.floor() as usize;
+            hi[i] = (lo[i] + 1).min(self.size[i] - 1);
+            frac[i] = x - lo[i] as f64;
+        }
+        let mut total = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0usize; 3];
+            for i in 0..3 {
+                if corner & (1 << i) == 0 {
+                    index[i] = lo[i];
+                    weight *= 1.0 - frac[i];
+                } else {
+                    index[i] = hi[i];
+                    weight *= frac[i];
+                }
+            }
+            total += weight * self.voxel(index[0], index[1], index[2]);
+        }
+        total
+    }
+}
+
+impl MajorantGrid {
+    pub fn new(grid: &DensityGrid, block: usize) -> Self {
+        let size = grid.size().map(|n| (n + block - 1) / block);
+        let mut majorant = Vec::with_capacity(size[0] * size[1] * size[2]);
+        for bz in 0..size[2] {
+            for by in 0..size[1] {
+                for bx in 0..size[0] {
+                    // Interpolation reaches one voxel into the neighboring blocks.
+                    let range = |b: usize, axis: usize| {
+                        (b * block).saturating_sub(1)..((b + 1) * block + 1).min(grid.size()[axis])
+                    };
+                    let mut max = 0.0f64;
+                    for z in range(bz, 2) {
+                        for y in range(by, 1) {
+                            for x in range(bx, 0) {
+                                max = max.max(grid.voxel(x, y, z));
+                            }
+                        }
+                    }
+                    majorant.push(max);
+                }
+            }
+        }
+        let scale = [0, 1, 2].map(|i| grid.size()[i] as f64 / block as f64);
+        MajorantGrid { size, scale, majorant }
+    }
+    fn get(&self, cell: [i64; 3]) -> f64 {
+        let [x, y, z] = cell.map(|x| x as usize);
+        self.majorant[x + self.size[0] * (y + self.size[1] * z)]
+    }
+    // Walks the cells crossed by the local ray between t_min and t_max, calling f with each
+    // sub-interval and its majorant until f returns false.
+    fn traverse(&self, orig: Vec3<f64>, dir: Vec3<f64>, t_min: f64, t_max: f64, mut f: impl FnMut(f64, f64, f64) -> bool) {
+        let start = orig + dir * t_min;
+        let mut cell = [0i64; 3];
+        let mut step = [0i64; 3];
+        let mut next = [f64::INFINITY; 3];
+        let mut delta = [f64::INFINITY; 3];
+        for i in 0..3 {
+            // Cell boundaries follow the block edges in voxel space, not an even split of the cube.
+            let n = self.scale[i];
+            let c = (start[i] * n).floor().clamp(0.0, (self.size[i] - 1) as f64);
+            cell[i] = c as i64;
+            if dir[i] > 0.0 {
+                step[i] = 1;
+                delta[i] = 1.0 / (n * dir[i]);
+                next[i] = t_min + ((c + 1.0) / n - start[i]) / dir[i];
+            } else if dir[i] < 0.0 {
+                step[i] = -1;
+                delta[i] = -1.0 / (n * dir[i]);
+                next[i] = t_min + (c / n - start[i]) / dir[i];
+            }
+        }
+        let mut t = t_min;
+        loop {
+            let axis = (0..3).min_by(|a, b| next[*a].total_cmp(&next[*b])).unwrap();
+            let t_end = next[axis].min(t_max);
+            if !f(t, t_end, self.get(cell)) || t_end >= t_max {
+                return;
+            }
+            t = t_end;
+            cell[axis] += step[axis];
+            if cell[axis] < 0 || cell[axis] >= self.size[axis] as i64 {
+                return;
+            }
+            next[axis] += delta[axis];
+        }
+    }
+}
+
+impl GridMedium {
+    pub fn new(transform: Transform<f64>, grid: Arc<DensityGrid>, absorption: f64, scattering: f64, g: f64) -> Self {
+        let majorant = MajorantGrid::new(&grid, MAJORANT_BLOCK);
+        GridMedium {
+            transform,
+            grid,
+            majorant,
+            absorption,
+            scattering,
+            phase: HenyeyGreenstein::new(g),
+        }
+    }
+    pub fn extinction(&self) -> f64 {
+        self.absorption + self.scattering
+    }
+    // Clips the world-space segment to the grid, returning the local ray and the parameter range.
+    fn clip(&self, orig: Vec3<f64>, dir: Vec3<f64>, length: f64) -> Option<(Ray<f64>, f64, f64)> {
+        let local = self.transform.reverse_ray(&Ray::new(orig, dir));
+        let interval = Bounds::new(Vec3::broadcast(0.0), Vec3::broadcast(1.0)).raycast(&local)?;
+        let t_min = interval.min().max(0.0);
+        let t_max = interval.max().min(length);
+        if t_min < t_max {
+            Some((local, t_min, t_max))
+        } else {
+            None
+        }
+    }
+    // Ratio tracking estimate of the transmittance along a world-space segment with unit direction.
+    pub fn transmittance(&self, orig: Vec3<f64>, dir: Vec3<f64>, length: f64, rng: &mut impl Rng) -> f64 {
+        let (local, t_min, t_max) = match self.clip(orig, dir, length) {
+            None => return 1.0,
+            Some(clip) => clip,
+        };
+        let mut transmittance = 1.0;
+        self.majorant.traverse(local.orig(), local.dir(), t_min, t_max, |start, end, majorant| {
+            let majorant = majorant * self.extinction();
+            if majorant <= 0.0 {
+                return true;
+            }
+            let mut t = start;
+            loop {
+                t -= (1.0 - rng.gen_range(0.0..1.0f64)).ln() / majorant;
+                if t >= end {
+                    return true;
+                }
+                let extinction = self.grid.density(local.pos(t)) * self.extinction();
+                transmittance *= 1.0 - extinction / majorant;
+            }
+        });
+        transmittance
+    }
+    // Delta tracking along a world-space segment. Returns the distance to the first real collision
+    // and the probability that it scatters rather than absorbs.
+    pub fn sample_scatter(&self, orig: Vec3<f64>, dir: Vec3<f64>, length: f64, rng: &mut impl Rng) -> Option<(f64, f64)> {
+        let (local, t_min, t_max) = self.clip(orig, dir, length)?;
+        let mut collision = None;
+        self.majorant.traverse(local.orig(), local.dir(), t_min, t_max, |start, end, majorant| {
+            let majorant = majorant * self.extinction();
+            if majorant <= 0.0 {
+                return true;
+            }
+            let mut t = start;
+            loop {
+                t -= (1.0 - rng.gen_range(0.0..1.0f64)).ln() / majorant;
+                if t >= end {
+                    return true;
+                }
+                let extinction = self.grid.density(local.pos(t)) * self.extinction();
+                if rng.gen_range(0.0..1.0) * majorant < extinction {
+                    collision = Some(t);
+                    return false;
+                }
+            }
+        });
+        Some((collision?, self.scattering / self.extinction()))
+    }
+}
+
+#[test]
+fn test_constant_grid_transmittance() {
+    let grid = Arc::new(DensityGrid::new([4, 4, 4], vec![0.5; 64]));
+    let medium = GridMedium::new(Transform::default(), grid, 1.0, 1.0, 0.0);
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+    let mut rng = SmallRng::seed_from_u64(1);
+    let orig = Vec3::new(-1.0, 0.5, 0.5);
+    let dir = Vec3::new(1.0, 0.0, 0.0);
+    let count = 10000;
+    let estimate: f64 = (0..count).map(|_| medium.transmittance(orig, dir, 10.0, &mut rng)).sum::<f64>() / count as f64;
+    assert!((estimate - (-1.0f64).exp()).abs() < 0.02);
+}
+
+#[test]
+fn test_majorant_bounds_density() {
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+    let mut rng = SmallRng::seed_from_u64(3);
+    // Sizes that are not multiples of the block, with the density falling along every axis so
+    // that looking up the block after the right one underestimates it.
+    let size = [13, 10, 19];
+    let mut density = vec![];
+    for z in 0..size[2] {
+        for y in 0..size[1] {
+            for x in 0..size[0] {
+                density.push((size[0] - x + size[1] - y + size[2] - z) as f32 + rng.gen_range(0.0..1.0));
+            }
+        }
+    }
+    let grid = DensityGrid::new(size, density);
+    let majorant = MajorantGrid::new(&grid, MAJORANT_BLOCK);
+    for _ in 0..1000 {
+        let point = |rng: &mut SmallRng| Vec3::new(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+        let orig = point(&mut rng);
+        let dir = (point(&mut rng) - orig).normalize();
+        let ray = Ray::new(orig, dir);
+        let interval = Bounds::new(Vec3::broadcast(0.0), Vec3::broadcast(1.0)).raycast(&ray).unwrap();
+        majorant.traverse(orig, dir, 0.0, interval.max(), |start, end, majorant| {
+            for _ in 0..10 {
+                let p = ray.pos(rng.gen_range(start..=end));
+                assert!(grid.density(p) <= majorant + 1e-9, "{:?} {} {}", p, grid.density(p), majorant);
+            }
+            true
+        });
+    }
+}
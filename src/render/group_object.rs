@@ -0,0 +1,74 @@
+use std::sync::Arc;
+use crate::geo::bounds::Bounds;
+use crate::geo::ray::Ray;
+use crate::geo::triangle::Sidedness;
+use crate::math::scalar::Scalar;
+use crate::render::mesh_object::MeshTree;
+use crate::render::object::{Manifold, Object, RaycastPoint};
+
+// Objects traced through their own tree as one object, so that groups of instances can nest inside
+// other groups. Unlike a mesh, hits keep the materials of the objects.
+pub struct GroupObject<M> {
+    tree: Arc<M>,
+}
+
+impl<M> GroupObject<M> {
+    pub fn new(tree: Arc<M>) -> Self {
+        GroupObject { tree }
+    }
+}
+
+impl<M: MeshTree> Object for GroupObject<M> {
+    fn raycast<T: Scalar>(&self, ray: &Ray<T>, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
+        self.tree.raycast(ray, Sidedness::Both, manifold)
+    }
+    fn occluded(&self, ray: &Ray<f64>, max_time: f64, ignore_transmissive: bool) -> bool {
+        self.tree.occluded(ray, Sidedness::Both, max_time, ignore_transmissive)
+    }
+    fn bounds(&self) -> Bounds<f64> {
+        self.tree.bounds()
+    }
+}
+
+#[test]
+fn test_nested_groups() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use crate::geo::sphere::Sphere;
+    use crate::geo::transform::TransformBuilder;
+    use crate::math::vec::Vec3;
+    use crate::render::material::Material;
+    use crate::render::sphere_object::SphereObject;
+    use crate::render::transform_object::TransformObject;
+    use crate::tree::bvh::{Bvh, BvhForest};
+    use crate::tree::bvh_sah::SahConfig;
+    let mut rng = SmallRng::seed_from_u64(19);
+    let point = |rng: &mut SmallRng| Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+    fn build<O: Object + Send + Sync>(objects: Vec<O>) -> Arc<Bvh<f64, Vec<O>>> {
+        let objects = Arc::new(objects);
+        let tree = BvhForest::new(&objects).sah(&SahConfig::default());
+        Arc::new(Bvh::new(objects, &tree))
+    }
+    let spheres: Vec<_> = (0..20).map(|_| SphereObject::new(Sphere::new(point(&mut rng), 0.2), Material::nan())).collect();
+    let cluster = build(spheres);
+    // Instances of the same cluster of spheres, grouped in turn.
+    let instances: Vec<_> = (0..10).map(|_| {
+        let center = point(&mut rng) * 10.0;
+        TransformObject::new(TransformBuilder::new().translate(center.x(), center.y(), center.z()).build(), GroupObject::new(cluster.clone()))
+    }).collect();
+    let group = GroupObject::new(build(instances));
+    let instances = group.tree.primitives();
+    let bounds: Bounds<f64> = instances.iter().map(|instance| instance.bounds()).collect();
+    assert_eq!((group.bounds().min(), group.bounds().max()), (bounds.min(), bounds.max()));
+    for _ in 0..200 {
+        let ray = Ray::new(point(&mut rng) * 12.0, point(&mut rng).normalize());
+        let expected = instances.iter().filter_map(|instance| instance.raycast(&ray, None)).map(|x| x.time).min_by(f64::total_cmp);
+        let actual = group.raycast(&ray, None);
+        assert_eq!(actual.as_ref().map(|x| x.time), expected);
+        if let Some(actual) = actual {
+            assert_eq!(group.raycast(&ray, Some(actual.manifold)).unwrap().time, actual.time);
+            assert!(group.occluded(&ray, actual.time * 1.01, false));
+            assert!(!group.occluded(&ray, actual.time * 0.99, false));
+        }
+    }
+}
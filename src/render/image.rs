@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::io;
-use std::io::{BufWriter, Write};
+use std::io::{BufRead, BufWriter, Write};
 use std::path::Path;
 use image::{ImageBuffer, ImageOutputFormat, Rgb};
-use image::codecs::hdr::HdrEncoder;
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
+use image::ImageResult;
 use crate::geo::color::{Color, smpte2048_encode};
 use crate::math::vec::Vec3;
 
@@ -30,6 +31,19 @@ impl ImageBuilder {
     pub fn size(&self) -> (usize, usize) {
         self.size
     }
+    pub fn get(&self, x: usize, y: usize) -> Option<Color> {
+        self.pixels.get(&(x, y)).map(|pixel| pixel.average())
+    }
+    pub fn read_hdr(r: impl BufRead) -> ImageResult<Self> {
+        let decoder = HdrDecoder::new(r)?;
+        let metadata = decoder.metadata();
+        let size = (metadata.width as usize, metadata.height as usize);
+        let mut result = ImageBuilder::new(size);
+        for (index, Rgb([r, g, b])) in decoder.read_image_hdr()?.into_iter().enumerate() {
+            result.insert(index % size.0, index / size.0, Color::new(r as f64, g as f64, b as f64));
+        }
+        Ok(result)
+    }
     pub fn smpte2048_encode(&self) -> Self {
         Self {
             pixels: self.pixels.iter().map(
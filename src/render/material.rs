@@ -1,13 +1,22 @@
 use crate::geo::color::Color;
+use crate::render::medium::Medium;
+use crate::render::param::Param;
+use crate::render::thin_film::{Conductor, ThinFilm};
 
 #[derive(Copy, Clone, Default, Debug)]
 pub struct Material {
     pub diffuse: Color,
-    pub dielectric: Option<(f64, f64)>,
+    pub dielectric: Option<(Param, Param)>,
+    pub medium: Option<Medium>,
+    pub conductor: Option<Conductor>,
+    pub thin_film: Option<ThinFilm>,
 }
 
 impl Material {
     pub fn nan() -> Self {
-        Material { diffuse: Color::nan(), dielectric: None }
+        Material { diffuse: Color::nan(), dielectric: None, medium: None, conductor: None, thin_film: None }
+    }
+    pub fn is_transmissive(&self) -> bool {
+        self.dielectric.is_some()
     }
 }
\ No newline at end of file
@@ -0,0 +1,88 @@
+use std::f64::consts::PI;
+use rand::Rng;
+use crate::math::vec::Vec3;
+use crate::geo::color::Color;
+
+#[derive(Copy, Clone, Debug)]
+pub struct HenyeyGreenstein {
+    g: f64,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Medium {
+    pub absorption: f64,
+    pub scattering: f64,
+    pub phase: HenyeyGreenstein,
+}
+
+/// A piece of a traced path that travels through a medium. `dir` is normalized and `length` may be
+/// infinite for rays that escape the scene. Segments outside of any object are `heterogeneous`, meaning
+/// the scene's grid media also apply. `specular_depth` counts the specular bounces before it.
+#[derive(Clone, Debug)]
+pub struct MediumSegment {
+    pub orig: Vec3<f64>,
+    pub dir: Vec3<f64>,
+    pub length: f64,
+    pub medium: Option<Medium>,
+    pub heterogeneous: bool,
+    pub specular_depth: usize,
+    pub attenuation: f64,
+    pub tint: Color,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        assert!(-1.0 < g && g < 1.0);
+        HenyeyGreenstein { g }
+    }
+    pub fn g(&self) -> f64 { self.g }
+    // cos_theta is measured between the incoming and outgoing directions of propagation.
+    pub fn eval(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+}
+
+impl Medium {
+    pub fn new(absorption: f64, scattering: f64, g: f64) -> Self {
+        Medium { absorption, scattering, phase: HenyeyGreenstein::new(g) }
+    }
+    pub fn extinction(&self) -> f64 {
+        self.absorption + self.scattering
+    }
+    pub fn transmittance(&self, distance: f64) -> f64 {
+        (-self.extinction() * distance).exp()
+    }
+    // Samples a scattering distance in 0..length proportionally to the transmittance. Returns the
+    // distance and the weight (scattering * transmittance / pdf) of the sample.
+    pub fn sample_distance(&self, length: f64, rng: &mut impl Rng) -> (f64, f64) {
+        let extinction = self.extinction();
+        if extinction <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let collide = 1.0 - self.transmittance(length);
+        let u: f64 = rng.gen_range(0.0..1.0);
+        let distance = -(1.0 - u * collide).ln() / extinction;
+        (distance, self.scattering / extinction * collide)
+    }
+}
+
+impl MediumSegment {
+    pub fn pos(&self, distance: f64) -> Vec3<f64> {
+        self.orig + self.dir * distance
+    }
+}
+
+#[test]
+fn test_henyey_greenstein_normalized() {
+    for g in [-0.5, 0.0, 0.3, 0.8] {
+        let phase = HenyeyGreenstein::new(g);
+        let steps = 100000;
+        let total: f64 = (0..steps).map(|i| {
+            let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / (steps as f64);
+            phase.eval(cos_theta) * 2.0 * PI * 2.0 / (steps as f64)
+        }).sum();
+        assert!((total - 1.0).abs() < 1e-3);
+    }
+}
@@ -1,27 +1,70 @@
 use std::sync::Arc;
+use crate::geo::bounds::Bounds;
 use crate::geo::ray::Ray;
+use crate::geo::triangle::Sidedness;
 //use crate::bvh::BVH;
 use crate::geo::color::Color;
 use crate::math::scalar::Scalar;
 use crate::render::material::Material;
 use crate::tree::bvh::Bvh;
 use crate::render::object::{Manifold, Object, RaycastPoint};
+use crate::tree::bvh_wide::WideBvh;
+use crate::tree::primitive::PrimitiveSet;
 
-pub struct MeshObject {
-    mesh: Arc<Bvh>,
+// The trees a mesh can be traced through.
+pub trait MeshTree: Send + Sync {
+    fn raycast<T: Scalar>(&self, ray: &Ray<T>, sidedness: Sidedness, manifold: Option<Manifold>) -> Option<RaycastPoint<T>>;
+    fn occluded(&self, ray: &Ray<f64>, sidedness: Sidedness, max_time: f64, ignore_transmissive: bool) -> bool;
+    fn bounds(&self) -> Bounds<f64>;
+}
+
+pub struct MeshObject<M = Bvh> {
+    mesh: Arc<M>,
     material: Material,
+    sidedness: Sidedness,
 }
 
-impl MeshObject {
-    pub fn new(mesh: Arc<Bvh>, material: Material) -> Self {
-        MeshObject { mesh, material }
+impl<M> MeshObject<M> {
+    pub fn new(mesh: Arc<M>, material: Material) -> Self {
+        MeshObject { mesh, material, sidedness: Sidedness::Front }
+    }
+    pub fn with_sidedness(self, sidedness: Sidedness) -> Self {
+        MeshObject { sidedness, ..self }
     }
 }
 
+impl<P: Scalar + Sync + Send, S: PrimitiveSet> MeshTree for Bvh<P, S> {
+    fn raycast<T: Scalar>(&self, ray: &Ray<T>, sidedness: Sidedness, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
+        Bvh::raycast(self, ray, sidedness, manifold)
+    }
+    fn occluded(&self, ray: &Ray<f64>, sidedness: Sidedness, max_time: f64, ignore_transmissive: bool) -> bool {
+        Bvh::occluded(self, ray, sidedness, max_time, ignore_transmissive)
+    }
+    fn bounds(&self) -> Bounds<f64> { Bvh::bounds(self) }
+}
 
-impl Object for MeshObject {
+impl<const W: usize, S: PrimitiveSet> MeshTree for WideBvh<W, S> {
+    fn raycast<T: Scalar>(&self, ray: &Ray<T>, sidedness: Sidedness, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
+        WideBvh::raycast(self, ray, sidedness, manifold)
+    }
+    fn occluded(&self, ray: &Ray<f64>, sidedness: Sidedness, max_time: f64, ignore_transmissive: bool) -> bool {
+        WideBvh::occluded(self, ray, sidedness, max_time, ignore_transmissive)
+    }
+    fn bounds(&self) -> Bounds<f64> { WideBvh::bounds(self) }
+}
+
+impl<M: MeshTree> Object for MeshObject<M> {
     fn raycast<T: Scalar>(&self, ray: &Ray<T>, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
-        let point = self.mesh.raycast(ray, manifold)?;
+        let point = self.mesh.raycast(ray, self.sidedness, manifold)?;
         Some(RaycastPoint { material: self.material, ..point })
     }
-}
\ No newline at end of file
+    fn occluded(&self, ray: &Ray<f64>, max_time: f64, ignore_transmissive: bool) -> bool {
+        if ignore_transmissive && self.material.is_transmissive() {
+            return false;
+        }
+        self.mesh.occluded(ray, self.sidedness, max_time, ignore_transmissive)
+    }
+    fn bounds(&self) -> Bounds<f64> {
+        self.mesh.bounds()
+    }
+}
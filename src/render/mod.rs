@@ -1,6 +1,7 @@
 pub mod any_object;
 pub mod image;
 pub mod mesh_object;
+pub mod group_object;
 pub mod object;
 pub mod scene_object;
 pub mod renderer;
@@ -9,3 +10,12 @@ pub mod transform_object;
 pub mod plane_object;
 pub mod material;
 pub mod dielectric;
+pub mod medium;
+pub mod grid_medium;
+pub mod polarization;
+pub mod spectrum;
+pub mod thin_film;
+pub mod stats;
+pub mod newton;
+pub mod param;
+pub mod optimize;
@@ -0,0 +1,93 @@
+use crate::math::mat::Mat2;
+use crate::math::vec::Vec2;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NewtonOutcome {
+    Converged,
+    Diverged,
+    TopologyChanged,
+    Occluded,
+}
+
+/// Damped Newton iteration driving a 2D separation to zero.
+#[derive(Copy, Clone, Debug)]
+pub struct NewtonSolver {
+    pub max_steps: usize,
+    pub tolerance: f64,
+    // Backtracking gives up once the step has been halved below this fraction.
+    pub min_damping: f64,
+    // Jacobians with a larger (estimated) condition number are treated as singular.
+    pub max_condition: f64,
+}
+
+// Upper bound on the condition number of a 2x2 matrix: |J|_F^2 / |det J| = s1/s2 + s2/s1.
+fn condition(jacobian: Mat2<f64>) -> f64 {
+    let [[a, b], [c, d]] = jacobian.into_row_array();
+    let det = a * d - b * c;
+    (a * a + b * b + c * c + d * d) / det.abs()
+}
+
+impl NewtonSolver {
+    pub fn new(max_steps: usize, tolerance: f64) -> Self {
+        NewtonSolver { max_steps, tolerance, min_damping: 1.0 / 64.0, max_condition: 1e8 }
+    }
+    // f evaluates the separation and its Jacobian at x, or returns None if the path no longer
+    // exists there. The outcome is never Occluded; that is for the caller to decide.
+    pub fn solve(&self, mut x: Vec2<f64>, mut f: impl FnMut(Vec2<f64>) -> Option<(Vec2<f64>, Mat2<f64>)>) -> (Vec2<f64>, NewtonOutcome) {
+        let (mut sep, mut jacobian) = match f(x) {
+            None => return (x, NewtonOutcome::TopologyChanged),
+            Some(eval) => eval,
+        };
+        for _ in 0..self.max_steps {
+            let norm = sep.length();
+            if norm < self.tolerance {
+                return (x, NewtonOutcome::Converged);
+            }
+            if !(condition(jacobian) <= self.max_condition) {
+                return (x, NewtonOutcome::Diverged);
+            }
+            let step = jacobian.inverse() * sep;
+            let mut damping = 1.0;
+            loop {
+                let candidate = x - step * damping;
+                match f(candidate) {
+                    Some((new_sep, new_jacobian)) if new_sep.length() < norm => {
+                        x = candidate;
+                        sep = new_sep;
+                        jacobian = new_jacobian;
+                        break;
+                    }
+                    failure => {
+                        damping /= 2.0;
+                        if damping < self.min_damping {
+                            let outcome = if failure.is_none() { NewtonOutcome::TopologyChanged } else { NewtonOutcome::Diverged };
+                            return (x, outcome);
+                        }
+                    }
+                }
+            }
+        }
+        if sep.length() < self.tolerance {
+            (x, NewtonOutcome::Converged)
+        } else {
+            (x, NewtonOutcome::Diverged)
+        }
+    }
+}
+
+#[test]
+fn test_newton() {
+    let solver = NewtonSolver::new(20, 1e-10);
+    let target = Vec2::new(2.0, 3.0);
+    let f = |x: Vec2<f64>| {
+        let sep = Vec2::new(x.x() * x.x() * x.x(), x.y() * x.x()) - target;
+        Some((sep, Mat2::from_row_arrays([[3.0 * x.x() * x.x(), 0.0], [x.y(), x.x()]])))
+    };
+    let (x, outcome) = solver.solve(Vec2::new(1.0, 1.0), f);
+    assert_eq!(outcome, NewtonOutcome::Converged);
+    assert!((x.x() - 2f64.cbrt()).abs() < 1e-8);
+    let (_, outcome) = solver.solve(Vec2::new(1.0, 1.0), |_| Some((Vec2::new(1.0, 1.0), Mat2::from_row_arrays([[1.0, 1.0], [1.0, 1.0]]))));
+    assert_eq!(outcome, NewtonOutcome::Diverged);
+    let (_, outcome) = solver.solve(Vec2::new(1.0, 1.0), |x| (x.x() == 1.0).then(|| (x, Mat2::identity())));
+    assert_eq!(outcome, NewtonOutcome::TopologyChanged);
+}
@@ -3,7 +3,7 @@ use std::mem;
 use arrayvec::ArrayVec;
 use crate::geo::ray::Ray;
 use ordered_float::NotNan;
-use crate::geo::bounds::Interval;
+use crate::geo::bounds::{Bounds, Interval};
 use crate::geo::color::Color;
 use crate::math::scalar::Scalar;
 use crate::math::vec::{Vec2, Vec3};
@@ -21,9 +21,13 @@ pub struct RaycastPoint<T> {
     pub position: Vec3<T>,
     pub inter_normal: Vec3<T>,
     pub geo_normal: Vec3<T>,
+    // Bound on the absolute rounding error in each component of position.
+    pub error: Vec3<f64>,
     pub manifold: Manifold,
     pub manifold_point: Vec2<T>,
     pub material: Material,
+    // Whether rounding could have made a different hit the closest one (see Scalar::overlaps).
+    pub ambiguous: bool,
 }
 
 pub struct RaycastPointHolder<T> {
@@ -32,6 +36,13 @@ pub struct RaycastPointHolder<T> {
 
 pub trait Object: Sync {
     fn raycast<T: Scalar>(&self, ray: &Ray<T>, manifold: Option<Manifold>) -> Option<RaycastPoint<T>>;
+    // Whether anything hits the ray before max_time, stopping at the first hit found rather than
+    // the closest. Visibility is only needed at the constant part, so this is not generic.
+    // Transmissive objects can be skipped, for light that passes through them. The renderer's
+    // shadow rays keep them, as light refracted through them arrives as photons instead.
+    fn occluded(&self, ray: &Ray<f64>, max_time: f64, ignore_transmissive: bool) -> bool;
+    // Bounds in the object's own space, infinite along any axis the object is unbounded in.
+    fn bounds(&self) -> Bounds<f64>;
 }
 
 impl Manifold {
@@ -60,10 +71,14 @@ impl Manifold {
 impl<T: Scalar> RaycastPointHolder<T> {
     pub fn new() -> Self { RaycastPointHolder { point: None } }
     pub fn add(&mut self, new: Option<RaycastPoint<T>>) {
-        if let Some(new) = new {
+        if let Some(mut new) = new {
             if let Some(old) = &mut self.point {
+                let ambiguous = new.time.overlaps(old.time);
                 if new.time < old.time {
+                    new.ambiguous |= ambiguous;
                     self.point = Some(new)
+                } else {
+                    old.ambiguous |= ambiguous;
                 }
             } else {
                 self.point = Some(new)
@@ -78,6 +93,22 @@ impl<T: Scalar> RaycastPointHolder<T> {
     }
 }
 
+impl<T: Scalar> RaycastPoint<T> {
+    pub fn into_const(&self) -> RaycastPoint<f64> {
+        RaycastPoint {
+            time: self.time.into_const(),
+            position: self.position.map(|x| x.into_const()),
+            inter_normal: self.inter_normal.map(|x| x.into_const()),
+            geo_normal: self.geo_normal.map(|x| x.into_const()),
+            error: self.error,
+            manifold: self.manifold,
+            manifold_point: self.manifold_point.map(|x| x.into_const()),
+            material: self.material,
+            ambiguous: self.ambiguous,
+        }
+    }
+}
+
 impl<T: Scalar> PartialEq for RaycastPoint<T> {
     fn eq(&self, other: &Self) -> bool {
         self.time.real_eq(other.time)
@@ -0,0 +1,179 @@
+use crate::render::image::ImageBuilder;
+use crate::render::object::Object;
+use crate::render::renderer::{Renderer, Scene};
+
+/// Fits the differentiable parameters of a scene to a target image by gradient descent on the mean
+/// squared pixel error. Parameter i of the fit should be marked with index i in the scene.
+#[derive(Copy, Clone, Debug)]
+pub struct GradientDescent {
+    pub steps: usize,
+    pub rate: f64,
+    // Step for the central differences of the caustic term, which automatic differentiation does
+    // not reach because photons are deposited at fixed positions.
+    pub caustic_epsilon: f64,
+    // Every render of the fit uses this seed, so the differences of the caustic term come from the
+    // parameters rather than from the photons.
+    pub seed: u64,
+    // Prints the loss, parameters and gradient of every step.
+    pub log: bool,
+}
+
+impl GradientDescent {
+    pub fn new(steps: usize, rate: f64) -> Self {
+        GradientDescent { steps, rate, caustic_epsilon: 1e-3, seed: 0, log: false }
+    }
+    pub fn fit<S: Object, const N: usize>(&self, build: impl Fn(&[f64; N]) -> Scene<S>, target: &ImageBuilder, mut params: [f64; N]) -> [f64; N] {
+        for step in 0..self.steps {
+            let (loss, gradient) = self.loss_gradient(&build, target, &params);
+            if self.log {
+                println!("step {} loss {:?} params {:?} gradient {:?}", step, loss, params, gradient);
+            }
+            for i in 0..N {
+                params[i] -= self.rate * gradient[i];
+            }
+        }
+        params
+    }
+    pub fn loss_gradient<S: Object, const N: usize>(&self, build: &impl Fn(&[f64; N]) -> Scene<S>, target: &ImageBuilder, params: &[f64; N]) -> (f64, [f64; N]) {
+        let mut renderer = Renderer::new(self.build(build, params));
+        renderer.render();
+        let image = renderer.radiosity();
+        let mut derivatives = renderer.render_derivatives::<N>();
+        for i in 0..N {
+            let mut caustics = [None, None];
+            for (caustic, sign) in caustics.iter_mut().zip([1.0, -1.0]) {
+                let mut params = *params;
+                params[i] += sign * self.caustic_epsilon;
+                let mut renderer = Renderer::new(self.build(build, &params));
+                renderer.trace_photons();
+                *caustic = Some(renderer.render_caustics());
+            }
+            let [plus, minus] = caustics.map(Option::unwrap);
+            derivatives[i] = difference(&derivatives[i], &plus, &minus, 0.5 / self.caustic_epsilon);
+        }
+        let (width, height) = image.size();
+        let mut loss = 0.0;
+        let mut gradient = [0.0; N];
+        let mut count = 0;
+        for x in 0..width {
+            for y in 0..height {
+                if let (Some(value), Some(target)) = (image.get(x, y), target.get(x, y)) {
+                    let error = value - target;
+                    loss += error.dot(error);
+                    for i in 0..N {
+                        gradient[i] += 2.0 * error.dot(derivatives[i].get(x, y).unwrap_or_default());
+                    }
+                    count += 1;
+                }
+            }
+        }
+        let count = count.max(1) as f64;
+        (loss / count, gradient.map(|x| x / count))
+    }
+    fn build<S: Object, const N: usize>(&self, build: &impl Fn(&[f64; N]) -> Scene<S>, params: &[f64; N]) -> Scene<S> {
+        Scene { seed: Some(self.seed), ..build(params) }
+    }
+}
+
+// base + (plus - minus) * scale, per pixel.
+fn difference(base: &ImageBuilder, plus: &ImageBuilder, minus: &ImageBuilder, scale: f64) -> ImageBuilder {
+    let (width, height) = base.size();
+    let mut result = ImageBuilder::new(base.size());
+    for x in 0..width {
+        for y in 0..height {
+            let get = |image: &ImageBuilder| image.get(x, y).unwrap_or_default();
+            result.insert(x, y, get(base) + (get(plus) - get(minus)) * scale);
+        }
+    }
+    result
+}
+
+#[test]
+fn test_fit_ior() {
+    use crate::SceneBuilder;
+    use crate::geo::color::Color;
+    use crate::geo::sphere::Sphere;
+    use crate::geo::transform::Transform;
+    use crate::geo::view::View;
+    use crate::math::vec::{Vec2, Vec3};
+    use crate::render::any_object::AnyObject;
+    use crate::render::material::Material;
+    use crate::render::param::Param;
+    use crate::render::plane_object::PlaneObject;
+    use crate::render::renderer::Light;
+    use crate::render::scene_object::SceneObject;
+    use crate::render::transform_object::TransformObject;
+    let builder = SceneBuilder::new(0);
+    // The sphere in front of a wall lit from the side, which it refracts without any sharp edges.
+    // Its caustic and shadow fall outside of the view, so no photons need to be gathered.
+    let build = |params: &[f64; 1]| {
+        let white = Material { diffuse: Color::new(1.0, 1.0, 1.0), dielectric: None, medium: None, conductor: None, thin_film: None };
+        let wall = PlaneObject::new(Vec3::new(0.0, 0.0, -0.5), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), white, white);
+        Scene {
+            size: (32, 32),
+            view: View::from_fov(Vec3::new(0.0, -0.2, 1.0), Vec2::new(0.93, 0.93)),
+            lights: vec![Light { sphere: Sphere::new(Vec3::new(1.5, 0.5, 0.5), 1.0), color: Color::broadcast(30.0) }],
+            scene_object: SceneObject::new(vec![
+                builder.sphere_with_ior(Param::var(params[0], 0)),
+                TransformObject::new(Transform::default(), AnyObject::Plane(wall)),
+            ]),
+            photon_count: 2000,
+            photon_samples: 0,
+            ..builder.scene()
+        }
+    };
+    let mut target = Renderer::new(Scene { seed: Some(1), ..build(&[1.5]) });
+    target.render();
+    let descent = GradientDescent { seed: 2, ..GradientDescent::new(10, 10.0) };
+    let [ior] = descent.fit(build, target.radiosity(), [1.4]);
+    assert!((ior - 1.5).abs() < 1e-3, "{}", ior);
+}
+
+#[test]
+fn test_fit_caustic() {
+    use crate::SceneBuilder;
+    use crate::geo::color::Color;
+    use crate::geo::sphere::Sphere;
+    use crate::geo::transform::Transform;
+    use crate::geo::view::View;
+    use crate::math::vec::{Vec2, Vec3};
+    use crate::render::any_object::AnyObject;
+    use crate::render::material::Material;
+    use crate::render::param::Param;
+    use crate::render::plane_object::PlaneObject;
+    use crate::render::renderer::Light;
+    use crate::render::scene_object::SceneObject;
+    use crate::render::sphere_object::SphereObject;
+    use crate::render::transform_object::TransformObject;
+    let builder = SceneBuilder::new(0);
+    // The view only sees the part of the wall in the sphere's shadow, where all of the light is the
+    // caustic focused by the sphere, so the whole gradient comes from the gathered photons.
+    let build = |params: &[f64; 1]| {
+        let white = Material { diffuse: Color::new(1.0, 1.0, 1.0), ..Material::default() };
+        let glass = Material { dielectric: Some((1.0.into(), Param::var(params[0], 0))), ..Material::default() };
+        let wall = PlaneObject::new(Vec3::new(0.0, 0.0, -0.5), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), white, white);
+        Scene {
+            size: (8, 8),
+            view: View::from_fov(Vec3::new(-0.4, 0.0, 0.3), Vec2::new(0.5, 0.5)),
+            lights: vec![Light { sphere: Sphere::new(Vec3::new(0.8, 0.0, 1.0), 1.0), color: Color::broadcast(30.0) }],
+            scene_object: SceneObject::new(vec![
+                TransformObject::new(Transform::default(), AnyObject::Sphere(SphereObject::new(Sphere::new(Vec3::broadcast(0.0), 0.2), glass))),
+                TransformObject::new(Transform::default(), AnyObject::Plane(wall)),
+            ]),
+            photon_count: 20000,
+            photon_samples: 8,
+            ..builder.scene()
+        }
+    };
+    let mut target = Renderer::new(Scene { seed: Some(1), ..build(&[1.5]) });
+    target.render();
+    let caustics = target.render_caustics();
+    for x in 0..8 {
+        for y in 0..8 {
+            assert_eq!(caustics.get(x, y), target.radiosity().get(x, y));
+        }
+    }
+    let descent = GradientDescent { seed: 2, ..GradientDescent::new(5, 0.002) };
+    let [ior] = descent.fit(build, target.radiosity(), [1.47]);
+    assert!((ior - 1.5).abs() < 1e-3, "{}", ior);
+}
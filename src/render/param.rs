@@ -0,0 +1,36 @@
+use crate::math::scalar::Scalar;
+
+/// A scene value that may be marked as differentiable. When traced with an AD scalar a marked
+/// value seeds the derivative with respect to input `index`; otherwise it is a constant.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Param {
+    pub value: f64,
+    pub index: Option<usize>,
+}
+
+impl Param {
+    pub fn new(value: f64) -> Self {
+        Param { value, index: None }
+    }
+    pub fn var(value: f64, index: usize) -> Self {
+        Param { value, index: Some(index) }
+    }
+    pub fn get<T: Scalar>(self) -> T {
+        match self.index {
+            None => T::from(self.value),
+            Some(index) => T::param(self.value, index),
+        }
+    }
+}
+
+impl From<f64> for Param {
+    fn from(value: f64) -> Self { Param::new(value) }
+}
+
+#[test]
+fn test_param() {
+    use crate::math::scalar::Der;
+    assert_eq!(Param::new(2.0).get::<Der<2>>(), Der::from(2.0));
+    assert_eq!(Param::var(2.0, 1).get::<Der<2>>(), Der::var(2.0, 1));
+    assert_eq!(Param::var(2.0, 1).get::<f64>(), 2.0);
+}
@@ -1,5 +1,6 @@
+use crate::geo::bounds::Bounds;
 use crate::geo::ray::Ray;
-use crate::math::scalar::Scalar;
+use crate::math::scalar::{gamma, Scalar};
 use crate::render::object::{Manifold, Object, RaycastPoint};
 use crate::geo::color::Color;
 use crate::math::vec::{Vec2, Vec3};
@@ -29,7 +30,9 @@ impl Object for PlaneObject {
         if t < T::from(0.0) {
             return None;
         }
+        // Project back onto the plane, so the error does not depend on t.
         let x = ray.pos(t);
+        let x = x - n * ((x - p).dot(n) / n.dot(n));
         let m1 = (x - p).dot(self.tan1.cast());
         let m2 = (x - p).dot(self.tan2.cast());
         let scale = 10.0;
@@ -42,9 +45,31 @@ impl Object for PlaneObject {
             position: x,
             inter_normal: n,
             geo_normal: n,
+            error: (x.map(|x| x.into_const().abs()) + self.position.map(f64::abs)) * gamma::<T>(7),
             material,
             manifold: Manifold::empty(),
             manifold_point: Vec2::new(m1, m2),
+            ambiguous: t.overlaps(T::from(0.0)),
         })
     }
-}
\ No newline at end of file
+    fn occluded(&self, ray: &Ray<f64>, max_time: f64, ignore_transmissive: bool) -> bool {
+        self.raycast(ray, None).map_or(false, |point| {
+            point.time < max_time && !(ignore_transmissive && point.material.is_transmissive())
+        })
+    }
+    fn bounds(&self) -> Bounds<f64> {
+        Bounds::full()
+    }
+}
+#[test]
+fn test_plane_occluded() {
+    let plane = PlaneObject::new(Vec3::new(0.0, -0.5, 0.0), Vec3::new(0.0, 0.0, -1.0), Vec3::new(-1.0, 0.0, 0.0), Material::default(), Material::default());
+    for dir in [Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, -3.0, 0.0)] {
+        let ray = Ray::new(Vec3::broadcast(0.0), dir);
+        let time = 0.5 / dir.length();
+        assert!(plane.occluded(&ray, time * 1.01, false));
+        assert!(!plane.occluded(&ray, time * 0.99, false));
+    }
+    // The plane is behind a ray leaving it.
+    assert!(!plane.occluded(&Ray::new(Vec3::broadcast(0.0), Vec3::new(0.0, 1.0, 0.0)), 10.0, false));
+}
@@ -0,0 +1,154 @@
+use crate::math::complex::Complex;
+use crate::math::mat::Mat4;
+use crate::math::vec::{Vec3, Vec4};
+use crate::render::spectrum::RGB_WAVELENGTHS;
+
+pub type Stokes = Vec4<f64>;
+pub type Mueller = Mat4<f64>;
+
+/// The Mueller matrix of a camera path so far, mapping Stokes vectors on the current segment into
+/// the camera's frame. `frame` is the reference (s) axis of the current segment.
+#[derive(Copy, Clone, Debug)]
+pub struct PolarizationFrame {
+    mueller: Mueller,
+    frame: Vec3<f64>,
+}
+
+pub fn unpolarized(intensity: f64) -> Stokes {
+    Stokes::new(intensity, 0.0, 0.0, 0.0)
+}
+
+// Converts Stokes vectors expressed in a frame rotated by angle into the unrotated frame.
+pub fn rotator(angle: f64) -> Mueller {
+    let (s, c) = (2.0 * angle).sin_cos();
+    Mueller::from_row_arrays([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, c, -s, 0.0],
+        [0.0, s, c, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+}
+
+// Mueller matrix for amplitude coefficients along s and p, scaled by the energy factor.
+pub fn fresnel_mueller(s: f64, p: f64, scale: f64) -> Mueller {
+    let sum = (s * s + p * p) / 2.0;
+    let diff = (s * s - p * p) / 2.0;
+    let cross = s * p;
+    Mueller::from_row_arrays([
+        [sum, diff, 0.0, 0.0],
+        [diff, sum, 0.0, 0.0],
+        [0.0, 0.0, cross, 0.0],
+        [0.0, 0.0, 0.0, cross],
+    ]) * scale
+}
+
+// Mueller matrix for complex reflection coefficients along s and p, whose phase difference
+// retards p against s, as for conductors and thin films.
+pub fn complex_fresnel_mueller(s: Complex, p: Complex) -> Mueller {
+    let sum = (s.norm_sqr() + p.norm_sqr()) / 2.0;
+    let diff = (s.norm_sqr() - p.norm_sqr()) / 2.0;
+    let cross = s * p.conj();
+    Mueller::from_row_arrays([
+        [sum, diff, 0.0, 0.0],
+        [diff, sum, 0.0, 0.0],
+        [0.0, 0.0, cross.re, cross.im],
+        [0.0, 0.0, -cross.im, cross.re],
+    ])
+}
+
+// The sum of a wavelength dependent interaction over the RGB wavelengths. The frame is shared by
+// all wavelengths, so it follows the polarization of their sum.
+pub fn rgb_mueller(mut f: impl FnMut(f64) -> Mueller) -> Mueller {
+    RGB_WAVELENGTHS.iter().fold(Mueller::default(), |sum, &wavelength| sum + f(wavelength))
+}
+
+// Total internal reflection keeps the intensity but shifts the phase between s and p.
+pub fn retarder_mueller(retardance: f64) -> Mueller {
+    let (s, c) = retardance.sin_cos();
+    Mueller::from_row_arrays([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, c, s],
+        [0.0, 0.0, -s, c],
+    ])
+}
+
+// The interaction scaled to unit intensity for unpolarized light, leaving only how it polarizes.
+pub fn normalize_mueller(mueller: Mueller) -> Mueller {
+    if mueller[(0, 0)] > 0.0 { mueller * (1.0 / mueller[(0, 0)]) } else { mueller }
+}
+
+pub fn degree_of_polarization(stokes: Stokes) -> f64 {
+    if stokes.x() <= 0.0 {
+        return 0.0;
+    }
+    (stokes.y() * stokes.y() + stokes.z() * stokes.z() + stokes.w() * stokes.w()).sqrt() / stokes.x()
+}
+
+fn perpendicular(dir: Vec3<f64>) -> Vec3<f64> {
+    let frame = dir.cross(Vec3::new(0.0, 1.0, 0.0));
+    if frame.length() > 1e-6 {
+        frame.normalize()
+    } else {
+        dir.cross(Vec3::new(1.0, 0.0, 0.0)).normalize()
+    }
+}
+
+impl PolarizationFrame {
+    pub fn new(dir: Vec3<f64>) -> Self {
+        PolarizationFrame { mueller: Mueller::identity(), frame: perpendicular(dir.normalize()) }
+    }
+    // Prepends an interaction at a surface hit by a ray travelling along dir. The interaction is
+    // expressed in the plane of incidence, whose s axis becomes the frame of the next segment.
+    pub fn interact(&self, dir: Vec3<f64>, normal: Vec3<f64>, interaction: Mueller) -> Self {
+        let dir = dir.normalize();
+        let s = dir.cross(normal);
+        let s = if s.length() > 1e-9 { s.normalize() } else { self.frame };
+        let angle = self.frame.cross(s).dot(dir).atan2(self.frame.dot(s));
+        PolarizationFrame {
+            mueller: self.mueller * rotator(angle) * interaction,
+            frame: s,
+        }
+    }
+    pub fn mueller(&self) -> Mueller { self.mueller }
+    pub fn intensity(&self) -> f64 { self.mueller[(0, 0)] }
+    // The Stokes vector reaching the camera from unpolarized light of unit intensity.
+    pub fn stokes(&self) -> Stokes {
+        self.mueller * unpolarized(1.0)
+    }
+}
+
+#[test]
+fn test_crossed_polarizers() {
+    let polarizer = fresnel_mueller(1.0, 0.0, 1.0);
+    let dir = Vec3::new(0.0, 0.0, -1.0);
+    let frame = PolarizationFrame::new(dir)
+        .interact(dir, Vec3::new(0.0, 1.0, 1.0).normalize(), polarizer)
+        .interact(dir, Vec3::new(1.0, 0.0, 1.0).normalize(), polarizer);
+    assert!(frame.intensity().abs() < 1e-12);
+    let frame = PolarizationFrame::new(dir)
+        .interact(dir, Vec3::new(0.0, 1.0, 1.0).normalize(), polarizer)
+        .interact(dir, Vec3::new(0.0, -1.0, 1.0).normalize(), polarizer);
+    assert!((frame.intensity() - 0.5).abs() < 1e-12);
+    assert!((degree_of_polarization(frame.stokes()) - 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_complex_fresnel_mueller() {
+    use crate::render::thin_film::fresnel_amplitudes;
+    // For a dielectric the coefficients are real and match the real Mueller matrix.
+    let (s, p) = fresnel_amplitudes(1.0, Complex::from(1.5), 0.6);
+    let real = fresnel_mueller(s.re, p.re, 1.0);
+    let complex = complex_fresnel_mueller(s, p);
+    for i in 0..4 {
+        for j in 0..4 {
+            assert!((real[(i, j)] - complex[(i, j)]).abs() < 1e-12);
+        }
+    }
+    // A metal retards p against s, turning linear into elliptical polarization without changing the
+    // polarized fraction of fully polarized light.
+    let (s, p) = fresnel_amplitudes(1.0, Complex::new(0.2, 3.0), 0.6);
+    let stokes = complex_fresnel_mueller(s, p) * Stokes::new(1.0, 0.0, 1.0, 0.0);
+    assert!(stokes.w().abs() > 0.1);
+    assert!((degree_of_polarization(stokes) - 1.0).abs() < 1e-12);
+}
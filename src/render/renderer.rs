@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::default::default;
 use std::f64::consts::PI;
 use crate::geo::view::View;
@@ -14,7 +16,7 @@ use image::codecs::hdr::HdrEncoder;
 use itertools::Itertools;
 use crate::render::image::ImageBuilder;
 use crate::geo::ray::Ray;
-use crate::math::vec::{Vec2, Vec3};
+use crate::math::vec::{Vec2, Vec3, Vector};
 use crate::util::itertools2::Itertools2;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng, thread_rng};
@@ -28,12 +30,21 @@ use rayon::iter::ParallelBridge;
 use indicatif::ProgressBar;
 use indicatif::ProgressFinish;
 use indicatif::ProgressStyle;
-use crate::math::scalar::{Der, Scalar};
+use crate::math::scalar::{Der, F32, Scalar};
+use crate::math::interval_scalar::IntervalScalar;
 use crate::tree::kd_tree::{KdEntry, KdTree};
 use crate::render::object::{Manifold, Object, RaycastPoint};
 use crate::util::rayon::IndexedParallelIteratorExt;
 use crate::geo::color::Color;
 use crate::render::dielectric::Dielectric;
+use crate::render::medium::{HenyeyGreenstein, Medium, MediumSegment};
+use crate::render::grid_medium::GridMedium;
+use crate::render::polarization::{complex_fresnel_mueller, degree_of_polarization, fresnel_mueller, normalize_mueller, PolarizationFrame, rgb_mueller, Stokes, unpolarized};
+use crate::render::spectrum::Tint;
+use crate::math::complex::Complex;
+use crate::render::stats::RenderStats;
+use crate::render::thin_film::unpolarized_reflectance;
+use crate::render::newton::{NewtonOutcome, NewtonSolver};
 
 #[derive(Debug)]
 pub struct Light {
@@ -50,36 +61,81 @@ pub struct Scene<S> {
     pub photon_samples: usize,
     pub newton_steps: usize,
     pub newton_epsilon: f64,
+    pub medium: Option<Medium>,
+    pub volumes: Vec<GridMedium>,
+    pub volume_samples: usize,
+    // Photons gathered for the caustic part of in-scattering in homogeneous media.
+    pub volume_photon_samples: usize,
+    pub polarized: bool,
+    pub spectral: bool,
+    pub precision: Precision,
+    // Retraces camera rays with interval arithmetic and flags pixels whose hits depend on rounding.
+    pub verify: bool,
+    pub max_specular_depth: usize,
+    // Specular paths with a throughput below this play Russian roulette.
+    pub specular_roulette: Option<f64>,
+    // Seeds the photon directions, so that renders of nearby scenes share their random numbers.
+    // Samples drawn inside the parallel passes still come from the thread's generator.
+    pub seed: Option<u64>,
 }
 
 pub struct Renderer<S> {
-    photons: KdTree<Photon>,
+    photons: AnyPhotonMaps,
     radiosity: ImageBuilder,
     perf: ImageBuilder,
+    polarization: ImageBuilder,
+    ambiguity: ImageBuilder,
     rng: SmallRng,
+    // Seeds the generators of individual paths, see path_rng.
+    path_seed: u64,
     scene: Scene<S>,
+    stats: RenderStats,
 }
 
 #[derive(Debug)]
-pub struct Photon {
-    origin: Vec3<f64>,
-    dir: ZenithY<f64>,
+pub struct Photon<P = f64> {
+    origin: Vec3<P>,
+    dir: ZenithY<P>,
     light: Color,
     manifold: Vec<Manifold>,
     modes: Vec<SpecularMode>,
     light_index: usize,
+    // Inverse survival probability of the roulette the photon went through.
+    weight: f64,
+}
+
+// Where light that went through a specular chain scatters in a homogeneous medium. Unlike surface
+// photons these are not replayed, so they carry their share of the light's power directly.
+#[derive(Debug)]
+pub struct VolumePhoton<P = f64> {
+    dir: Vec3<P>,
+    power: Color,
+}
+
+// The photon maps with positions and directions stored at precision P.
+pub struct PhotonMaps<P = f64> {
+    photons: KdTree<Photon<P>, P>,
+    volume_photons: KdTree<VolumePhoton<P>, P>,
+}
+
+pub enum AnyPhotonMaps {
+    Double(PhotonMaps<f64>),
+    Single(PhotonMaps<F32>),
 }
 
 pub struct AdjustedPhoton {
     light: Color,
     position: Vec3<Der<2>>,
     normal: Vec3<f64>,
+    weight: f64,
 }
 
 #[derive(Default)]
 pub struct RenderedRay {
     radiosity: Color,
     depth: f64,
+    polarization: f64,
+    ambiguous: bool,
 }
 
 pub struct RenderedPixel {
@@ -94,69 +150,189 @@ pub enum SpecularMode {
     Refract,
 }
 
+// Precision of the ray traversal, the photon maps and the Newton solve gathering caustics from
+// them. SceneBuilder also uses it for the node bounds of mesh BVHs. Triangle vertices and the hit
+// points returned to shading are always kept in f64.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Precision {
+    Double,
+    Single,
+}
+
 #[derive(Debug)]
 pub struct SpecularPath<T> {
     raycast_point: RaycastPoint<T>,
     manifolds: Vec<Manifold>,
     modes: Vec<SpecularMode>,
     attenuation: T,
+    // The part of the attenuation that comes from surviving roulette.
+    roulette_weight: f64,
+    stokes: Option<Stokes>,
+    tint: Color,
+    // Whether the hit, or the side of the surface or total internal reflection there, could have
+    // been decided differently under rounding.
+    ambiguous: bool,
+}
+
+impl<T: Scalar> SpecularPath<T> {
+    pub fn into_const(&self) -> SpecularPath<f64> {
+        SpecularPath {
+            raycast_point: self.raycast_point.into_const(),
+            manifolds: self.manifolds.clone(),
+            modes: self.modes.clone(),
+            attenuation: self.attenuation.into_const(),
+            roulette_weight: self.roulette_weight,
+            stokes: self.stokes,
+            tint: self.tint,
+            ambiguous: self.ambiguous,
+        }
+    }
 }
 
 impl<S: Object> Renderer<S> {
     pub fn new(scene: Scene<S>) -> Self {
+        let mut rng = scene.seed.map_or_else(SmallRng::from_entropy, SmallRng::seed_from_u64);
         Renderer {
-            photons: KdTree::default(),
+            photons: AnyPhotonMaps::Double(PhotonMaps { photons: KdTree::default(), volume_photons: KdTree::default() }),
             radiosity: ImageBuilder::new(scene.size),
             perf: ImageBuilder::new(scene.size),
+            polarization: ImageBuilder::new(scene.size),
+            ambiguity: ImageBuilder::new(scene.size),
+            path_seed: rng.gen(),
+            rng,
             scene,
-            rng: SmallRng::from_entropy(),
+            stats: RenderStats::new(),
         }
     }
     pub fn render(&mut self) {
+        self.trace_photons();
+        let pixels = self.pixels().into_par_iter()
+            .progress_as("raytrace")
+            .map(|(x, y)| self.render_pixel(x, y)).collect::<Vec<_>>();
+        for RenderedPixel { pos: (x, y), rendered_ray: RenderedRay { radiosity, depth, polarization, ambiguous }, time } in pixels {
+            self.radiosity.insert(x, y, radiosity);
+            self.polarization.insert(x, y, Color::broadcast(polarization));
+            if ambiguous {
+                self.stats.ambiguous_pixels.inc();
+            }
+            self.ambiguity.insert(x, y, if ambiguous { Color::new(1.0, 0.0, 0.0) } else { radiosity * 0.2 });
+            self.perf.insert(x, y, Color::new(1.0, 1.0, 1.0) * time.as_secs_f64() * 3000.0);
+        }
+    }
+    pub fn trace_photons(&mut self) {
         let photon_sources = self.scene.lights.iter().enumerate().flat_map(|(index, light)| {
             Sphere::fibonacci_sphere(self.scene.photon_count, &mut self.rng).into_iter().map(move |dir| (index, light, dir))
         }).collect::<Vec<_>>();
-        let photons =
+        self.photons = match self.scene.precision {
+            Precision::Double => AnyPhotonMaps::Double(self.trace_photon_maps(&photon_sources)),
+            Precision::Single => AnyPhotonMaps::Single(self.trace_photon_maps(&photon_sources)),
+        };
+    }
+    fn trace_photon_maps<P: Scalar + Send + Sync>(&self, photon_sources: &[(usize, &Light, ZenithY<f64>)]) -> PhotonMaps<P> {
+        let traced =
             photon_sources.par_iter()
+                .enumerate()
                 .progress_as("photons")
-                .flat_map(|(light_index, light, dir)| {
+                .map(|(index, (light_index, light, dir))| {
                     let ray = Ray::new(light.sphere.orig(), dir.into_normal());
-                    self.raytrace_all_specular(&ray, &[], None).into_iter().flat_map(|path| {
+                    let (paths, segments) = self.raytrace_at_precision(&ray);
+                    let mut rng = self.path_rng(("volume photons", index));
+                    let volume_photons = segments.iter().filter_map(|segment| {
+                        // Light that reaches a medium without a specular bounce is handled by the
+                        // shadow rays of compute_scattered_radiance.
+                        let medium = segment.medium.filter(|_| segment.specular_depth > 0)?;
+                        let (distance, mut weight) = medium.sample_distance(segment.length, &mut rng);
+                        if segment.heterogeneous {
+                            weight *= self.volumes_transmittance(segment.orig, segment.dir, distance, None, &mut rng);
+                        }
+                        let power = light.color.map_mul(segment.tint) * (segment.attenuation * weight / self.scene.photon_count as f64);
+                        Some(KdEntry::new(segment.pos(distance), VolumePhoton { dir: segment.dir.map(P::from), power }))
+                    }).collect::<Vec<_>>();
+                    let photons = paths.into_iter().flat_map(|path| {
                         let pos = path.raycast_point.position;
                         if path.modes.len() == 0 {
                             return None;
                         }
                         Some(KdEntry::new(pos, Photon {
-                            origin: ray.orig(),
-                            dir: *dir,
-                            light: light.color * path.attenuation,
+                            origin: ray.orig().map(P::from),
+                            dir: ZenithY(dir.0.map(P::from)),
+                            light: light.color.map_mul(path.tint) * path.attenuation,
                             manifold: path.manifolds,
                             modes: path.modes,
                             light_index: *light_index,
+                            weight: path.roulette_weight,
                         }))
-                    }).collect::<Vec<_>>()
+                    }).collect::<Vec<_>>();
+                    (photons, volume_photons)
                 }).collect::<Vec<_>>();
+        let (photons, volume_photons): (Vec<_>, Vec<_>) = traced.into_iter().unzip();
+        let photons = photons.into_iter().flatten().collect::<Vec<_>>();
         dbg!(photons.len());
-        self.photons = KdTree::new(photons);
+        PhotonMaps {
+            photons: KdTree::new(photons),
+            volume_photons: KdTree::new(volume_photons.into_iter().flatten().collect()),
+        }
+    }
+    // Renders one image per input of N, holding the derivatives of the pixel values with respect to
+    // the scene parameters marked with that index. The photon map must already be traced. The
+    // caustic and in-scattering terms are held constant; see `optimize` for the former.
+    pub fn render_derivatives<const N: usize>(&self) -> Vec<ImageBuilder> {
+        let pixels = self.pixels().into_par_iter()
+            .progress_as("derivatives")
+            .map(|(x, y)| (x, y, self.raytrace_pixel_derivatives::<N>(self.pixel_viewpoint(x, y))))
+            .collect::<Vec<_>>();
+        let mut images = vec![ImageBuilder::new(self.scene.size); N];
+        for (x, y, derivatives) in pixels {
+            for (image, derivative) in images.iter_mut().zip(derivatives) {
+                image.insert(x, y, derivative);
+            }
+        }
+        images
+    }
+    // Renders only the photon-mapped caustic term.
+    pub fn render_caustics(&self) -> ImageBuilder {
+        let pixels = self.pixels().into_par_iter()
+            .progress_as("caustics")
+            .map(|(x, y)| {
+                let ray = self.scene.view.get_ray(self.pixel_viewpoint(x, y));
+                let radiance = self.raytrace_at_precision(&ray).0.iter().map(|path| {
+                    self.compute_indirect_irrad(&path.raycast_point)
+                        .map_mul(path.raycast_point.material.diffuse)
+                        .map_mul(path.tint)
+                        * path.attenuation
+                }).fold(Color::default(), |x, y| x + y);
+                (x, y, radiance)
+            }).collect::<Vec<_>>();
+        let mut image = ImageBuilder::new(self.scene.size);
+        for (x, y, radiance) in pixels {
+            image.insert(x, y, radiance);
+        }
+        image
+    }
+    fn pixels(&self) -> Vec<(usize, usize)> {
         let mut pixels = vec![];
         for x in 0..self.scene.size.0 {
             for y in 0..self.scene.size.1 {
                 pixels.push((x, y));
             }
         }
-        let pixels = pixels.into_par_iter()
-            .progress_as("raytrace")
-            .map(|(x, y)| self.render_pixel(x, y)).collect::<Vec<_>>();
-        for RenderedPixel { pos: (x, y), rendered_ray: RenderedRay { radiosity, depth }, time } in pixels {
-            self.radiosity.insert(x, y, radiosity);
-            self.perf.insert(x, y, Color::new(1.0, 1.0, 1.0) * time.as_secs_f64() * 3000.0);
-        }
+        pixels
     }
-    pub fn render_pixel(&self, x: usize, y: usize) -> RenderedPixel {
-        let start = Instant::now();
+    fn pixel_viewpoint(&self, x: usize, y: usize) -> Vec2<f64> {
         let sx = (x as f64 - (self.scene.size.0 as f64 - 1.0) / 2.0) / (self.scene.size.0 as f64);
         let sy = -(y as f64 - (self.scene.size.1 as f64 - 1.0) / 2.0) / (self.scene.size.1 as f64);
-        let s = Vec2::from([sx, sy]);
+        Vec2::from([sx, sy])
+    }
+    pub fn radiosity(&self) -> &ImageBuilder {
+        &self.radiosity
+    }
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
+    pub fn render_pixel(&self, x: usize, y: usize) -> RenderedPixel {
+        let start = Instant::now();
+        let s = self.pixel_viewpoint(x, y);
+        let (sx, sy) = (s.x(), s.y());
 
         let rr = if true {
             self.raytrace_pixel(s)
@@ -168,6 +344,8 @@ impl<S: Object> Renderer<S> {
                     Color::new(1.0 - sy * 2.0, 1.0 - sy * 2.0, 1.0 - sy * 2.0) * 0.01
                 },
                 depth: 0.0,
+                polarization: 0.0,
+                ambiguous: false,
             }
         };
 
@@ -181,126 +359,327 @@ impl<S: Object> Renderer<S> {
     pub fn compute_ambient_irrad(&self, p: &RaycastPoint<f64>) -> Color {
         Color::broadcast(0.00)
     }
-    pub fn compute_direct_irrad(&self, p: &RaycastPoint<f64>) -> Color {
-        //let mut lighting = Color::default();
-        let mut lighting = Color::new(1.0, 1.0, 1.0) * 0.0;
+    // Visibility and transmittance are evaluated at the constant part of the point.
+    pub fn compute_direct_irrad<T: Scalar>(&self, p: &RaycastPoint<T>) -> Vec3<T> {
+        let mut lighting = Vec3::<T>::default();
         for light in self.scene.lights.iter() {
-            let disp = light.sphere.orig() - p.position;
+            let disp = light.sphere.orig_param::<T>() - p.position;
             let dis2 = disp.dot(disp);
             let dis = dis2.sqrt();
             let dir = disp / dis;
             let mut dot = dir.dot(p.inter_normal);
-            if dot < 0.0 {
+            if dot < T::from(0.0) {
                 continue;
             }
-            let ray = Ray::new_bounce(p.position, dir);
-            if let Some(occlude) = self.scene.scene_object.raycast(&ray, None) {
-                if occlude.time < dis {
-                    continue;
-                }
+            let (position, dir, dis) = (p.position.map(|x| x.into_const()), dir.map(|x| x.into_const()), dis.into_const());
+            let ray = Ray::new_bounce(position, p.error, p.geo_normal.map(|x| x.into_const()), dir);
+            if self.scene.scene_object.occluded(&ray, dis, false) {
+                continue;
             }
-            lighting += light.color * dot / (4.0 * PI * dis2);
+            let scale = dot * T::from(self.scene_transmittance(position, dir, dis)) / (T::from(4.0 * PI) * dis2);
+            lighting += light.color.cast::<T>() * scale;
         }
         lighting
     }
+    // Transmittance along a segment outside of any object, through the homogeneous and grid media.
+    pub fn scene_transmittance(&self, orig: Vec3<f64>, dir: Vec3<f64>, length: f64) -> f64 {
+        self.scene.medium.map_or(1.0, |medium| medium.transmittance(length))
+            * self.volumes_transmittance(orig, dir, length, None, &mut thread_rng())
+    }
+    fn volumes_transmittance(&self, orig: Vec3<f64>, dir: Vec3<f64>, length: f64, skip: Option<usize>, rng: &mut impl Rng) -> f64 {
+        self.scene.volumes.iter().enumerate()
+            .filter(|(index, _)| Some(*index) != skip)
+            .map(|(_, volume)| volume.transmittance(orig, dir, length, rng))
+            .product()
+    }
+    // Radiance scattered towards the segment origin by a medium particle at position.
+    fn compute_scattered_radiance(&self, position: Vec3<f64>, toward: Vec3<f64>, phase: &HenyeyGreenstein) -> Color {
+        let mut total = Color::default();
+        for light in self.scene.lights.iter() {
+            let disp = light.sphere.orig() - position;
+            let dis2 = disp.dot(disp);
+            let dis = dis2.sqrt();
+            let dir = disp / dis;
+            let ray = Ray::new(position, dir);
+            if self.scene.scene_object.occluded(&ray, dis, false) {
+                continue;
+            }
+            total += light.color * phase.eval(dir.dot(toward)) * self.scene_transmittance(position, dir, dis) / (4.0 * PI * dis2);
+        }
+        total
+    }
+    // Radiance scattered towards the segment origin by the volume photons around position, which
+    // carry the light that reached the medium through specular chains, e.g. a beam focused by a lens.
+    pub fn compute_volume_photon_radiance(&self, position: Vec3<f64>, toward: Vec3<f64>, medium: &Medium) -> Color {
+        match &self.photons {
+            AnyPhotonMaps::Double(maps) => self.gather_volume_photons(&maps.volume_photons, position, toward, medium),
+            AnyPhotonMaps::Single(maps) => self.gather_volume_photons(&maps.volume_photons, position, toward, medium),
+        }
+    }
+    fn gather_volume_photons<P: Scalar + Send>(&self, volume_photons: &KdTree<VolumePhoton<P>, P>, position: Vec3<f64>, toward: Vec3<f64>, medium: &Medium) -> Color {
+        let photons = volume_photons.nearest(&position, self.scene.volume_photon_samples);
+        let radius = match photons.iter().map(|photon| photon.distance).max_by(f64::total_cmp) {
+            Some(radius) if radius > 0.0 => radius,
+            _ => return Color::default(),
+        };
+        let mut total = Color::default();
+        for photon in photons.iter() {
+            let photon = photon.entry.value();
+            total += photon.power * medium.phase.eval(-photon.dir.map(|x| x.into_const()).dot(toward));
+        }
+        // The photons were deposited with the scattering coefficient, which the caller applies again.
+        total / (4.0 / 3.0 * PI * radius * radius * radius * medium.scattering)
+    }
+    pub fn compute_inscatter_radiance(&self, segment: &MediumSegment) -> Color {
+        let mut rng = thread_rng();
+        let mut total = Color::default();
+        for _ in 0..self.scene.volume_samples {
+            if let Some(medium) = segment.medium {
+                let (distance, mut weight) = medium.sample_distance(segment.length, &mut rng);
+                if segment.heterogeneous {
+                    weight *= self.volumes_transmittance(segment.orig, segment.dir, distance, None, &mut rng);
+                }
+                let position = segment.pos(distance);
+                // Inside an object the object itself blocks every shadow ray, so light only arrives
+                // through its surface as volume photons.
+                if segment.heterogeneous {
+                    total += self.compute_scattered_radiance(position, segment.dir, &medium.phase) * weight;
+                }
+                total += self.compute_volume_photon_radiance(position, segment.dir, &medium) * weight;
+            }
+            if segment.heterogeneous {
+                for (index, volume) in self.scene.volumes.iter().enumerate() {
+                    if let Some((distance, mut weight)) = volume.sample_scatter(segment.orig, segment.dir, segment.length, &mut rng) {
+                        weight *= self.scene.medium.map_or(1.0, |medium| medium.transmittance(distance));
+                        weight *= self.volumes_transmittance(segment.orig, segment.dir, distance, Some(index), &mut rng);
+                        total += self.compute_scattered_radiance(segment.pos(distance), segment.dir, &volume.phase) * weight;
+                    }
+                }
+            }
+        }
+        total.map_mul(segment.tint) * segment.attenuation / (self.scene.volume_samples.max(1) as f64)
+    }
     pub fn compute_indirect_irrad(&self, p: &RaycastPoint<f64>) -> Color {
+        match &self.photons {
+            AnyPhotonMaps::Double(maps) => self.gather_caustic(&maps.photons, p),
+            AnyPhotonMaps::Single(maps) => self.gather_caustic(&maps.photons, p),
+        }
+    }
+    // Replays the photons around p at their own precision, so that the Newton solve of a Single
+    // precision scene runs on DerF32.
+    fn gather_caustic<P: Scalar + Send>(&self, photon_map: &KdTree<Photon<P>, P>, p: &RaycastPoint<f64>) -> Color {
         let mut total = Color::default();
         let mut photons = HashMap::new();
-        for photon in self.photons.nearest(&p.position, self.scene.photon_samples) {
-            let photon = photon.entry;
-            let mut dir = photon.value().dir;
-            let mut filter_manifolds: Vec<_> = photon.value().manifold.iter().cloned().map(Some).collect();
+        let solver = NewtonSolver::new(self.scene.newton_steps, self.scene.newton_epsilon);
+        for photon in photon_map.nearest(&p.position, self.scene.photon_samples) {
+            let photon = photon.entry.value();
+            let origin = photon.origin.map(|x| x.into_const());
+            let mut filter_manifolds: Vec<_> = photon.manifold.iter().cloned().map(Some).collect();
             filter_manifolds.push(Some(p.manifold));
-            let mut seps = vec![];
-            for _ in 0..self.scene.newton_steps {
-                let ray = Ray::new(photon.value().origin.cast(), dir.as_input().into_normal());
-                let hit = self.raytrace_all_specular::<Der<2>>(&ray, &filter_manifolds, Some(&photon.value().modes));
+            let (dir, outcome) = solver.solve(photon.dir.0.map(|x| x.into_const()), |dir| {
+                let ray = Ray::new(origin.cast(), ZenithY(dir).as_input_at::<P>().into_normal());
+                let hit = self.raytrace_all_specular::<Der<2, P>>(&ray, &filter_manifolds, Some(&photon.modes));
                 assert!(hit.len() < 2);
-                let hit = match hit.into_iter().next() {
-                    None => break,
-                    Some(hit) => hit,
-                };
-                let sep = hit.raycast_point.manifold_point - p.manifold_point.cast();
-                seps.push(sep);
-                let sep_value: Vec2<f64> = sep.cast();
-                let sep_jacobian: Mat2<f64> = sep.jacobian();
-                let new_dir = ZenithY(dir.0 - sep_jacobian.inverse() * sep_value);
-                dir = new_dir;
-            }
-            // let dy = 0.000001;
-            // let a = self.raytrace_all_specular::<Der<2>>(
-            //     &Ray::new(photon.value().origin.cast(), ZenithY(Vec2::new(dir.0.x(), dir.0.y())).as_input().into_normal()),
-            //     &filter_manifolds, Some(&photon.value().modes)).into_iter().next();
-            // let b = self.raytrace_all_specular::<Der<2>>(
-            //     &Ray::new(photon.value().origin.cast(), ZenithY(Vec2::new(dir.0.x(), dir.0.y() + dy)).as_input().into_normal()),
-            //     &filter_manifolds, Some(&photon.value().modes)).into_iter().next();
-            // if let (Some(a), Some(b)) = (a, b) {
-            //     let dp = b.raycast_point.manifold_point - a.raycast_point.manifold_point;
-            //     let edp = a.raycast_point.manifold_point.der(1) * dy;
-            //     println!("{:?} {:?} {:?}", photon.value().modes.len(), Vec2::new(dp.x().v, dp.y().v), edp);
-            // }
-
-            // if photon.value().modes.len() > 0 {
-            //     println!("{:?}", seps);
-            // }
-            let ray = Ray::new(photon.value().origin.cast(), dir.as_input().into_normal());
-            let real_photon = self.raytrace_all_specular::<Der<2>>(&ray, &[], Some(&photon.value().modes));
-            assert!(real_photon.len() < 2);
-            if let Some(real_photon) = real_photon.into_iter().next() {
-                if real_photon.raycast_point.position.cast().distance(p.position) < self.scene.newton_epsilon {
-                    photons.insert(photon.value().light_index, AdjustedPhoton {
-                        light: photon.value().light,
-                        position: real_photon.raycast_point.position,
-                        normal: real_photon.raycast_point.inter_normal.cast(),
-                    });
+                let sep = (hit.into_iter().next()?.raycast_point.manifold_point - p.manifold_point.cast()).map(Der::to_f64);
+                Some((sep.cast(), sep.jacobian()))
+            });
+            let outcome = if outcome == NewtonOutcome::Converged {
+                // The solve only considered the photon's own surfaces, so check the unfiltered path.
+                let ray = Ray::new(origin.cast(), ZenithY(dir).as_input_at::<P>().into_normal());
+                let real_photon = self.raytrace_all_specular::<Der<2, P>>(&ray, &[], Some(&photon.modes));
+                assert!(real_photon.len() < 2);
+                match real_photon.into_iter().next() {
+                    None => NewtonOutcome::TopologyChanged,
+                    Some(real_photon) => {
+                        if real_photon.manifolds != photon.manifold || real_photon.raycast_point.manifold != p.manifold {
+                            NewtonOutcome::Occluded
+                        } else {
+                            photons.entry(photon.light_index).or_insert(vec![]).push(AdjustedPhoton {
+                                light: self.scene.lights[photon.light_index].color.map_mul(real_photon.tint) * real_photon.attenuation.into_const(),
+                                position: real_photon.raycast_point.position.map(Der::to_f64),
+                                normal: real_photon.raycast_point.inter_normal.map(|x| x.into_const()),
+                                weight: photon.weight,
+                            });
+                            NewtonOutcome::Converged
+                        }
+                    }
                 }
-            }
+            } else {
+                outcome
+            };
+            self.stats.record_newton(outcome);
         }
-        for photon in photons.values() {
-            let area_vector = photon.position.der(0).cross(photon.position.der(1));
-            let area = 4.0 * PI * area_vector.length();
-            total += photon.light / area;
+        // Every converged photon evaluates its whole path exactly, so the estimate for a light is the
+        // mean over them. Photons that survived roulette stand in for the ones it killed, so the
+        // mean is weighted by the inverse survival probability recorded with each photon.
+        for photons in photons.values() {
+            let mut irrad = Color::default();
+            let mut weight = 0.0;
+            for photon in photons {
+                let area_vector = photon.position.der(0).cross(photon.position.der(1));
+                let area = 4.0 * PI * area_vector.length();
+                irrad += photon.light / area * photon.weight;
+                weight += photon.weight;
+            }
+            total += irrad / weight;
         }
         return total;
     }
     pub fn raytrace_pixel(&self, s: Vec2<f64>) -> RenderedRay {
         let ray = self.scene.view.get_ray(s);
         let mut total = Color::default();
-        for path in self.raytrace_all_specular(&ray, &[], None) {
+        let mut stokes = Stokes::default();
+        let (paths, segments) = self.raytrace_at_precision(&ray);
+        for path in paths {
             let irrad =
                 self.compute_indirect_irrad(&path.raycast_point)
                     + self.compute_direct_irrad(&path.raycast_point)
                     + self.compute_ambient_irrad(&path.raycast_point);
 
-            total += irrad
+            let radiance = irrad
                 .map_mul(path.raycast_point.material.diffuse)
+                .map_mul(path.tint)
                 * path.attenuation;
+            total += radiance;
+            // The Stokes vector is already weighted by the Fresnel part of the attenuation.
+            let scale = luminance(radiance) / path.stokes.map_or(1.0, |s| s.x());
+            if scale.is_finite() {
+                stokes += path.stokes.unwrap_or(unpolarized(1.0)) * scale;
+            }
+        }
+        for segment in segments.iter() {
+            let radiance = self.compute_inscatter_radiance(segment);
+            total += radiance;
+            stokes += unpolarized(luminance(radiance));
         }
         RenderedRay {
             radiosity: total,
             depth: 0.0,
+            polarization: degree_of_polarization(stokes),
+            ambiguous: self.scene.verify && self.verify_pixel(s),
+        }
+    }
+    // Whether any hit or specular branch along the paths of the pixel could have gone the other way
+    // under rounding, e.g. a ray grazing a triangle edge or two nearly coincident hits. BVH traversal
+    // is conservative, so its comparisons are not counted.
+    pub fn verify_pixel(&self, s: Vec2<f64>) -> bool {
+        let ray = self.scene.view.get_ray::<IntervalScalar>(s.cast());
+        self.raytrace_all_specular(&ray, &[], None).iter().any(|path| path.ambiguous)
+    }
+    pub fn raytrace_pixel_derivatives<const N: usize>(&self, s: Vec2<f64>) -> Vector<N, Color> {
+        let ray = self.scene.view.get_ray::<Der<N>>(s.cast());
+        let mut total = Vec3::<Der<N>>::default();
+        for path in self.raytrace_all_specular(&ray, &[], None) {
+            let point = path.raycast_point.into_const();
+            let irrad = self.compute_direct_irrad(&path.raycast_point)
+                + (self.compute_indirect_irrad(&point) + self.compute_ambient_irrad(&point)).cast();
+            total += irrad.zip(point.material.diffuse.map_mul(path.tint)).map(|(x, y)| x * Der::from(y))
+                * path.attenuation;
+        }
+        let mut result = Vector::<N, Color>::default();
+        for i in 0..N {
+            result[i] = total.der(i);
+        }
+        result
+    }
+    // A generator for the random decisions along one path, seeded from the renderer's seed and a key
+    // identifying the path, so that a seeded render does not depend on how rayon schedules paths.
+    fn path_rng(&self, key: impl Hash) -> SmallRng {
+        let mut hasher = DefaultHasher::new();
+        (self.path_seed, key).hash(&mut hasher);
+        SmallRng::seed_from_u64(hasher.finish())
+    }
+    // Traces at the scene's precision, returning the paths in f64 either way.
+    fn raytrace_at_precision(&self, ray: &Ray<f64>) -> (Vec<SpecularPath<f64>>, Vec<MediumSegment>) {
+        match self.scene.precision {
+            Precision::Double => self.raytrace_all_specular_with_media(ray, &[], None),
+            Precision::Single => {
+                let ray = Ray::new(ray.orig().cast::<F32>(), ray.dir().cast());
+                let (paths, segments) = self.raytrace_all_specular_with_media(&ray, &[], None);
+                (paths.iter().map(SpecularPath::into_const).collect(), segments)
+            }
         }
     }
     pub fn raytrace_all_specular<T: Scalar>(&self, ray: &Ray<T>, manifolds: &[Option<Manifold>], modes: Option<&[SpecularMode]>) -> Vec<SpecularPath<T>> {
+        self.raytrace_all_specular_with_media(ray, manifolds, modes).0
+    }
+    pub fn raytrace_all_specular_with_media<T: Scalar>(&self, ray: &Ray<T>, manifolds: &[Option<Manifold>], modes: Option<&[SpecularMode]>) -> (Vec<SpecularPath<T>>, Vec<MediumSegment>) {
         let mut output = vec![];
+        let mut segments = vec![];
+        let polarization = self.scene.polarized.then(|| PolarizationFrame::new(ray.dir().map(|x| x.into_const())));
         self.raytrace_all_specular_rec(
             ray,
             T::from(1.0),
+            1.0,
+            polarization,
+            Tint::white(self.scene.spectral),
             manifolds,
             modes,
+            &mut self.path_rng(ray.orig().into_iter().chain(ray.dir()).map(|x| x.into_const().to_bits()).collect::<Vec<_>>()),
             &mut vec![],
             &mut vec![],
+            &mut segments,
             &mut output);
-        output
+        (output, segments)
+    }
+    // Records the segment with the throughput reaching its origin and returns its transmittance.
+    fn add_medium_segment<T: Scalar>(&self, ray: &Ray<T>, length: T, medium: Option<Medium>, heterogeneous: bool, specular_depth: usize, throughput: T, tint: Tint, rng: &mut SmallRng, output_segments: &mut Vec<MediumSegment>) -> T {
+        let heterogeneous = heterogeneous && !self.scene.volumes.is_empty();
+        if medium.is_none() && !heterogeneous {
+            return T::from(1.0);
+        }
+        let orig = ray.orig().map(|x| x.into_const());
+        let dir = ray.dir().map(|x| x.into_const());
+        let scale = dir.length();
+        let dir = dir / scale;
+        let length = length.into_const() * scale;
+        output_segments.push(MediumSegment {
+            orig,
+            dir,
+            length,
+            medium,
+            heterogeneous,
+            specular_depth,
+            attenuation: throughput.into_const(),
+            tint: tint.to_color(),
+        });
+        let mut transmittance = medium.map_or(1.0, |medium| medium.transmittance(length));
+        if heterogeneous {
+            transmittance *= self.volumes_transmittance(orig, dir, length, None, rng);
+        }
+        T::from(transmittance)
+    }
+    // Survivors are reweighted by the inverse survival probability, keeping the estimate unbiased.
+    fn specular_roulette(&self, throughput: f64, rng: &mut SmallRng) -> Option<f64> {
+        let threshold = match self.scene.specular_roulette {
+            None => return Some(1.0),
+            Some(threshold) => threshold,
+        };
+        if throughput >= threshold {
+            return Some(1.0);
+        }
+        let survival = throughput / threshold;
+        if rng.gen::<f64>() < survival {
+            self.stats.specular_roulette_survived.inc();
+            Some(1.0 / survival)
+        } else {
+            self.stats.specular_roulette_killed.inc();
+            None
+        }
     }
     pub fn raytrace_all_specular_rec<T: Scalar>(
         &self,
         ray: &Ray<T>,
         attenuation: T,
+        roulette_weight: f64,
+        polarization: Option<PolarizationFrame>,
+        tint: Tint,
         filter_manifolds: &[Option<Manifold>],
         filter_modes: Option<&[SpecularMode]>,
+        rng: &mut SmallRng,
         output_manifolds: &mut Vec<Manifold>,
         output_modes: &mut Vec<SpecularMode>,
+        output_segments: &mut Vec<MediumSegment>,
         output: &mut Vec<SpecularPath<T>>) {
         fn slice_pop<T: Copy>(x: &[T]) -> (Option<T>, &[T]) {
             if x.len() >= 1 {
@@ -309,71 +688,186 @@ impl<S: Object> Renderer<S> {
                 (None, &[])
             }
         }
-        if output_manifolds.len() >= 4 {
+        if output_manifolds.len() >= self.scene.max_specular_depth {
+            self.stats.specular_depth_truncated.inc();
             return;
         }
+        // The Fresnel factors for unpolarized light stay in the attenuation, so they keep their
+        // derivatives. The Mueller matrices are normalized to match, so the intensity of the frame
+        // only corrects for the polarization of the light arriving at each surface.
+        let throughput = |attenuation: T| polarization.map_or(attenuation, |p| attenuation * T::from(p.intensity()));
+        // Paths replayed along given modes must stay deterministic, so only free paths are culled.
+        let (attenuation, roulette_weight) = if filter_modes.is_none() && !output_manifolds.is_empty() {
+            match self.specular_roulette(throughput(attenuation).into_const() * luminance(tint.to_color()), rng) {
+                None => return,
+                Some(weight) => (attenuation * T::from(weight), roulette_weight * weight),
+            }
+        } else {
+            (attenuation, roulette_weight)
+        };
         let (filter_manifold, filter_manifolds) = slice_pop(filter_manifolds);
         let first = self.scene.scene_object.raycast(ray, filter_manifold.flatten());
         let first = match first {
-            None => return,
+            None => {
+                self.add_medium_segment(ray, T::from(f64::INFINITY), self.scene.medium, true, output_manifolds.len(), throughput(attenuation), tint, rng, output_segments);
+                return;
+            }
             Some(first) => first,
         };
+        // A ray leaving through the surface travelled inside the object.
+        let side = ray.dir().dot(first.geo_normal);
+        let attenuation = attenuation * if side > T::from(0.0) {
+            self.add_medium_segment(ray, first.time, first.material.medium, false, output_manifolds.len(), throughput(attenuation), tint, rng, output_segments)
+        } else {
+            self.add_medium_segment(ray, first.time, self.scene.medium, true, output_manifolds.len(), throughput(attenuation), tint, rng, output_segments)
+        };
+        let material = first.material;
+        let dielectric = material.dielectric.map(|(n1, n2)| Dielectric::new(ray.dir(), first.geo_normal, n1.get(), n2.get()));
         if filter_modes.map_or(true, |filter_modes| filter_modes.is_empty()) {
             output.push(SpecularPath {
                 raycast_point: first.clone(),
                 manifolds: output_manifolds.clone(),
                 modes: output_modes.clone(),
-                attenuation,
+                attenuation: throughput(attenuation),
+                roulette_weight,
+                stokes: polarization.map(|p| p.stokes()),
+                tint: tint.to_color(),
+                ambiguous: first.ambiguous || side.overlaps(T::from(0.0)) || dielectric.as_ref().map_or(false, |x| x.ambiguous),
             });
         }
-        if let Some((n1, n2)) = first.material.dielectric {
-            let dielectric = Dielectric::new(ray.dir(), first.geo_normal, T::from(n1), T::from(n2));
-            let (filter_mode, filter_modes) = match filter_modes {
-                None => (None, None),
-                Some(xs) => {
-                    if xs.len() == 0 {
-                        return;
-                    } else {
-                        (xs.first().cloned(), Some(&xs[1..]))
-                    }
-                }
-            };
-            {
-                if true {
-                    if filter_mode.map_or(true, |x| x == SpecularMode::Reflect) {
-                        output_manifolds.push(first.manifold);
-                        output_modes.push(SpecularMode::Reflect);
-                        let reflect = Ray::new_bounce(first.position, dielectric.reflect);
-                        self.raytrace_all_specular_rec(
-                            &reflect,
-                            attenuation * dielectric.reflectance,
-                            filter_manifolds,
-                            filter_modes,
-                            output_manifolds,
-                            output_modes,
-                            output);
-                        output_manifolds.pop();
-                        output_modes.pop();
-                    }
+        if material.dielectric.is_none() && material.conductor.is_none() {
+            return;
+        }
+        let (filter_mode, filter_modes) = match filter_modes {
+            None => (None, None),
+            Some(xs) => {
+                if xs.len() == 0 {
+                    return;
+                } else {
+                    (xs.first().cloned(), Some(&xs[1..]))
                 }
             }
+        };
+        let dir = ray.dir().map(|x| x.into_const());
+        let normal = first.geo_normal.map(|x| x.into_const());
+        if let Some(dielectric) = dielectric {
+            // A coated surface takes its Fresnel factors from the film, per wavelength. The attenuation
+            // keeps the factor of the bare surface with its derivatives, and the tint scales it to the
+            // film's.
+            let film = material.thin_film.filter(|_| dielectric.refract.is_some()).map(|film| {
+                let n_i = dielectric.n_i.into_const();
+                let n_t = Complex::from(dielectric.n_t.into_const());
+                let cos_theta_i = dielectric.cos_theta_i.into_const();
+                move |wavelength: f64| film.amplitudes(n_i, n_t, cos_theta_i, wavelength)
+            });
+            let bare = dielectric.reflectance.into_const();
+            if filter_mode.map_or(true, |x| x == SpecularMode::Reflect) {
+                output_manifolds.push(first.manifold);
+                output_modes.push(SpecularMode::Reflect);
+                let reflect = Ray::new_bounce(first.position, first.error, first.geo_normal, dielectric.reflect);
+                let tint = match film {
+                    Some(film) => tint.scale(|w| unpolarized_reflectance(film(w)) / bare),
+                    None => tint,
+                };
+                let polarization = polarization.map(|p| {
+                    let mueller = match film {
+                        Some(film) => rgb_mueller(|w| {
+                            let (s, p) = film(w);
+                            complex_fresnel_mueller(s, p)
+                        }),
+                        None => dielectric.reflect_mueller(),
+                    };
+                    p.interact(dir, normal, normalize_mueller(mueller))
+                });
+                let attenuation = attenuation * dielectric.reflectance;
+                self.raytrace_all_specular_rec(
+                    &reflect,
+                    attenuation,
+                    roulette_weight,
+                    polarization,
+                    tint,
+                    filter_manifolds,
+                    filter_modes,
+                    rng,
+                    output_manifolds,
+                    output_modes,
+                    output_segments,
+                    output);
+                output_manifolds.pop();
+                output_modes.pop();
+            }
             if let Some(refract) = dielectric.refract {
                 if filter_mode.map_or(true, |x| x == SpecularMode::Refract) {
                     output_manifolds.push(first.manifold);
                     output_modes.push(SpecularMode::Refract);
-                    let reflect = Ray::new_bounce(first.position, refract);
+                    let reflect = Ray::new_bounce(first.position, first.error, first.geo_normal, refract);
+                    let tint = match film {
+                        Some(film) => tint.scale(|w| (1.0 - unpolarized_reflectance(film(w))) / (1.0 - bare)),
+                        None => tint,
+                    };
+                    let polarization = polarization.map(|p| {
+                        // The film absorbs nothing, so each of s and p transmits what it does not
+                        // reflect. The phase difference it adds between them is ignored.
+                        let mueller = match film {
+                            Some(film) => rgb_mueller(|w| {
+                                let (s, p) = film(w);
+                                fresnel_mueller((1.0 - s.norm_sqr()).sqrt(), (1.0 - p.norm_sqr()).sqrt(), 1.0)
+                            }),
+                            None => dielectric.refract_mueller(),
+                        };
+                        p.interact(dir, normal, normalize_mueller(mueller))
+                    });
+                    let attenuation = attenuation * (T::from(1.0) - dielectric.reflectance);
                     self.raytrace_all_specular_rec(
                         &reflect,
-                        attenuation * (T::from(1.0) - dielectric.reflectance),
+                        attenuation,
+                        roulette_weight,
+                        polarization,
+                        tint,
                         filter_manifolds,
                         filter_modes,
+                        rng,
                         output_manifolds,
                         output_modes,
+                        output_segments,
                         output);
                     output_manifolds.pop();
                     output_modes.pop();
                 }
             }
+        } else if let Some(conductor) = material.conductor {
+            if filter_mode.map_or(true, |x| x == SpecularMode::Reflect) {
+                output_manifolds.push(first.manifold);
+                output_modes.push(SpecularMode::Reflect);
+                let incident = ray.dir().normalize();
+                let cos_theta_i = incident.dot(first.geo_normal);
+                let reflect = Ray::new_bounce(first.position, first.error, first.geo_normal, incident - first.geo_normal * (cos_theta_i * T::from(2.0)));
+                let cos_theta_i = cos_theta_i.into_const().abs();
+                let amplitudes = |w| conductor.amplitudes(1.0, material.thin_film.as_ref(), cos_theta_i, w);
+                let tint = tint.scale(|w| unpolarized_reflectance(amplitudes(w)));
+                let polarization = polarization.map(|p| {
+                    let mueller = rgb_mueller(|w| {
+                        let (s, p) = amplitudes(w);
+                        complex_fresnel_mueller(s, p)
+                    });
+                    p.interact(dir, normal, normalize_mueller(mueller))
+                });
+                self.raytrace_all_specular_rec(
+                    &reflect,
+                    attenuation,
+                    roulette_weight,
+                    polarization,
+                    tint,
+                    filter_manifolds,
+                    filter_modes,
+                    rng,
+                    output_manifolds,
+                    output_modes,
+                    output_segments,
+                    output);
+                output_manifolds.pop();
+                output_modes.pop();
+            }
         }
     }
     pub fn images(&self) -> HashMap<String, Vec<u8>> {
@@ -381,6 +875,268 @@ impl<S: Object> Renderer<S> {
         result.insert("image.hdr".to_string(), self.radiosity.to_hdr());
         result.insert("image.pq.hdr".to_string(), self.radiosity.smpte2048_encode().to_hdr());
         result.insert("perf.hdr".to_string(), self.perf.to_hdr());
+        if self.scene.polarized {
+            result.insert("polarization.hdr".to_string(), self.polarization.to_hdr());
+        }
+        if self.scene.verify {
+            result.insert("ambiguity.hdr".to_string(), self.ambiguity.to_hdr());
+        }
         result
     }
 }
+
+fn luminance(color: Color) -> f64 {
+    (color.x() + color.y() + color.z()) / 3.0
+}
+
+#[test]
+fn test_caustic_roulette_unbiased() {
+    use crate::SceneBuilder;
+    let builder = SceneBuilder::new(0);
+    let irradiance = |specular_roulette: Option<f64>| {
+        let mut renderer = Renderer::new(Scene {
+            lights: builder.lights().into_iter().take(1).collect(),
+            photon_count: 20000,
+            specular_roulette,
+            seed: Some(3),
+            ..builder.scene()
+        });
+        renderer.trace_photons();
+        // Points on the floor where the light refracted through the sphere lands.
+        let points: Vec<_> = (-2..=2).map(|i| {
+            let ray = Ray::new(Vec3::new(-1.5 + 0.1 * i as f64, 0.0, -1.5 - 0.05 * i as f64), Vec3::new(0.0, -1.0, 0.0));
+            luminance(renderer.compute_indirect_irrad(&renderer.scene.scene_object.raycast(&ray, None).unwrap()))
+        }).collect();
+        (points, renderer.stats.specular_roulette_killed.total())
+    };
+    let (expected, killed) = irradiance(None);
+    assert_eq!(killed, 0);
+    let (actual, killed) = irradiance(Some(2.0));
+    assert!(killed > 0);
+    for (expected, actual) in expected.into_iter().zip(actual) {
+        assert!(expected > 0.0);
+        assert!((actual - expected).abs() < expected * 0.01, "{} {}", actual, expected);
+    }
+}
+
+#[test]
+fn test_volume_caustic() {
+    use crate::SceneBuilder;
+    use crate::render::any_object::AnyObject;
+    use crate::render::material::Material;
+    use crate::render::scene_object::SceneObject;
+    use crate::render::sphere_object::SphereObject;
+    use crate::render::transform_object::TransformObject;
+    use crate::geo::transform::Transform;
+    let builder = SceneBuilder::new(0);
+    let fog = Medium::new(0.0, 2.0, 0.0);
+    let center = Vec3::new(0.0, -0.2, 0.0);
+    let glass = SphereObject::new(Sphere::new(center, 0.2), Material { medium: Some(fog), ..builder.material() });
+    let mut renderer = Renderer::new(Scene {
+        lights: builder.lights().into_iter().take(1).collect(),
+        scene_object: SceneObject::new(vec![TransformObject::new(Transform::default(), AnyObject::Sphere(glass)), builder.plane()]),
+        photon_count: 100000,
+        medium: Some(fog),
+        seed: Some(5),
+        ..builder.scene()
+    });
+    renderer.trace_photons();
+    let light = renderer.scene.lights[0].sphere.orig();
+    let axis = (center - light).normalize();
+    let side = axis.cross(Vec3::new(0.0, 1.0, 0.0)).normalize();
+    let radiance = |position: Vec3<f64>| {
+        let toward = Vec3::new(0.0, 1.0, 0.0);
+        let caustic = luminance(renderer.compute_volume_photon_radiance(position, toward, &fog));
+        let direct = luminance(renderer.compute_scattered_radiance(position, toward, &fog.phase));
+        (caustic, direct)
+    };
+    // Behind the sphere the beam converges far above the light that passes it by, although the
+    // shadow rays towards the light are blocked.
+    let (focus, focus_direct) = radiance(center + axis * 0.4);
+    let (_, beside) = radiance(center + axis * 0.4 + side * 0.4);
+    assert_eq!(focus_direct, 0.0);
+    assert!(focus > beside * 5.0, "{} {}", focus, beside);
+    // Light only reaches the medium inside the sphere through its surface.
+    let (inside, inside_direct) = radiance(center);
+    assert_eq!(inside_direct, 0.0);
+    assert!(inside > 0.0);
+}
+
+#[test]
+fn test_hyper_der_trace() {
+    use crate::SceneBuilder;
+    use crate::math::scalar::HyperDer;
+    // The hit position and the attenuation along the way.
+    fn values<T: Scalar>(path: &SpecularPath<T>) -> [T; 4] {
+        let position = path.raycast_point.position;
+        [position.x(), position.y(), position.z(), path.attenuation]
+    }
+    let close = |x: f64, y: f64, tolerance: f64| (x - y).abs() <= tolerance * (1.0 + y.abs());
+    let renderer = Renderer::new(SceneBuilder::new(0).scene());
+    // Photons from above the glass sphere, refracted through it onto the floor.
+    let orig = Vec3::new(0.0, 0.5, 0.0);
+    let dir = Vec2::new(0.3, -0.985);
+    let trace = |dir: Vec2<f64>| {
+        let ray = Ray::new(orig.cast(), ZenithY(dir).as_input().into_normal());
+        renderer.raytrace_all_specular::<Der<2>>(&ray, &[], None)
+    };
+    let ray = Ray::new(orig.cast(), ZenithY(dir).as_hyper_input().into_normal());
+    let hyper = renderer.raytrace_all_specular::<HyperDer<2>>(&ray, &[], None);
+    let first = trace(dir);
+    assert_eq!(hyper.len(), first.len());
+    assert!(hyper.iter().any(|path| path.modes.len() == 2));
+    // Second derivatives by central differences of the first derivatives.
+    let h = 1e-5;
+    let shifted: Vec<_> = (0..2).map(|i| {
+        let mut step = Vec2::broadcast(0.0);
+        step[i] = h;
+        (trace(dir + step), trace(dir - step))
+    }).collect();
+    for (p, path) in hyper.iter().enumerate() {
+        assert_eq!(path.modes, first[p].modes);
+        for (k, (hyper, first)) in values(path).into_iter().zip(values(&first[p])).enumerate() {
+            assert!(close(hyper.v, first.v, 1e-12), "{:?} {:?}", hyper, first);
+            for i in 0..2 {
+                assert!(close(hyper.d[i], first.d[i], 1e-12), "{:?} {:?}", hyper, first);
+                let (plus, minus) = (values(&shifted[i].0[p])[k], values(&shifted[i].1[p])[k]);
+                for j in 0..2 {
+                    let central = (plus.d[j] - minus.d[j]) / (2.0 * h);
+                    assert!(close(hyper.h[(i, j)], central, 1e-4), "{:?} {}", hyper, central);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_single_precision_caustic() {
+    use crate::SceneBuilder;
+    let builder = SceneBuilder::new(0);
+    let irradiance = |precision: Precision| {
+        let mut renderer = Renderer::new(Scene {
+            lights: builder.lights().into_iter().take(1).collect(),
+            photon_count: 20000,
+            precision,
+            ..builder.scene()
+        });
+        renderer.trace_photons();
+        assert_eq!(matches!(renderer.photons, AnyPhotonMaps::Single(_)), precision == Precision::Single);
+        (-2..=2).map(|i| {
+            let ray = Ray::new(Vec3::new(-1.5 + 0.1 * i as f64, 0.0, -1.5 - 0.05 * i as f64), Vec3::new(0.0, -1.0, 0.0));
+            luminance(renderer.compute_indirect_irrad(&renderer.scene.scene_object.raycast(&ray, None).unwrap()))
+        }).collect::<Vec<_>>()
+    };
+    let expected = irradiance(Precision::Double);
+    let actual = irradiance(Precision::Single);
+    for (expected, actual) in expected.into_iter().zip(actual) {
+        assert!(expected > 0.0);
+        assert!((actual - expected).abs() < expected * 1e-3, "{} {}", actual, expected);
+    }
+}
+
+#[test]
+fn test_polarized_ior_derivatives() {
+    use crate::SceneBuilder;
+    let trace = |polarized: bool| {
+        let renderer = Renderer::new(Scene { polarized, ..SceneBuilder::new(0).ior_scene(1.5) });
+        // Off-center through the sphere, so that s and p light differ at every surface.
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), (Vec3::new(0.1, -0.15, 0.0) - Vec3::new(0.0, 0.0, 1.0)).normalize());
+        renderer.raytrace_all_specular::<Der<1>>(&Ray::new(ray.orig().cast(), ray.dir().cast()), &[], None)
+    };
+    let (plain, polarized) = (trace(false), trace(true));
+    assert_eq!(plain.len(), polarized.len());
+    assert!(plain.iter().any(|path| path.modes.len() >= 3));
+    let mut corrected = false;
+    for (plain, polarized) in plain.iter().zip(polarized.iter()) {
+        assert_eq!(plain.modes, polarized.modes);
+        if plain.modes.is_empty() {
+            continue;
+        }
+        let single = plain.modes.len() == 1;
+        let (plain, polarized) = (plain.attenuation, polarized.attenuation);
+        assert!(plain.d[0] != 0.0);
+        // Unpolarized light meets the first surface, so only later surfaces see a difference.
+        if single {
+            assert!((polarized.v - plain.v).abs() < 1e-12, "{:?} {:?}", plain, polarized);
+        }
+        corrected |= (polarized.v - plain.v).abs() > 1e-6;
+        // The correction scales the path without dropping the derivatives of its Fresnel factors.
+        assert!((polarized.d[0] / polarized.v - plain.d[0] / plain.v).abs() < 1e-9, "{:?} {:?}", plain, polarized);
+    }
+    assert!(corrected);
+}
+
+#[test]
+fn test_thin_film_derivatives() {
+    use crate::SceneBuilder;
+    use crate::render::any_object::AnyObject;
+    use crate::render::material::Material;
+    use crate::render::param::Param;
+    use crate::render::scene_object::SceneObject;
+    use crate::render::sphere_object::SphereObject;
+    use crate::render::thin_film::ThinFilm;
+    use crate::render::transform_object::TransformObject;
+    use crate::geo::transform::Transform;
+    let builder = SceneBuilder::new(0);
+    let trace = |thin_film: Option<ThinFilm>, polarized: bool| {
+        let material = Material { dielectric: Some((1.0.into(), Param::var(1.5, 0))), thin_film, ..Material::default() };
+        let sphere = SphereObject::new(Sphere::new(Vec3::new(0.0, -0.2, 0.0), 0.2), material);
+        let renderer = Renderer::new(Scene {
+            scene_object: SceneObject::new(vec![TransformObject::new(Transform::default(), AnyObject::Sphere(sphere)), builder.plane()]),
+            polarized,
+            ..builder.scene()
+        });
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), (Vec3::new(0.1, -0.15, 0.0) - Vec3::new(0.0, 0.0, 1.0)).normalize());
+        renderer.raytrace_all_specular::<Der<1>>(&Ray::new(ray.orig().cast(), ray.dir().cast()), &[], None)
+    };
+    let bare = trace(None, false);
+    for polarized in [false, true] {
+        for (thickness, changed) in [(0.0, false), (300.0, true)] {
+            let coated = trace(Some(ThinFilm::new(thickness, 1.33)), polarized);
+            assert_eq!(bare.len(), coated.len());
+            let mut tinted = false;
+            for (bare, coated) in bare.iter().zip(coated.iter()) {
+                assert_eq!(bare.modes, coated.modes);
+                // The film only rescales the Fresnel factors of the bare surface, whose derivatives stay.
+                let (a, b) = (bare.attenuation, coated.attenuation);
+                assert!((b.d[0] / b.v - a.d[0] / a.v).abs() < 1e-9, "{:?} {:?}", a, b);
+                if !polarized {
+                    assert_eq!(a, b);
+                }
+                tinted |= (coated.tint - bare.tint).length() > 1e-6;
+                if !changed {
+                    assert!((coated.tint - bare.tint).length() < 1e-9);
+                }
+            }
+            assert_eq!(tinted, changed);
+        }
+    }
+}
+
+#[test]
+fn test_conductor_polarization() {
+    use crate::SceneBuilder;
+    use crate::render::any_object::AnyObject;
+    use crate::render::material::Material;
+    use crate::render::scene_object::SceneObject;
+    use crate::render::sphere_object::SphereObject;
+    use crate::render::thin_film::Conductor;
+    use crate::render::transform_object::TransformObject;
+    use crate::geo::transform::Transform;
+    let builder = SceneBuilder::new(0);
+    let gold = Conductor::new(Color::new(0.18, 0.42, 1.37), Color::new(3.42, 2.35, 1.77));
+    let sphere = SphereObject::new(Sphere::new(Vec3::new(0.0, -0.2, 0.0), 0.2), Material { conductor: Some(gold), ..Material::default() });
+    let renderer = Renderer::new(Scene {
+        scene_object: SceneObject::new(vec![TransformObject::new(Transform::default(), AnyObject::Sphere(sphere)), builder.plane()]),
+        polarized: true,
+        ..builder.scene()
+    });
+    // Onto the lower half of the sphere, reflecting down onto the floor.
+    let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), (Vec3::new(0.0, -0.3, 0.0) - Vec3::new(0.0, 0.0, 1.0)).normalize());
+    let paths = renderer.raytrace_all_specular::<f64>(&ray, &[], None);
+    let reflected = paths.iter().find(|path| path.modes == vec![SpecularMode::Reflect]).unwrap();
+    assert!(degree_of_polarization(reflected.stokes.unwrap()) > 0.01, "{:?}", reflected.stokes);
+    // The Mueller matrix only carries the polarization, the tint keeps the metal's reflectance.
+    assert!((reflected.attenuation - 1.0).abs() < 1e-12);
+    assert!(reflected.tint.z() < reflected.tint.x());
+}
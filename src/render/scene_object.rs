@@ -1,37 +1,135 @@
-use std::iter;
-use itertools::Either;
+use std::sync::Arc;
 use crate::render::any_object::AnyObject;
+use crate::geo::bounds::Bounds;
 use crate::geo::ray::Ray;
+use crate::geo::triangle::Sidedness;
 use crate::math::scalar::Scalar;
-use crate::render::object::{Manifold, Object, RaycastPoint};
+use crate::render::object::{Manifold, Object, RaycastPoint, RaycastPointHolder};
 use crate::render::transform_object::TransformObject;
+use crate::tree::bvh::{Bvh, BvhForest};
+use crate::tree::bvh_build::BvhStrategy;
+use crate::tree::bvh_sah::SahConfig;
 
 pub struct SceneObject {
-    objects: Vec<TransformObject<AnyObject>>,
+    // Top-level BVH over all objects in the order they were given, so that manifolds carry each
+    // object's index in that order. Only bounded objects are leaves of the tree.
+    bvh: Bvh<f64, Vec<TransformObject<AnyObject>>>,
+    // Objects such as planes with infinite bounds, tested against every ray.
+    unbounded: Vec<u32>,
 }
 
 impl SceneObject {
     pub fn new(objects: Vec<TransformObject<AnyObject>>) -> Self {
-        SceneObject { objects }
+        let objects = Arc::new(objects);
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = BvhForest::new(&objects).trees.into_iter()
+            .partition(|tree| tree.bounds().is_finite());
+        let unbounded = unbounded.iter().map(|tree| tree.leaves()[0]).collect();
+        let tree = BvhForest { trees: bounded }.build(&objects, &BvhStrategy::Sah(SahConfig::default()));
+        SceneObject { bvh: Bvh::new(objects, &tree), unbounded }
+    }
+    fn raycast_object<T: Scalar>(&self, index: usize, ray: &Ray<T>, inner: Option<Manifold>) -> Option<RaycastPoint<T>> {
+        let point = self.bvh.primitives()[index].raycast(ray, inner)?;
+        Some(RaycastPoint { manifold: point.manifold.push(index), ..point })
     }
 }
 
 impl Object for SceneObject {
     fn raycast<T: Scalar>(&self, ray: &Ray<T>, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
-        let mut inner = None;
-        let it;
         if let Some(manifold) = manifold {
-            let r = manifold.pop().unwrap();
-            inner = Some(r.0);
-            let index = r.1;
-            it = Either::Left(iter::once((index, &self.objects[index])));
-        } else {
-            it = Either::Right(self.objects.iter().enumerate());
+            let (inner, index) = manifold.pop().unwrap();
+            return self.raycast_object(index, ray, Some(inner));
+        }
+        let mut holder = RaycastPointHolder::new();
+        for &index in &self.unbounded {
+            holder.add(self.raycast_object(index as usize, ray, None));
         }
-        it.flat_map(|(index, shape)| {
-            let point = shape.raycast(&ray, inner)?;
-            Some(RaycastPoint { manifold: point.manifold.push(index), ..point })
-        }).min()
+        // Sidedness only applies to triangles, which the objects handle themselves.
+        self.bvh.raycast_into(ray, Sidedness::Both, &mut holder);
+        holder.into_point()
+    }
+    fn occluded(&self, ray: &Ray<f64>, max_time: f64, ignore_transmissive: bool) -> bool {
+        let objects = self.bvh.primitives();
+        self.unbounded.iter().any(|&index| objects[index as usize].occluded(ray, max_time, ignore_transmissive))
+            || self.bvh.occluded(ray, Sidedness::Both, max_time, ignore_transmissive)
+    }
+    fn bounds(&self) -> Bounds<f64> {
+        let objects = self.bvh.primitives();
+        self.unbounded.iter().map(|&index| objects[index as usize].bounds()).fold(self.bvh.bounds(), |x, y| x.union(&y))
     }
 }
 
+#[test]
+fn test_scene_bvh() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use crate::geo::sphere::Sphere;
+    use crate::geo::transform::TransformBuilder;
+    use crate::math::vec::Vec3;
+    use crate::render::material::Material;
+    use crate::render::plane_object::PlaneObject;
+    use crate::render::sphere_object::SphereObject;
+    let mut rng = SmallRng::seed_from_u64(13);
+    let point = |rng: &mut SmallRng| Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+    let plane = |y: f64| TransformObject::new(
+        TransformBuilder::new().translate(0.0, y, 0.0).build(),
+        AnyObject::Plane(PlaneObject::new(Vec3::default(), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), Material::nan(), Material::nan())),
+    );
+    let mut objects = vec![plane(-20.0)];
+    for i in 0..100 {
+        // Unbounded objects first and in the middle, where the BVH would otherwise renumber them.
+        if i == 50 {
+            objects.push(plane(20.0));
+        }
+        let center = point(&mut rng) * 10.0;
+        objects.push(TransformObject::new(
+            TransformBuilder::new().translate(center.x(), center.y(), center.z()).scale(rng.gen_range(0.5..2.0)).build(),
+            AnyObject::Sphere(SphereObject::new(Sphere::new(Vec3::default(), 0.5), Material::nan())),
+        ));
+    }
+    let count = objects.len();
+    let scene = SceneObject::new(objects);
+    assert_eq!(scene.unbounded, vec![0, 51]);
+    let mut planes = [0, 0];
+    for _ in 0..200 {
+        let ray = Ray::new(point(&mut rng) * 15.0, point(&mut rng).normalize());
+        let expected = (0..count).filter_map(|index| {
+            let point = scene.bvh.primitives()[index].raycast(&ray, None)?;
+            Some((point.time, index))
+        }).min_by(|x, y| x.0.total_cmp(&y.0));
+        let actual = scene.raycast(&ray, None);
+        assert_eq!(actual.as_ref().map(|x| (x.time, x.manifold.pop().unwrap().1)), expected);
+        if let Some(actual) = actual {
+            match actual.manifold.pop().unwrap().1 {
+                0 => planes[0] += 1,
+                51 => planes[1] += 1,
+                _ => {}
+            }
+            let replay = scene.raycast(&ray, Some(actual.manifold)).unwrap();
+            assert_eq!(replay.time, actual.time);
+            assert!(scene.occluded(&ray, actual.time * 1.01, false));
+            assert!(!scene.occluded(&ray, actual.time * 0.99, false));
+        }
+    }
+    assert!(planes[0] > 0 && planes[1] > 0, "{:?}", planes);
+}
+
+#[test]
+fn test_scene_occluded_transmissive() {
+    use crate::geo::sphere::Sphere;
+    use crate::geo::transform::TransformBuilder;
+    use crate::math::vec::Vec3;
+    use crate::render::material::Material;
+    use crate::render::sphere_object::SphereObject;
+    let sphere = |x: f64, material: Material| TransformObject::new(
+        TransformBuilder::new().translate(x, 0.0, -2.0).build(),
+        AnyObject::Sphere(SphereObject::new(Sphere::new(Vec3::default(), 0.5), material)),
+    );
+    let glass = Material { dielectric: Some((1.0.into(), 1.5.into())), ..Material::default() };
+    let scene = SceneObject::new(vec![sphere(0.0, glass), sphere(2.0, Material::default())]);
+    let through_glass = Ray::new(Vec3::broadcast(0.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(scene.occluded(&through_glass, 10.0, false));
+    assert!(!scene.occluded(&through_glass, 10.0, true));
+    let through_diffuse = Ray::new(Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(scene.occluded(&through_diffuse, 10.0, false));
+    assert!(scene.occluded(&through_diffuse, 10.0, true));
+}
@@ -0,0 +1,83 @@
+use crate::geo::color::Color;
+use crate::math::vec::Vector;
+
+pub const SPECTRAL_BINS: usize = 16;
+pub type Spectrum = Vector<SPECTRAL_BINS, f64>;
+
+const MIN_WAVELENGTH: f64 = 400.0;
+const MAX_WAVELENGTH: f64 = 700.0;
+
+// Representative wavelengths (nm) of the red, green and blue channels.
+pub const RGB_WAVELENGTHS: [f64; 3] = [610.0, 545.0, 455.0];
+const RGB_WIDTHS: [f64; 3] = [40.0, 40.0, 30.0];
+
+/// Wavelength dependent throughput of a path. In RGB mode each channel is evaluated at a single
+/// wavelength, in spectral mode the visible range is binned and projected to RGB at the end.
+#[derive(Copy, Clone, Debug)]
+pub enum Tint {
+    Rgb(Color),
+    Spectral(Spectrum),
+}
+
+pub fn wavelength(bin: usize) -> f64 {
+    MIN_WAVELENGTH + (bin as f64 + 0.5) * (MAX_WAVELENGTH - MIN_WAVELENGTH) / (SPECTRAL_BINS as f64)
+}
+
+pub fn wavelengths() -> Spectrum {
+    let mut result = Spectrum::default();
+    for bin in 0..SPECTRAL_BINS {
+        result[bin] = wavelength(bin);
+    }
+    result
+}
+
+fn response(channel: usize, wavelength: f64) -> f64 {
+    let x = (wavelength - RGB_WAVELENGTHS[channel]) / RGB_WIDTHS[channel];
+    (-0.5 * x * x).exp()
+}
+
+impl Tint {
+    pub fn white(spectral: bool) -> Self {
+        if spectral {
+            Tint::Spectral(Spectrum::broadcast(1.0))
+        } else {
+            Tint::Rgb(Color::broadcast(1.0))
+        }
+    }
+    // Scales the tint by a factor that depends on the wavelength in nanometers.
+    pub fn scale(self, mut f: impl FnMut(f64) -> f64) -> Self {
+        match self {
+            Tint::Rgb(color) => Tint::Rgb(Color::from(RGB_WAVELENGTHS).zip(color).map(|(w, c)| c * f(w))),
+            Tint::Spectral(spectrum) => Tint::Spectral(wavelengths().zip(spectrum).map(|(w, s)| s * f(w))),
+        }
+    }
+    pub fn to_color(self) -> Color {
+        match self {
+            Tint::Rgb(color) => color,
+            Tint::Spectral(spectrum) => {
+                let mut color = Color::default();
+                for channel in 0..3 {
+                    let mut total = 0.0;
+                    let mut weight = 0.0;
+                    for bin in 0..SPECTRAL_BINS {
+                        let r = response(channel, wavelength(bin));
+                        total += spectrum[bin] * r;
+                        weight += r;
+                    }
+                    color[channel] = total / weight;
+                }
+                color
+            }
+        }
+    }
+}
+
+#[test]
+fn test_white() {
+    for spectral in [false, true] {
+        let color = Tint::white(spectral).to_color();
+        for c in color {
+            assert!((c - 1.0).abs() < 1e-12);
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use roots::find_roots_quadratic;
 use crate::geo::color::Color;
 use crate::math::scalar::Scalar;
+use crate::geo::bounds::Bounds;
 use crate::geo::ray::Ray;
+use crate::math::vec::Vec3;
 use crate::geo::sphere::Sphere;
 use crate::render::material::Material;
 use crate::render::object::{Manifold, Object, RaycastPoint};
@@ -22,4 +24,28 @@ impl Object for SphereObject {
         let point = self.sphere.raycast(ray)?;
         Some(RaycastPoint { material: self.material, ..point })
     }
+    fn occluded(&self, ray: &Ray<f64>, max_time: f64, ignore_transmissive: bool) -> bool {
+        if ignore_transmissive && self.material.is_transmissive() {
+            return false;
+        }
+        self.sphere.raycast(ray).map_or(false, |point| point.time < max_time)
+    }
+    fn bounds(&self) -> Bounds<f64> {
+        let rad = Vec3::broadcast(self.sphere.rad());
+        Bounds::new(self.sphere.orig() - rad, self.sphere.orig() + rad)
+    }
+}
+
+#[test]
+fn test_sphere_occluded() {
+    let sphere = SphereObject::new(Sphere::new(Vec3::new(0.0, 0.0, -2.0), 0.5), Material::default());
+    // Times are in units of the direction's length, whether or not it is normalized.
+    for dir in [Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, -4.0)] {
+        let ray = Ray::new(Vec3::broadcast(0.0), dir);
+        let time = sphere.raycast(&ray, None).unwrap().time;
+        assert!((time * dir.length() - 1.5).abs() < 1e-12);
+        assert!(sphere.occluded(&ray, time * 1.01, false));
+        assert!(!sphere.occluded(&ray, time * 0.99, false));
+    }
+    assert!(!sphere.occluded(&Ray::new(Vec3::broadcast(0.0), Vec3::new(0.0, 0.0, 1.0)), 10.0, false));
 }
@@ -0,0 +1,53 @@
+use std::fmt::{Debug, Formatter};
+use crate::render::newton::NewtonOutcome;
+use crate::util::counter::Counter;
+
+/// Event counts gathered while rendering, read back through `Renderer::stats`.
+pub struct RenderStats {
+    pub specular_depth_truncated: Counter,
+    pub specular_roulette_killed: Counter,
+    pub specular_roulette_survived: Counter,
+    pub newton_converged: Counter,
+    pub newton_diverged: Counter,
+    pub newton_topology_changed: Counter,
+    pub newton_occluded: Counter,
+    pub ambiguous_pixels: Counter,
+}
+
+impl RenderStats {
+    pub fn new() -> Self {
+        RenderStats {
+            specular_depth_truncated: Counter::new(),
+            specular_roulette_killed: Counter::new(),
+            specular_roulette_survived: Counter::new(),
+            newton_converged: Counter::new(),
+            newton_diverged: Counter::new(),
+            newton_topology_changed: Counter::new(),
+            newton_occluded: Counter::new(),
+            ambiguous_pixels: Counter::new(),
+        }
+    }
+    pub fn record_newton(&self, outcome: NewtonOutcome) {
+        match outcome {
+            NewtonOutcome::Converged => self.newton_converged.inc(),
+            NewtonOutcome::Diverged => self.newton_diverged.inc(),
+            NewtonOutcome::TopologyChanged => self.newton_topology_changed.inc(),
+            NewtonOutcome::Occluded => self.newton_occluded.inc(),
+        }
+    }
+}
+
+impl Debug for RenderStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RenderStats")
+            .field("specular_depth_truncated", &self.specular_depth_truncated.total())
+            .field("specular_roulette_killed", &self.specular_roulette_killed.total())
+            .field("specular_roulette_survived", &self.specular_roulette_survived.total())
+            .field("newton_converged", &self.newton_converged.total())
+            .field("newton_diverged", &self.newton_diverged.total())
+            .field("newton_topology_changed", &self.newton_topology_changed.total())
+            .field("newton_occluded", &self.newton_occluded.total())
+            .field("ambiguous_pixels", &self.ambiguous_pixels.total())
+            .finish()
+    }
+}
@@ -0,0 +1,120 @@
+use std::f64::consts::PI;
+use crate::geo::color::Color;
+use crate::math::complex::Complex;
+use crate::render::spectrum::RGB_WAVELENGTHS;
+
+/// A non-absorbing coating of the given thickness (nm) and index of refraction.
+#[derive(Copy, Clone, Debug)]
+pub struct ThinFilm {
+    pub thickness: f64,
+    pub ior: f64,
+}
+
+/// A metal with complex index of refraction eta + i k, given at the RGB wavelengths.
+#[derive(Copy, Clone, Debug)]
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+}
+
+fn cos_transmitted(n0: f64, n: Complex, cos0: f64) -> Complex {
+    let sin2 = (1.0 - cos0 * cos0).max(0.0);
+    let ratio = Complex::from(n0) / n;
+    (Complex::from(1.0) - ratio * ratio * sin2).sqrt()
+}
+
+// Amplitude reflection coefficients (s, p) at an interface from a to b.
+fn amplitudes(na: Complex, cos_a: Complex, nb: Complex, cos_b: Complex) -> (Complex, Complex) {
+    let s = (na * cos_a - nb * cos_b) / (na * cos_a + nb * cos_b);
+    let p = (nb * cos_a - na * cos_b) / (nb * cos_a + na * cos_b);
+    (s, p)
+}
+
+// Amplitude reflection coefficients (s, p) of a bare interface from a dielectric n0 into a
+// possibly absorbing base.
+pub fn fresnel_amplitudes(n0: f64, base: Complex, cos0: f64) -> (Complex, Complex) {
+    let cos2 = cos_transmitted(n0, base, cos0);
+    amplitudes(Complex::from(n0), Complex::from(cos0), base, cos2)
+}
+
+// Unpolarized reflectance for the amplitude coefficients (s, p).
+pub fn unpolarized_reflectance((s, p): (Complex, Complex)) -> f64 {
+    (s.norm_sqr() + p.norm_sqr()) / 2.0
+}
+
+pub fn fresnel_reflectance(n0: f64, base: Complex, cos0: f64) -> f64 {
+    unpolarized_reflectance(fresnel_amplitudes(n0, base, cos0))
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, ior: f64) -> Self {
+        ThinFilm { thickness, ior }
+    }
+    // Amplitude reflection coefficients (s, p), summing the multiple reflections inside the film
+    // (Airy formula).
+    pub fn amplitudes(&self, n0: f64, base: Complex, cos0: f64, wavelength: f64) -> (Complex, Complex) {
+        let n1 = Complex::from(self.ior);
+        let cos1 = cos_transmitted(n0, n1, cos0);
+        let cos2 = cos_transmitted(n0, base, cos0);
+        let (s01, p01) = amplitudes(Complex::from(n0), Complex::from(cos0), n1, cos1);
+        let (s12, p12) = amplitudes(n1, cos1, base, cos2);
+        let phase = (Complex::i() * n1 * cos1 * (4.0 * PI * self.thickness / wavelength)).exp();
+        let airy = |r01: Complex, r12: Complex| {
+            (r01 + r12 * phase) / (Complex::from(1.0) + r01 * r12 * phase)
+        };
+        (airy(s01, s12), airy(p01, p12))
+    }
+    pub fn reflectance(&self, n0: f64, base: Complex, cos0: f64, wavelength: f64) -> f64 {
+        unpolarized_reflectance(self.amplitudes(n0, base, cos0, wavelength))
+    }
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color) -> Self {
+        Conductor { eta, k }
+    }
+    // Piecewise linear interpolation between the RGB wavelengths, clamped outside of them.
+    pub fn ior(&self, wavelength: f64) -> Complex {
+        let [r, g, b] = RGB_WAVELENGTHS;
+        let lerp = |c: Color| {
+            if wavelength >= r {
+                c.x()
+            } else if wavelength >= g {
+                c.y() + (c.x() - c.y()) * (wavelength - g) / (r - g)
+            } else if wavelength >= b {
+                c.z() + (c.y() - c.z()) * (wavelength - b) / (g - b)
+            } else {
+                c.z()
+            }
+        };
+        Complex::new(lerp(self.eta), lerp(self.k))
+    }
+    pub fn amplitudes(&self, n0: f64, film: Option<&ThinFilm>, cos0: f64, wavelength: f64) -> (Complex, Complex) {
+        match film {
+            None => fresnel_amplitudes(n0, self.ior(wavelength), cos0),
+            Some(film) => film.amplitudes(n0, self.ior(wavelength), cos0, wavelength),
+        }
+    }
+    pub fn reflectance(&self, n0: f64, film: Option<&ThinFilm>, cos0: f64, wavelength: f64) -> f64 {
+        unpolarized_reflectance(self.amplitudes(n0, film, cos0, wavelength))
+    }
+}
+
+#[test]
+fn test_zero_thickness() {
+    let film = ThinFilm::new(0.0, 1.33);
+    for base in [Complex::from(1.5), Complex::new(0.2, 3.0)] {
+        for cos0 in [1.0, 0.7, 0.2] {
+            let expected = fresnel_reflectance(1.0, base, cos0);
+            assert!((film.reflectance(1.0, base, cos0, 550.0) - expected).abs() < 1e-12);
+        }
+    }
+}
+
+#[test]
+fn test_quarter_wave_coating() {
+    let ior = 1.5f64.sqrt();
+    let film = ThinFilm::new(550.0 / (4.0 * ior), ior);
+    assert!(film.reflectance(1.0, Complex::from(1.5), 1.0, 550.0) < 1e-12);
+    assert!(fresnel_reflectance(1.0, Complex::from(1.5), 1.0) > 0.03);
+}
@@ -1,29 +1,92 @@
+use crate::geo::bounds::Bounds;
 use crate::geo::ray::Ray;
 use crate::math::scalar::Scalar;
 use crate::render::object::{Manifold, Object, RaycastPoint};
 use crate::geo::transform::Transform;
+use crate::math::mat::Mat4;
+use crate::render::param::Param;
 
 pub struct TransformObject<O> {
     transform: Transform<f64>,
+    // Set when some entries are differentiable, so the transform is rebuilt for scalar types that
+    // carry derivatives. Plain scalars cast the cached f64 transform instead.
+    params: Option<Mat4<Param>>,
     inner: O,
 }
 
 impl<O> TransformObject<O> {
     pub fn new(transform: Transform<f64>, inner: O) -> Self {
-        TransformObject { transform, inner }
+        TransformObject { transform, params: None, inner }
+    }
+    pub fn with_params(forward: Mat4<Param>, inner: O) -> Self {
+        TransformObject { transform: Transform::from(forward.map(|x| x.value)), params: Some(forward), inner }
     }
 }
 
 impl<O: Object> Object for TransformObject<O> {
     fn raycast<T: Scalar>(&self, ray_outer: &Ray<T>, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
-        let transform = self.transform.cast::<T>();
+        let transform = match &self.params {
+            Some(params) if !T::is_plain() => Transform::from(params.map(|x| x.get::<T>())),
+            _ => self.transform.cast::<T>(),
+        };
         let ray_inner = transform.reverse_ray(ray_outer);
         let point = self.inner.raycast(&ray_inner, manifold)?;
         Some(RaycastPoint {
-            position: ray_outer.pos(point.time),
+            position: transform.forward_pos(point.position),
+            error: transform.forward_pos_error(point.position, point.error),
             inter_normal: transform.forward_norm(point.inter_normal),
             geo_normal: transform.forward_norm(point.geo_normal),
             ..point
         })
     }
-}
\ No newline at end of file
+    fn occluded(&self, ray_outer: &Ray<f64>, max_time: f64, ignore_transmissive: bool) -> bool {
+        // Times carry over unchanged, since the direction is not renormalized.
+        self.inner.occluded(&self.transform.reverse_ray(ray_outer), max_time, ignore_transmissive)
+    }
+    fn bounds(&self) -> Bounds<f64> {
+        let inner = self.inner.bounds();
+        if !inner.is_finite() {
+            return Bounds::full();
+        }
+        inner.corners().into_iter().map(|x| Bounds::from(self.transform.forward_pos(x))).collect()
+    }
+}
+#[test]
+fn test_transform_occluded() {
+    use crate::geo::sphere::Sphere;
+    use crate::geo::transform::TransformBuilder;
+    use crate::math::vec::Vec3;
+    use crate::render::material::Material;
+    use crate::render::sphere_object::SphereObject;
+    // A unit sphere scaled to radius 2 around (0, 0, -5), as the translation is scaled too. The
+    // inner ray's direction has length 0.5, and its times must still be world distances.
+    let transform = TransformBuilder::new().scale(2.0).translate(0.0, 0.0, -2.5).build();
+    let object = TransformObject::new(transform, SphereObject::new(Sphere::new(Vec3::broadcast(0.0), 1.0), Material::default()));
+    let ray = Ray::new(Vec3::broadcast(0.0), Vec3::new(0.0, 0.0, -1.0));
+    let time = object.raycast(&ray, None).unwrap().time;
+    assert!((time - 3.0).abs() < 1e-12);
+    assert!(object.occluded(&ray, 3.01, false));
+    assert!(!object.occluded(&ray, 2.99, false));
+    assert!(!object.occluded(&Ray::new(Vec3::broadcast(0.0), Vec3::new(0.0, 1.0, 0.0)), 10.0, false));
+}
+
+#[test]
+fn test_transform_params() {
+    use crate::geo::sphere::Sphere;
+    use crate::math::scalar::Der;
+    use crate::math::vec::Vec3;
+    use crate::render::material::Material;
+    use crate::render::sphere_object::SphereObject;
+    // A unit sphere moved to z = -3 by a differentiable translation.
+    let mut forward = Mat4::identity().map(Param::new);
+    forward[(2, 3)] = Param::var(-3.0, 0);
+    let object = TransformObject::with_params(forward, SphereObject::new(Sphere::new(Vec3::broadcast(0.0), 1.0), Material::default()));
+    let ray = Ray::new(Vec3::broadcast(0.0), Vec3::new(0.0, 0.0, -1.0));
+    let time = object.raycast(&ray, None).unwrap().time;
+    assert!((time - 2.0).abs() < 1e-12);
+    let time = object.raycast::<Der<1>>(&Ray::new(ray.orig().cast(), ray.dir().cast()), None).unwrap().time;
+    assert!((time.v - 2.0).abs() < 1e-12);
+    assert!((time.d[0] + 1.0).abs() < 1e-12, "{:?}", time);
+    assert!(object.occluded(&ray, 2.01, false));
+    assert!(!object.occluded(&ray, 1.99, false));
+}
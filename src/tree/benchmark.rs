@@ -3,9 +3,15 @@ extern crate test;
 use std::hint::black_box;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
+use crate::geo::bounds::Bounds;
 use crate::geo::ray::Ray;
+use crate::geo::triangle::Sidedness;
 use crate::math::vec::Vec3;
-use crate::mesh::pinecone;
+use crate::math::scalar::{F32, Scalar};
+use crate::mesh::{bunny, pinecone};
+use crate::tree::bvh::{Bvh, BvhForest};
+use crate::tree::bvh_build::BvhStrategy;
+use crate::tree::bvh_sah::SahConfig;
 use crate::tree::kd_tree::{KdEntry, KdTree};
 
 #[bench]
@@ -24,7 +30,7 @@ fn bench_mesh(b: &mut test::Bencher) {
             break;
         }
         let ray = Ray::new(orig, dir);
-        black_box(mesh.raycast(&ray, None));
+        black_box(mesh.raycast(&ray, Sidedness::Front, None));
     });
 }
 
@@ -32,11 +38,86 @@ fn bench_mesh(b: &mut test::Bencher) {
 #[ignore]
 fn bench_kd_tree(b: &mut test::Bencher) {
     let mut rng = SmallRng::seed_from_u64(10212233);
-    let tree = KdTree::new((0..100000).map(|_| {
+    let tree = KdTree::<_>::new((0..100000).map(|_| {
         KdEntry::new(Vec3::<()>::default().map(|x| rng.gen_range(0.0..1.0)), ())
     }).collect());
     let delta = 1.0;
     b.iter(|| {
         tree.nearest(&Vec3::<()>::default().map(|x| rng.gen_range(0.0 - delta..1.0 + delta)), 1)
     });
-}
\ No newline at end of file
+}
+
+fn random_ray(rng: &mut SmallRng, bounds: &Bounds<f64>) -> Ray<f64> {
+    let orig = rng.sample(bounds);
+    loop {
+        let dir = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+        if dir.length() <= 1.0 {
+            return Ray::new(orig, dir.normalize());
+        }
+    }
+}
+
+fn bench_precision<P: Scalar>(b: &mut test::Bencher, mesh: &Bvh) {
+    let bounds = mesh.bounds();
+    let mesh = mesh.to_precision::<P>();
+    let mut rng = SmallRng::seed_from_u64(10212233);
+    b.iter(|| {
+        let ray = random_ray(&mut rng, &bounds);
+        black_box(mesh.raycast(&Ray::new(ray.orig().map(P::from), ray.dir().map(P::from)), Sidedness::Front, None));
+    });
+}
+
+#[bench]
+#[ignore]
+fn bench_bunny_f64(b: &mut test::Bencher) { bench_precision::<f64>(b, &bunny()); }
+
+#[bench]
+#[ignore]
+fn bench_bunny_f32(b: &mut test::Bencher) { bench_precision::<F32>(b, &bunny()); }
+
+#[bench]
+#[ignore]
+fn bench_pinecone_f64(b: &mut test::Bencher) { bench_precision::<f64>(b, &pinecone()); }
+
+#[bench]
+#[ignore]
+fn bench_pinecone_f32(b: &mut test::Bencher) { bench_precision::<F32>(b, &pinecone()); }
+
+// Reports how far single precision hits land from the double precision ones.
+#[test]
+#[ignore]
+fn test_precision_error() {
+    for (name, mesh) in [("bunny", bunny()), ("pinecone", pinecone())] {
+        let single = mesh.to_precision::<F32>();
+        let bounds = mesh.bounds();
+        let mut rng = SmallRng::seed_from_u64(10212233);
+        let (mut mismatched, mut max_error, mut hits) = (0, 0.0f64, 0);
+        for _ in 0..100000 {
+            let ray = random_ray(&mut rng, &bounds);
+            let expected = mesh.raycast(&ray, Sidedness::Front, None);
+            let actual = single.raycast(&Ray::new(ray.orig().map(F32::from), ray.dir().map(F32::from)), Sidedness::Front, None);
+            match (expected, actual) {
+                (Some(expected), Some(actual)) if expected.manifold == actual.manifold => {
+                    hits += 1;
+                    max_error = max_error.max(expected.position.distance(actual.position.map(|x| x.into_const())));
+                }
+                (None, None) => {}
+                _ => mismatched += 1,
+            }
+        }
+        println!("{}: {} hits, {} mismatched, max error {:e}", name, hits, mismatched, max_error);
+    }
+}
+
+// Compares build time and SAH cost of the trees built by each strategy.
+#[test]
+#[ignore]
+fn test_strategy_quality() {
+    let config = SahConfig::default();
+    for (name, mesh) in [("bunny", bunny()), ("pinecone", pinecone())] {
+        for strategy in BvhStrategy::all() {
+            let (tree, time) = BvhForest::new(mesh.primitives()).build_timed(mesh.primitives(), &strategy);
+            println!("{} {}: built in {:?}, cost {:.2}", name, strategy.name(), time, tree.sah_cost(&config));
+        }
+    }
+}
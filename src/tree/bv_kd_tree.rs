@@ -4,6 +4,7 @@ use rand::{Rng, SeedableRng};
 use rand_xorshift::XorShiftRng;
 use crate::geo::axis_plane::AxisPlane;
 use crate::geo::bounds::Bounds;
+use crate::math::scalar::Scalar;
 use crate::math::vec::Vec3;
 use crate::tree::kd_tree::{KdEntry, KdIter, KdNeighbor, KdQuery, KdTree};
 
@@ -31,7 +32,7 @@ impl<T> KdQuery<BvKdEntry<T>> for BvKdQuery {
     fn center(&self) -> Vec3<f64> {
         self.bounds.center()
     }
-    fn distance(&self, node: &KdEntry<BvKdEntry<T>>) -> f64 {
+    fn distance<P: Scalar>(&self, node: &KdEntry<BvKdEntry<T>, P>) -> f64 {
         self.bounds.union(&node.value().bounds).surface_area()
     }
     fn min_distance(&self, space: AxisPlane) -> f64 {
@@ -51,7 +52,7 @@ impl<T> KdQuery<BvKdEntry<T>> for BvKdQuery {
 }
 
 impl<T> BvKdTree<T> {
-    pub fn new(volumes: Vec<BvKdEntry<T>>) -> Self {
+    pub fn new(volumes: Vec<BvKdEntry<T>>) -> Self where T: Send {
         let inner = KdTree::new(
             volumes.into_iter()
                 .map(|x| KdEntry::new(x.bounds.center(), x))
@@ -1,33 +1,51 @@
 use std::ops::Range;
+use std::sync::Arc;
 use itertools::Itertools;
 use partition::partition;
 use ordered_float::NotNan;
 use crate::geo::bounds::{Bounds, Interval};
 use crate::math::scalar::Scalar;
+use crate::math::vec::Vec3;
 use crate::geo::ray::Ray;
-use crate::mesh::{Mesh, MeshFace, TriMesh, TriVerts};
+use crate::geo::triangle::Sidedness;
+use crate::geo::indexed_mesh::IndexedMesh;
+use crate::tree::bvh_sah::SahConfig;
+use crate::tree::primitive::PrimitiveSet;
 use crate::render::object::{Manifold, RaycastPoint, RaycastPointHolder};
 use partition::partition_index;
 use crate::tree::kd_tree::KdTree;
 use crate::util::itertools2::Itertools2;
 
-struct BvhEntry {
-    bounds: Bounds<f64>,
+#[derive(Clone)]
+struct BvhEntry<P = f64> {
+    bounds: Bounds<P>,
     child_nodes: Range<usize>,
     child_leaves: Range<usize>,
 }
 
-pub struct Bvh {
-    nodes: Vec<BvhEntry>,
-    leaves: Vec<TriVerts>,
+// Node bounds are stored at precision P; the primitives themselves stay in f64. Leaves are
+// indices into the shared primitive set, by default the triangles of a mesh.
+pub struct Bvh<P = f64, S = IndexedMesh> {
+    primitives: Arc<S>,
+    nodes: Vec<BvhEntry<P>>,
+    leaves: Vec<u32>,
     root: usize,
 }
 
+// By hand, so that the shared primitives need not be Clone themselves.
+impl<P: Clone, S> Clone for Bvh<P, S> {
+    fn clone(&self) -> Self {
+        Bvh { primitives: self.primitives.clone(), nodes: self.nodes.clone(), leaves: self.leaves.clone(), root: self.root }
+    }
+}
+
 #[derive(Debug)]
 pub struct BvhTree {
-    leaves: Vec<TriVerts>,
+    leaves: Vec<u32>,
     nodes: Vec<BvhTree>,
     bounds: Bounds<f64>,
+    // Set for a tree holding a single primitive, whose centroid may differ from its box's center.
+    centroid: Option<Vec3<f64>>,
 }
 
 #[derive(Debug)]
@@ -36,9 +54,9 @@ pub struct BvhForest {
 }
 
 impl BvhEntry {
-    pub fn new(tris: &Vec<TriVerts>, child_leaves: Range<usize>) -> Self {
+    pub fn new<S: PrimitiveSet + ?Sized>(primitives: &S, leaves: &[u32], child_leaves: Range<usize>) -> Self {
         BvhEntry {
-            bounds: tris[child_leaves.clone()].iter().map(|x| x.triangle().bounds()).collect(),
+            bounds: leaves[child_leaves.clone()].iter().map(|&x| primitives.bounds(x)).collect(),
             child_nodes: 0..0,
             child_leaves,
         }
@@ -46,11 +64,12 @@ impl BvhEntry {
 }
 
 impl BvhForest {
-    pub fn new(mesh: &[TriVerts]) -> Self {
+    pub fn new<S: PrimitiveSet + ?Sized>(primitives: &S) -> Self {
         BvhForest {
-            trees: mesh.into_iter().map(|face| {
+            trees: (0..primitives.len() as u32).map(|index| {
                 let mut tree = BvhTree::new();
-                tree.add_leaf(&face);
+                tree.add_leaf(index, primitives.bounds(index));
+                tree.centroid = Some(primitives.centroid(index));
                 tree
             }).collect()
         }
@@ -63,22 +82,54 @@ impl BvhTree {
             leaves: vec![],
             nodes: vec![],
             bounds: Bounds::empty(),
+            centroid: None,
         }
     }
-    pub fn add_leaf(&mut self, leaf: &TriVerts) {
-        self.bounds = self.bounds.union(&leaf.triangle().bounds());
-        self.leaves.push(leaf.clone());
+    pub fn add_leaf(&mut self, leaf: u32, bounds: Bounds<f64>) {
+        self.bounds = self.bounds.union(&bounds);
+        self.centroid = None;
+        self.leaves.push(leaf);
     }
     pub fn add_node(&mut self, node: BvhTree) {
         self.bounds = self.bounds.union(&node.bounds);
+        self.centroid = None;
         self.nodes.push(node);
     }
+    // Takes over the leaves and children of other.
+    pub fn absorb(&mut self, other: BvhTree) {
+        self.bounds = self.bounds.union(&other.bounds);
+        self.centroid = None;
+        self.leaves.extend(other.leaves);
+        self.nodes.extend(other.nodes);
+    }
     pub fn bounds(&self) -> &Bounds<f64> { &self.bounds }
+    pub fn centroid(&self) -> Vec3<f64> { self.centroid.unwrap_or_else(|| self.bounds.center()) }
+    pub fn leaves(&self) -> &[u32] { &self.leaves }
+    pub fn nodes(&self) -> &[BvhTree] { &self.nodes }
+}
+
+impl<S: PrimitiveSet> Bvh<f64, S> {
+    pub fn new(primitives: Arc<S>, tree: &BvhTree) -> Self {
+        Self::with_precision(primitives, tree)
+    }
+    pub fn to_precision<P: Scalar>(&self) -> Bvh<P, S> {
+        Bvh {
+            primitives: self.primitives.clone(),
+            nodes: self.nodes.iter().map(|node| BvhEntry {
+                bounds: node.bounds.round_out(),
+                child_nodes: node.child_nodes.clone(),
+                child_leaves: node.child_leaves.clone(),
+            }).collect(),
+            leaves: self.leaves.clone(),
+            root: self.root,
+        }
+    }
 }
 
-impl Bvh {
-    pub fn new(tree: &BvhTree) -> Bvh {
+impl<P: Scalar, S: PrimitiveSet> Bvh<P, S> {
+    pub fn with_precision(primitives: Arc<S>, tree: &BvhTree) -> Self {
         let mut bvh = Bvh {
+            primitives,
             nodes: vec![],
             leaves: vec![],
             root: 0,
@@ -88,9 +139,9 @@ impl Bvh {
         bvh.nodes.push(root);
         bvh
     }
-    fn add_bvh_tree(&mut self, tree: &BvhTree) -> BvhEntry {
+    fn add_bvh_tree(&mut self, tree: &BvhTree) -> BvhEntry<P> {
         let leaf_start = self.leaves.len();
-        self.leaves.extend(tree.leaves.iter().cloned());
+        self.leaves.extend_from_slice(&tree.leaves);
         let leaf_end = self.leaves.len();
         let nodes: Vec<_> = tree.nodes.iter().map(|child| {
             self.add_bvh_tree(child)
@@ -99,39 +150,142 @@ impl Bvh {
         self.nodes.extend(nodes);
         let nodes_end = self.nodes.len();
         BvhEntry {
-            bounds: tree.bounds,
+            bounds: tree.bounds.round_out(),
             child_nodes: nodes_start..nodes_end,
             child_leaves: leaf_start..leaf_end,
         }
     }
-    pub fn raycast<T: Scalar>(&self, ray: &Ray<T>, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
+    pub fn raycast<T: Scalar>(&self, ray: &Ray<T>, sidedness: Sidedness, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
         if let Some(manifold) = manifold {
             let (inner, index) = manifold.pop().unwrap();
-            let point = self.leaves[index].raycast(ray, true)?;
+            let point = self.primitives.raycast(index as u32, ray, sidedness, Some(inner))?;
             Some(RaycastPoint { manifold: point.manifold.push(index), ..point })
         } else {
             let mut holder = RaycastPointHolder::new();
-            self.raycast_rec(ray, self.root, &mut holder);
+            self.traverse(ray, sidedness, self.root, &mut holder);
             holder.into_point()
         }
     }
-    pub fn raycast_rec<T: Scalar>(&self, ray: &Ray<T>, node: usize, output: &mut RaycastPointHolder<T>) {
-        let node = &self.nodes[node];
-        if let Some(bounds_times) = node.bounds.cast::<T>().raycast(ray) {
-            if bounds_times.intersect(&output.interval()).is_some() {
-                for child in node.child_leaves.clone() {
-                    let f = &self.leaves[child];
-                    output.add(f.raycast(ray, false).map(|point| {
-                        RaycastPoint { manifold: point.manifold.push(child), ..point }
-                    }))
-                }
-                for child in node.child_nodes.clone() {
-                    self.raycast_rec(ray, child, output);
+    // Adds the closest hit to output, skipping anything beyond the hit output already holds.
+    pub fn raycast_into<T: Scalar>(&self, ray: &Ray<T>, sidedness: Sidedness, output: &mut RaycastPointHolder<T>) {
+        self.traverse(ray, sidedness, self.root, output);
+    }
+    // Visits nodes from an explicit stack, nearer children first, and skips any node whose box is
+    // entered after the closest hit found so far.
+    pub fn traverse<T: Scalar>(&self, ray: &Ray<T>, sidedness: Sidedness, root: usize, output: &mut RaycastPointHolder<T>) {
+        let inv_dir = ray.inv_dir();
+        let entry = |node: usize, output: &RaycastPointHolder<T>| {
+            let times = self.nodes[node].bounds.convert::<T>().raycast_inverse(ray.orig(), inv_dir)?;
+            Some(times.intersect(&output.interval())?.min())
+        };
+        let mut stack = vec![];
+        stack.extend(entry(root, output).map(|time| (root, time)));
+        let mut children = vec![];
+        while let Some((node, time)) = stack.pop() {
+            if !(time < output.interval().max()) {
+                continue;
+            }
+            let node = &self.nodes[node];
+            for child in node.child_leaves.clone() {
+                // Manifolds name the primitive rather than the leaf, which may be one of several
+                // references to it.
+                let primitive = self.leaves[child];
+                output.add(self.primitives.raycast(primitive, ray, sidedness, None).map(|point| {
+                    RaycastPoint { manifold: point.manifold.push(primitive as usize), ..point }
+                }))
+            }
+            children.clear();
+            children.extend(node.child_nodes.clone().filter_map(|child| Some((child, entry(child, output)?))));
+            // Farthest first, so that the nearest is popped next.
+            children.sort_by(|x, y| y.1.real_cmp(x.1));
+            stack.extend_from_slice(&children);
+        }
+    }
+    // Any-hit query: stops at the first primitive hit before max_time, in whatever order. Like
+    // Object::occluded, only the constant part of a ray is needed.
+    pub fn occluded(&self, ray: &Ray<f64>, sidedness: Sidedness, max_time: f64, ignore_transmissive: bool) -> bool {
+        let inv_dir = ray.inv_dir();
+        let interval = Interval::new(0.0, max_time);
+        let mut stack = vec![self.root];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let entered = node.bounds.convert::<f64>().raycast_inverse(ray.orig(), inv_dir)
+                .map_or(false, |times| times.intersect(&interval).is_some());
+            if !entered {
+                continue;
+            }
+            for &primitive in &self.leaves[node.child_leaves.clone()] {
+                if self.primitives.occluded(primitive, ray, sidedness, max_time, ignore_transmissive) {
+                    return true;
                 }
             }
+            stack.extend(node.child_nodes.clone());
+        }
+        false
+    }
+    // Recomputes the node bounds around moved primitives, keeping the topology. Children are stored
+    // before their parents, so a single pass in order is bottom-up.
+    pub fn refit(&mut self, primitives: Arc<S>) {
+        assert_eq!(primitives.len(), self.primitives.len());
+        let leaves = &self.leaves;
+        let mut exact: Vec<Bounds<f64>> = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.iter_mut() {
+            let bounds: Bounds<f64> = leaves[node.child_leaves.clone()].iter().map(|&x| primitives.bounds(x))
+                .chain(node.child_nodes.clone().map(|x| exact[x]))
+                .collect();
+            node.bounds = bounds.round_out();
+            exact.push(bounds);
         }
+        self.primitives = primitives;
+    }
+    // As BvhTree::sah_cost.
+    pub fn sah_cost(&self, config: &SahConfig) -> f64 {
+        self.node_cost(self.root, config)
+    }
+    fn node_cost(&self, node: usize, config: &SahConfig) -> f64 {
+        let node = &self.nodes[node];
+        let area = node.bounds.convert::<f64>().surface_area();
+        let children: f64 = node.child_nodes.clone()
+            .map(|child| self.nodes[child].bounds.convert::<f64>().surface_area() / area * (config.traversal_cost + self.node_cost(child, config)))
+            .sum();
+        node.child_leaves.len() as f64 * config.intersection_cost + children
     }
+    pub fn primitives(&self) -> &Arc<S> { &self.primitives }
     pub fn bounds(&self) -> Bounds<f64> {
-        self.nodes[self.root].bounds
+        self.nodes[self.root].bounds.convert()
     }
-}
\ No newline at end of file
+    // The topology as a BvhTree, with exact bounds, for collapsing into a wide BVH without a rebuild.
+    pub fn tree(&self) -> BvhTree {
+        self.node_tree(self.root)
+    }
+    fn node_tree(&self, node: usize) -> BvhTree {
+        let node = &self.nodes[node];
+        let mut tree = BvhTree::new();
+        for &leaf in &self.leaves[node.child_leaves.clone()] {
+            tree.add_leaf(leaf, self.primitives.bounds(leaf));
+        }
+        for child in node.child_nodes.clone() {
+            tree.add_node(self.node_tree(child));
+        }
+        tree
+    }
+}
+#[test]
+fn test_indexed_bvh() {
+    let grid = (0..4).flat_map(|i| (0..4).map(move |j| Vec3::new(i as f64, j as f64, i as f64 * 0.1))).collect();
+    let indices = (0..3u32).flat_map(|i| (0..3u32).flat_map(move |j| {
+        let k = i * 4 + j;
+        [[k, k + 4, k + 5], [k, k + 5, k + 1]]
+    })).collect();
+    let mesh = Arc::new(IndexedMesh::new(grid, indices));
+    let bvh = Bvh::new(mesh.clone(), &BvhForest::new(&mesh).subdivide());
+    let ray = Ray::new(Vec3::new(1.3, 2.6, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = bvh.raycast(&ray, Sidedness::Front, None).unwrap();
+    assert!(hit.position.distance(Vec3::new(1.3, 2.6, 0.13)) < 1e-12);
+    let replay = bvh.raycast(&ray, Sidedness::Front, Some(hit.manifold)).unwrap();
+    assert_eq!(replay.manifold, hit.manifold);
+    assert!(bvh.raycast(&ray, Sidedness::Back, None).is_none());
+    assert!(bvh.occluded(&ray, Sidedness::Front, 1.0, false));
+    assert!(!bvh.occluded(&ray, Sidedness::Front, 0.8, false));
+    assert!(!bvh.occluded(&ray, Sidedness::Back, 1.0, false));
+}
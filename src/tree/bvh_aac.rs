@@ -0,0 +1,167 @@
+use rayon::slice::ParallelSliceMut;
+use crate::geo::bounds::Bounds;
+use crate::tree::bvh::{BvhForest, BvhTree};
+use crate::tree::bvh_build::PARALLEL_THRESHOLD;
+
+#[derive(Copy, Clone, Debug)]
+pub struct AacConfig {
+    // Buckets at most this large are clustered directly.
+    pub delta: usize,
+    // Growth of the number of clusters kept per subtree with its size: larger keeps more
+    // clusters for the slower, higher quality merges further up.
+    pub alpha: f64,
+}
+
+impl Default for AacConfig {
+    // The high quality preset of Gu et al.
+    fn default() -> Self {
+        AacConfig { delta: 20, alpha: 0.2 }
+    }
+}
+
+impl AacConfig {
+    // How many clusters a subtree of size n is reduced to before merging upwards.
+    fn reduction(&self, n: usize) -> usize {
+        let delta = self.delta as f64;
+        ((delta.powf(0.5 - self.alpha) / 2.0 * (n as f64).powf(self.alpha)).ceil() as usize).max(1)
+    }
+}
+
+// Interleaves the low 10 bits of x, y and z.
+fn morton(cell: [u32; 3]) -> u32 {
+    fn spread(mut x: u32) -> u32 {
+        x &= 0x3ff;
+        x = (x | (x << 16)) & 0x030000ff;
+        x = (x | (x << 8)) & 0x0300f00f;
+        x = (x | (x << 4)) & 0x030c30c3;
+        x = (x | (x << 2)) & 0x09249249;
+        x
+    }
+    spread(cell[0]) << 2 | spread(cell[1]) << 1 | spread(cell[2])
+}
+
+fn distance(a: &BvhTree, b: &BvhTree) -> f64 {
+    a.bounds().union(b.bounds()).surface_area()
+}
+
+fn nearest(clusters: &[BvhTree], i: usize) -> usize {
+    (0..clusters.len())
+        .filter(|&j| j != i)
+        .min_by(|&x, &y| distance(&clusters[i], &clusters[x]).total_cmp(&distance(&clusters[i], &clusters[y])))
+        .unwrap()
+}
+
+// Greedily merges the closest pair, by surface area of the union, until at most n clusters remain.
+fn combine(mut clusters: Vec<BvhTree>, n: usize) -> Vec<BvhTree> {
+    if clusters.len() <= n {
+        return clusters;
+    }
+    let mut closest: Vec<usize> = (0..clusters.len()).map(|i| nearest(&clusters, i)).collect();
+    while clusters.len() > n {
+        let (a, b) = (0..clusters.len())
+            .map(|i| (i, closest[i]))
+            .min_by(|x, y| distance(&clusters[x.0], &clusters[x.1]).total_cmp(&distance(&clusters[y.0], &clusters[y.1])))
+            .unwrap();
+        let (lo, hi) = (a.min(b), a.max(b));
+        let last = clusters.len() - 1;
+        let removed = clusters.swap_remove(hi);
+        closest.swap_remove(hi);
+        let kept = std::mem::replace(&mut clusters[lo], BvhTree::new());
+        let mut merged = BvhTree::new();
+        merged.add_node(kept);
+        merged.add_node(removed);
+        clusters[lo] = merged;
+        if clusters.len() == 1 {
+            break;
+        }
+        // Indices pointing at the merged pair are stale, and the last cluster moved into hi.
+        let mut stale = vec![lo];
+        for i in 0..clusters.len() {
+            if closest[i] == lo || closest[i] == hi {
+                stale.push(i);
+            } else if closest[i] == last {
+                closest[i] = hi;
+            }
+        }
+        for i in stale {
+            closest[i] = nearest(&clusters, i);
+        }
+    }
+    clusters
+}
+
+impl BvhForest {
+    // Approximate agglomerative clustering (Gu, He, Fatahalian and Blelloch 2013): trees are sorted
+    // along a Morton curve, bucketed top-down by Morton bits, and clustered bottom-up, with each
+    // subtree reduced to a few clusters before merging with its sibling.
+    pub fn aac(self, config: &AacConfig) -> BvhTree {
+        if self.trees.is_empty() {
+            return BvhTree::new();
+        }
+        let centroids: Bounds<f64> = self.trees.iter().map(|x| Bounds::from(x.centroid())).collect();
+        // A cubic grid, so that thin axes do not get as many Morton bits as long ones.
+        let extent = (0..3).map(|axis| centroids.dim(axis)).fold(0.0, f64::max);
+        let mut coded: Vec<(u32, BvhTree)> = self.trees.into_iter().map(|tree| {
+            let center = tree.centroid();
+            let cell = [0, 1, 2].map(|axis| {
+                let offset = if extent > 0.0 { (center[axis] - centroids.min()[axis]) / extent } else { 0.0 };
+                (offset * 1023.0) as u32
+            });
+            (morton(cell), tree)
+        }).collect();
+        coded.par_sort_by_key(|x| x.0);
+        let clusters = aac_rec(coded, 29, config);
+        combine(clusters, 1).into_iter().next().unwrap()
+    }
+}
+
+fn aac_rec(mut coded: Vec<(u32, BvhTree)>, bit: i32, config: &AacConfig) -> Vec<BvhTree> {
+    let n = coded.len();
+    if n <= config.delta {
+        let clusters = coded.into_iter().map(|x| x.1).collect();
+        return combine(clusters, config.reduction(config.delta));
+    }
+    // Split where the highest bit that differs across the bucket flips; codes are sorted.
+    let mut bit = bit;
+    while bit >= 0 && (coded[0].0 >> bit) & 1 == (coded[n - 1].0 >> bit) & 1 {
+        bit -= 1;
+    }
+    let split = if bit < 0 {
+        n / 2
+    } else {
+        coded.partition_point(|x| (x.0 >> bit) & 1 == 0)
+    };
+    let right = coded.split_off(split);
+    let (mut left, right) = if n >= PARALLEL_THRESHOLD {
+        rayon::join(|| aac_rec(coded, bit - 1, config), || aac_rec(right, bit - 1, config))
+    } else {
+        (aac_rec(coded, bit - 1, config), aac_rec(right, bit - 1, config))
+    };
+    left.extend(right);
+    combine(left, config.reduction(n))
+}
+
+#[test]
+fn test_aac() {
+    use std::sync::Arc;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use crate::geo::indexed_mesh::IndexedMesh;
+    use crate::geo::triangle::Triangle;
+    use crate::math::vec::Vec3;
+    use crate::tree::bvh_sah::SahConfig;
+    let mut rng = SmallRng::seed_from_u64(3);
+    let tris: Vec<_> = (0..2000).map(|_| {
+        let center = Vec3::new(rng.gen_range(0.0..10.0), rng.gen_range(0.0..10.0), rng.gen_range(0.0..1.0));
+        Triangle::new([center, center + Vec3::new(0.2, 0.0, 0.0), center + Vec3::new(0.0, 0.2, 0.0)])
+    }).collect();
+    let mesh = Arc::new(IndexedMesh::from_triangles(tris));
+    let tree = BvhForest::new(&mesh).aac(&AacConfig::default());
+    fn leaves(tree: &BvhTree) -> usize {
+        tree.leaves().len() + tree.nodes().iter().map(leaves).sum::<usize>()
+    }
+    assert_eq!(leaves(&tree), 2000);
+    let config = SahConfig::default();
+    assert!(tree.sah_cost(&config) < BvhForest::new(&mesh).subdivide().sah_cost(&config));
+    assert!(tree.sah_cost(&config) < BvhForest::new(&mesh).contract().sah_cost(&config));
+}
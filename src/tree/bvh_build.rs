@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+use crate::tree::bvh::{BvhForest, BvhTree};
+use crate::tree::bvh_aac::AacConfig;
+use crate::tree::bvh_sah::SahConfig;
+use crate::tree::bvh_sbvh::SbvhConfig;
+use crate::tree::primitive::PrimitiveSet;
+
+// Forests smaller than this are built on the current thread.
+pub const PARALLEL_THRESHOLD: usize = 4096;
+
+#[derive(Copy, Clone, Debug)]
+pub enum BvhStrategy {
+    Subdivide,
+    Contract,
+    Hybrid,
+    Sah(SahConfig),
+    Aac(AacConfig),
+    Sbvh(SbvhConfig),
+}
+
+impl BvhStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BvhStrategy::Subdivide => "subdivide",
+            BvhStrategy::Contract => "contract",
+            BvhStrategy::Hybrid => "hybrid",
+            BvhStrategy::Sah(_) => "sah",
+            BvhStrategy::Aac(_) => "aac",
+            BvhStrategy::Sbvh(_) => "sbvh",
+        }
+    }
+    pub fn all() -> [BvhStrategy; 6] {
+        [
+            BvhStrategy::Subdivide,
+            BvhStrategy::Contract,
+            BvhStrategy::Hybrid,
+            BvhStrategy::Sah(SahConfig::default()),
+            BvhStrategy::Aac(AacConfig::default()),
+            BvhStrategy::Sbvh(SbvhConfig::default()),
+        ]
+    }
+}
+
+impl BvhForest {
+    // Only spatial splits look at the primitives themselves, the rest only at the trees' bounds.
+    pub fn build<S: PrimitiveSet + ?Sized>(self, primitives: &S, strategy: &BvhStrategy) -> BvhTree {
+        match strategy {
+            BvhStrategy::Subdivide => self.subdivide(),
+            BvhStrategy::Contract => self.contract(),
+            BvhStrategy::Hybrid => self.hybrid(),
+            BvhStrategy::Sah(config) => self.sah(config),
+            BvhStrategy::Aac(config) => self.aac(config),
+            BvhStrategy::Sbvh(config) => self.sbvh(primitives, config),
+        }
+    }
+    // Builds and returns the wall-clock build time.
+    pub fn build_timed<S: PrimitiveSet + ?Sized>(self, primitives: &S, strategy: &BvhStrategy) -> (BvhTree, Duration) {
+        let start = Instant::now();
+        let tree = self.build(primitives, strategy);
+        (tree, start.elapsed())
+    }
+}
+
+#[test]
+fn test_parallel_build() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use crate::geo::indexed_mesh::IndexedMesh;
+    use crate::geo::triangle::Triangle;
+    use crate::math::vec::Vec3;
+    let mut rng = SmallRng::seed_from_u64(7);
+    let tris: Vec<_> = (0..6000).map(|_| {
+        let center = Vec3::new(rng.gen_range(0.0..10.0), rng.gen_range(0.0..10.0), rng.gen_range(0.0..10.0));
+        Triangle::new([center, center + Vec3::new(0.1, 0.0, 0.0), center + Vec3::new(0.0, 0.1, 0.0)])
+    }).collect();
+    let mesh = IndexedMesh::from_triangles(tris);
+    fn leaves(tree: &BvhTree, output: &mut Vec<u32>) {
+        output.extend_from_slice(tree.leaves());
+        for node in tree.nodes() {
+            leaves(node, output);
+        }
+    }
+    fn same(a: &BvhTree, b: &BvhTree) -> bool {
+        a.leaves() == b.leaves() && a.nodes().len() == b.nodes().len() && a.nodes().iter().zip(b.nodes()).all(|(a, b)| same(a, b))
+    }
+    let serial = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    // Contract and hybrid pair up trees round by round, which takes seconds unoptimized at this size.
+    for strategy in [BvhStrategy::Subdivide, BvhStrategy::Sah(SahConfig::default()), BvhStrategy::Aac(AacConfig::default()), BvhStrategy::Sbvh(SbvhConfig::default())] {
+        let tree = BvhForest::new(&mesh).build(&mesh, &strategy);
+        let mut references = vec![];
+        leaves(&tree, &mut references);
+        // Spatial splits may reference a triangle from several leaves, up to the duplication budget.
+        let max_references = match &strategy {
+            BvhStrategy::Sbvh(config) => ((1.0 + config.max_duplication) * 6000.0) as usize,
+            _ => 6000,
+        };
+        assert!(references.len() <= max_references, "{} {}", strategy.name(), references.len());
+        let mut distinct = references.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct, (0..6000).collect::<Vec<u32>>(), "{}", strategy.name());
+        let expected = serial.install(|| BvhForest::new(&mesh).build(&mesh, &strategy));
+        assert!(same(&tree, &expected), "{}", strategy.name());
+    }
+}
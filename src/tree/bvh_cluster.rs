@@ -5,7 +5,6 @@ use std::collections::{HashMap, HashSet};
 use std::collections::btree_map::Entry;
 use std::iter::{Peekable, Rev};
 use ordered_float::NotNan;
-use crate::mesh::TriMesh;
 use unordered_pair::UnorderedPair;
 use crate::geo::bounds::Bounds;
 use priority_queue::PriorityQueue;
@@ -13,6 +12,7 @@ use crate::tree::bv_kd_tree::{BvKdEntry, BvKdQuery, BvKdTree};
 use crate::tree::bvh::{BvhForest, BvhTree};
 use crate::tree::kd_tree::KdIter;
 use crate::util::itertools2::{Itertools2, Peeker};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 
 
@@ -46,6 +46,8 @@ impl BvhForest {
         }
         self.trees.into_iter().next().unwrap()
     }
+    // The forest halves with every round, so rebuilding the kd-tree over the merged bounds costs
+    // about twice the first build in total.
     pub fn contract_once(&mut self) {
         let kdtree = BvKdTree::new(
             self.trees.iter().enumerate()
@@ -53,11 +55,15 @@ impl BvhForest {
                 .collect());
         let mut graph = PriorityQueue::<usize, EdgeSet>::new();
         let mut reverse = HashMap::<_, Vec<_>>::new();
-        for (index, tree) in self.trees.iter().enumerate() {
+        // Finding each tree's nearest neighbour dominates, so that part runs in parallel.
+        let edge_sets: Vec<_> = self.trees.par_iter().enumerate().map(|(index, tree)| {
             let mut edge_set = EdgeSet { iter: kdtree.nearest_iter(*tree.bounds()).peeker() };
             if *edge_set.iter.peek().unwrap().entry.value().value() == index {
                 edge_set.iter.next();
             }
+            edge_set
+        }).collect();
+        for (index, edge_set) in edge_sets.into_iter().enumerate() {
             reverse.entry(*edge_set.iter.peek().unwrap().entry.value().value()).or_default().push(index);
             graph.push(index, edge_set);
         }
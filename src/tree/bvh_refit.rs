@@ -0,0 +1,147 @@
+use std::sync::Arc;
+use crate::geo::indexed_mesh::IndexedMesh;
+use crate::geo::vertex_animation::VertexAnimation;
+use crate::tree::bvh::{Bvh, BvhForest};
+use crate::tree::bvh_build::BvhStrategy;
+use crate::tree::bvh_sah::SahConfig;
+use crate::tree::primitive::PrimitiveSet;
+
+#[derive(Copy, Clone, Debug)]
+pub struct RefitConfig {
+    pub strategy: BvhStrategy,
+    // Costs used to judge the refitted tree.
+    pub sah: SahConfig,
+    // Rebuild once the SAH cost grows past this multiple of the cost right after the last build.
+    pub max_degradation: f64,
+}
+
+impl Default for RefitConfig {
+    fn default() -> Self {
+        RefitConfig { strategy: BvhStrategy::Sah(SahConfig::default()), sah: SahConfig::default(), max_degradation: 1.5 }
+    }
+}
+
+// A BVH over primitives that move every frame. Refitting keeps the topology built for earlier
+// positions, which gets worse as the primitives drift apart, so it is rebuilt when too slow. The
+// BVH is shared, so that a scene can trace one frame while the next is prepared.
+pub struct DeformingBvh<S = IndexedMesh> {
+    config: RefitConfig,
+    bvh: Arc<Bvh<f64, S>>,
+    built_cost: f64,
+    rebuilds: usize,
+}
+
+impl<S: PrimitiveSet> DeformingBvh<S> {
+    pub fn new(primitives: Arc<S>, config: RefitConfig) -> Self {
+        let bvh = Arc::new(Self::build(primitives, &config));
+        let built_cost = bvh.sah_cost(&config.sah);
+        DeformingBvh { config, bvh, built_cost, rebuilds: 0 }
+    }
+    fn build(primitives: Arc<S>, config: &RefitConfig) -> Bvh<f64, S> {
+        let tree = BvhForest::new(&primitives).build(&primitives, &config.strategy);
+        Bvh::new(primitives, &tree)
+    }
+    // Moves to the next frame's primitives, and returns whether that took a rebuild. The refit is
+    // in place unless the previous frame's BVH is still shared.
+    pub fn update(&mut self, primitives: Arc<S>) -> bool {
+        Arc::make_mut(&mut self.bvh).refit(primitives);
+        if self.bvh.sah_cost(&self.config.sah) <= self.built_cost * self.config.max_degradation {
+            return false;
+        }
+        self.bvh = Arc::new(Self::build(self.bvh.primitives().clone(), &self.config));
+        self.built_cost = self.bvh.sah_cost(&self.config.sah);
+        self.rebuilds += 1;
+        true
+    }
+    pub fn bvh(&self) -> &Arc<Bvh<f64, S>> { &self.bvh }
+    pub fn rebuilds(&self) -> usize { self.rebuilds }
+}
+
+// A mesh moved by a vertex animation, kept across frames so that its BVH is refitted rather than
+// built for each. Frames loop.
+pub struct AnimatedMesh {
+    rest: IndexedMesh,
+    animation: VertexAnimation,
+    bvh: DeformingBvh,
+    frame: usize,
+}
+
+impl AnimatedMesh {
+    pub fn new(rest: IndexedMesh, animation: VertexAnimation, config: RefitConfig) -> Self {
+        assert!(animation.frame_count() > 0);
+        let bvh = DeformingBvh::new(Arc::new(animation.mesh_at(&rest, 0)), config);
+        AnimatedMesh { rest, animation, bvh, frame: 0 }
+    }
+    // The BVH over the mesh at the given frame.
+    pub fn at(&mut self, frame: usize) -> Arc<Bvh> {
+        let frame = frame % self.animation.frame_count();
+        if frame != self.frame {
+            self.bvh.update(Arc::new(self.animation.mesh_at(&self.rest, frame)));
+            self.frame = frame;
+        }
+        self.bvh.bvh().clone()
+    }
+    pub fn rebuilds(&self) -> usize { self.bvh.rebuilds() }
+}
+
+#[test]
+fn test_refit() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use crate::geo::ray::Ray;
+    use crate::geo::triangle::Sidedness;
+    use crate::geo::vertex_animation::VertexAnimation;
+    use crate::math::vec::Vec3;
+    let n = 20;
+    let grid = (0..n).flat_map(|i| (0..n).map(move |j| Vec3::new(i as f64, j as f64, 0.0))).collect();
+    let indices = (0..n as u32 - 1).flat_map(|i| (0..n as u32 - 1).flat_map(move |j| {
+        let k = i * n as u32 + j;
+        [[k, k + n as u32, k + n as u32 + 1], [k, k + n as u32 + 1, k + 1]]
+    })).collect();
+    let rest = IndexedMesh::new(grid, indices);
+    // A gentle wave, then the grid folded in half so that distant triangles overlap.
+    let wave = rest.positions().iter().map(|p| Vec3::new(0.0, 0.0, (p.x() * 0.5).sin())).collect();
+    let fold = rest.positions().iter().map(|p| Vec3::new(-2.0 * (p.x() - (n / 2) as f64).max(0.0), 0.0, 0.0)).collect();
+    let animation = VertexAnimation::Morph {
+        targets: vec![wave, fold],
+        weights: vec![vec![0.0, 0.0], vec![0.5, 0.0], vec![1.0, 0.0], vec![1.0, 1.0]],
+    };
+    let mut bvh = DeformingBvh::new(Arc::new(rest.clone()), RefitConfig::default());
+    let mut rng = SmallRng::seed_from_u64(19);
+    for frame in 1..animation.frame_count() {
+        let mesh = Arc::new(animation.mesh_at(&rest, frame));
+        let rebuilt = bvh.update(mesh.clone());
+        assert_eq!(rebuilt, frame == 3);
+        for _ in 0..100 {
+            let orig = Vec3::new(rng.gen_range(0.0..n as f64), rng.gen_range(0.0..n as f64), 3.0);
+            let ray = Ray::new(orig, Vec3::new(rng.gen_range(-0.2..0.2), rng.gen_range(-0.2..0.2), -1.0).normalize());
+            let expected = (0..mesh.triangle_count() as u32).filter_map(|i| IndexedMesh::raycast(&mesh, i, &ray, Sidedness::Both, false))
+                .map(|x| x.time).min_by(f64::total_cmp);
+            assert_eq!(bvh.bvh().raycast(&ray, Sidedness::Both, None).map(|x| x.time), expected);
+        }
+    }
+    assert_eq!(bvh.rebuilds(), 1);
+}
+
+#[test]
+fn test_animated_mesh() {
+    use crate::geo::ray::Ray;
+    use crate::geo::transform::Transform;
+    use crate::geo::triangle::{Sidedness, Triangle};
+    use crate::math::vec::Vec3;
+    use crate::render::object::Object;
+    use crate::SceneBuilder;
+    let rest = IndexedMesh::from_triangles([Triangle::new([Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)])]);
+    let lift = rest.positions().iter().map(|_| Vec3::new(0.0, 0.0, 1.0)).collect();
+    let animation = VertexAnimation::Morph { targets: vec![lift], weights: vec![vec![0.0], vec![1.0], vec![2.0]] };
+    let mut mesh = AnimatedMesh::new(rest, animation, RefitConfig::default());
+    let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let first = mesh.at(1);
+    for time in 0..6 {
+        let object = SceneBuilder::new(time).animated_mesh(&mut mesh, Transform::default());
+        assert_eq!(object.raycast(&ray, None).map(|x| x.time), Some(5.0 - (time % 3) as f64));
+    }
+    // Scenes still holding an earlier frame keep it.
+    assert_eq!(first.raycast(&ray, Sidedness::Both, None).map(|x| x.time), Some(4.0));
+    assert_eq!(mesh.rebuilds(), 0);
+}
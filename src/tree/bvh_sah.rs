@@ -0,0 +1,185 @@
+use crate::geo::bounds::Bounds;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use crate::tree::bvh::{BvhForest, BvhTree};
+use crate::tree::bvh_build::PARALLEL_THRESHOLD;
+
+#[derive(Copy, Clone, Debug)]
+pub struct SahConfig {
+    // Buckets per axis that centroids are sorted into when searching for a split.
+    pub bins: usize,
+    pub traversal_cost: f64,
+    pub intersection_cost: f64,
+    // Subtrees at most this large may become a single leaf, and larger ones are always split.
+    pub max_leaf_size: usize,
+}
+
+impl Default for SahConfig {
+    fn default() -> Self {
+        SahConfig { bins: 16, traversal_cost: 1.0, intersection_cost: 1.0, max_leaf_size: 4 }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    bounds: Bounds<f64>,
+    count: usize,
+}
+
+pub(crate) struct Split {
+    pub axis: usize,
+    // Trees in bins below this go left.
+    pub bin: usize,
+    pub cost: f64,
+    pub left: Bounds<f64>,
+    pub right: Bounds<f64>,
+}
+
+fn centroid_bin(config: &SahConfig, centroids: &Bounds<f64>, tree: &BvhTree, axis: usize) -> usize {
+    let offset = (tree.centroid()[axis] - centroids.min()[axis]) / centroids.dim(axis);
+    ((offset * config.bins as f64) as usize).min(config.bins - 1)
+}
+
+impl BvhForest {
+    // Top-down construction choosing, on every axis, the binned split with the lowest
+    // surface area heuristic cost.
+    pub fn sah(self, config: &SahConfig) -> BvhTree {
+        match self.trees.len() {
+            0 => return BvhTree::new(),
+            1 => return self.trees.into_iter().next().unwrap(),
+            _ => {}
+        }
+        let bounds: Bounds<f64> = self.trees.iter().map(|x| *x.bounds()).collect();
+        let leaf_cost = self.trees.len() as f64 * config.intersection_cost;
+        let split = self.best_split(config, &bounds);
+        let make_leaf = self.trees.len() <= config.max_leaf_size
+            && split.as_ref().map_or(true, |split| leaf_cost <= split.cost);
+        if make_leaf {
+            let mut leaf = BvhTree::new();
+            for tree in self.trees {
+                leaf.absorb(tree);
+            }
+            return leaf;
+        }
+        let (left, right) = match split {
+            Some(split) => self.partition(config, split),
+            // All centroids coincide, so any partition is as good as another.
+            None => {
+                let mut left = self;
+                let right = left.trees.split_off(left.trees.len() / 2);
+                (left, BvhForest { trees: right })
+            }
+        };
+        let (left, right) = if left.trees.len() + right.trees.len() >= PARALLEL_THRESHOLD {
+            rayon::join(|| left.sah(config), || right.sah(config))
+        } else {
+            (left.sah(config), right.sah(config))
+        };
+        let mut node = BvhTree::new();
+        node.add_node(left);
+        node.add_node(right);
+        node
+    }
+    fn centroid_bounds(&self) -> Bounds<f64> {
+        self.trees.iter().map(|x| Bounds::from(x.centroid())).collect()
+    }
+    pub(crate) fn best_split(&self, config: &SahConfig, bounds: &Bounds<f64>) -> Option<Split> {
+        let centroids = self.centroid_bounds();
+        let area = bounds.surface_area();
+        let mut best: Option<Split> = None;
+        for axis in 0..3 {
+            if !(centroids.dim(axis) > 0.0) {
+                continue;
+            }
+            let bins = self.bins(config, &centroids, axis);
+            // Sweep from the right to get the cost of each right half, then from the left.
+            let mut right = vec![Bin { bounds: Bounds::empty(), count: 0 }; config.bins];
+            let mut acc = Bin { bounds: Bounds::empty(), count: 0 };
+            for i in (1..config.bins).rev() {
+                acc.bounds = acc.bounds.union(&bins[i].bounds);
+                acc.count += bins[i].count;
+                right[i] = acc;
+            }
+            let mut acc = Bin { bounds: Bounds::empty(), count: 0 };
+            for i in 1..config.bins {
+                acc.bounds = acc.bounds.union(&bins[i - 1].bounds);
+                acc.count += bins[i - 1].count;
+                if acc.count == 0 || right[i].count == 0 {
+                    continue;
+                }
+                let cost = config.traversal_cost + config.intersection_cost
+                    * (acc.bounds.surface_area() * acc.count as f64 + right[i].bounds.surface_area() * right[i].count as f64) / area;
+                if best.as_ref().map_or(true, |best| cost < best.cost) {
+                    best = Some(Split { axis, bin: i, cost, left: acc.bounds, right: right[i].bounds });
+                }
+            }
+        }
+        best
+    }
+    fn bins(&self, config: &SahConfig, centroids: &Bounds<f64>, axis: usize) -> Vec<Bin> {
+        let empty = || vec![Bin { bounds: Bounds::empty(), count: 0 }; config.bins];
+        let add = |mut bins: Vec<Bin>, tree: &BvhTree| {
+            let bin = &mut bins[centroid_bin(config, centroids, tree, axis)];
+            bin.bounds = bin.bounds.union(tree.bounds());
+            bin.count += 1;
+            bins
+        };
+        if self.trees.len() < PARALLEL_THRESHOLD {
+            return self.trees.iter().fold(empty(), add);
+        }
+        self.trees.par_iter().fold(empty, add).reduce(empty, |a, b| {
+            a.into_iter().zip(b).map(|(a, b)| Bin { bounds: a.bounds.union(&b.bounds), count: a.count + b.count }).collect()
+        })
+    }
+    pub(crate) fn partition(self, config: &SahConfig, split: Split) -> (Self, Self) {
+        let centroids = self.centroid_bounds();
+        let (left, right) = self.trees.into_iter()
+            .partition(|tree| centroid_bin(config, &centroids, tree, split.axis) < split.bin);
+        (BvhForest { trees: left }, BvhForest { trees: right })
+    }
+}
+
+impl BvhTree {
+    // Expected cost of tracing a ray that hits the root, assuming the probability of entering a
+    // node is proportional to its surface area.
+    pub fn sah_cost(&self, config: &SahConfig) -> f64 {
+        let area = self.bounds().surface_area();
+        let children: f64 = self.nodes().iter()
+            .map(|child| child.bounds().surface_area() / area * (config.traversal_cost + child.sah_cost(config)))
+            .sum();
+        self.leaves().len() as f64 * config.intersection_cost + children
+    }
+}
+
+#[test]
+fn test_sah() {
+    use std::sync::Arc;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use crate::geo::indexed_mesh::IndexedMesh;
+    use crate::geo::ray::Ray;
+    use crate::geo::triangle::{Sidedness, Triangle};
+    use crate::math::vec::Vec3;
+    use crate::tree::bvh::Bvh;
+    let mut rng = SmallRng::seed_from_u64(5);
+    let point = |rng: &mut SmallRng| Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+    let tris: Vec<_> = (0..500).map(|_| {
+        let center = point(&mut rng) * 10.0;
+        Triangle::new([center + point(&mut rng), center + point(&mut rng), center + point(&mut rng)])
+    }).collect();
+    let mesh = Arc::new(IndexedMesh::from_triangles(tris));
+    let config = SahConfig::default();
+    let tree = BvhForest::new(&mesh).sah(&config);
+    fn check(tree: &BvhTree, config: &SahConfig) -> usize {
+        assert!(tree.leaves().len() <= config.max_leaf_size);
+        tree.leaves().len() + tree.nodes().iter().map(|x| check(x, config)).sum::<usize>()
+    }
+    assert_eq!(check(&tree, &config), 500);
+    assert!(tree.sah_cost(&config) < BvhForest::new(&mesh).subdivide().sah_cost(&config));
+    let bvh = Bvh::new(mesh.clone(), &tree);
+    for _ in 0..200 {
+        let ray = Ray::new(point(&mut rng) * 12.0, point(&mut rng).normalize());
+        let expected = (0..500).filter_map(|i| mesh.raycast(i, &ray, Sidedness::Both, false))
+            .map(|x| x.time).min_by(f64::total_cmp);
+        assert_eq!(bvh.raycast(&ray, Sidedness::Both, None).map(|x| x.time), expected);
+    }
+}
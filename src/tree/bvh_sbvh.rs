@@ -0,0 +1,252 @@
+use crate::geo::bounds::Bounds;
+use crate::geo::indexed_mesh::IndexedMesh;
+use crate::geo::triangle::Triangle;
+use crate::math::vec::Vec3;
+use crate::tree::bvh::{BvhForest, BvhTree};
+use crate::tree::bvh_sah::SahConfig;
+use crate::tree::bvh_build::PARALLEL_THRESHOLD;
+use crate::tree::primitive::PrimitiveSet;
+
+#[derive(Copy, Clone, Debug)]
+pub struct SbvhConfig {
+    pub sah: SahConfig,
+    // Spatial splits are only tried where the children of the best object split overlap by more
+    // than this fraction of the root's surface area, which bounds the number of references.
+    pub alpha: f64,
+    // Extra references spatial splits may add, as a fraction of the number of primitives.
+    pub max_duplication: f64,
+    pub max_depth: usize,
+}
+
+impl Default for SbvhConfig {
+    fn default() -> Self {
+        SbvhConfig { sah: SahConfig::default(), alpha: 1e-5, max_duplication: 1.0, max_depth: 64 }
+    }
+}
+
+struct SpatialSplit {
+    axis: usize,
+    position: f64,
+    cost: f64,
+}
+
+// Bounds of the part of the triangle between lo and hi along axis.
+pub(crate) fn clip_triangle(triangle: &Triangle<f64>, axis: usize, lo: f64, hi: f64) -> Bounds<f64> {
+    let vertices = *triangle.vertices();
+    let mut bounds = Bounds::empty();
+    for i in 0..3 {
+        let (a, b) = (vertices[i], vertices[(i + 1) % 3]);
+        if (lo..=hi).contains(&a[axis]) {
+            bounds = bounds.union(&Bounds::from(a));
+        }
+        // Where the edge crosses either plane.
+        for plane in [lo, hi] {
+            if (a[axis] < plane) != (b[axis] < plane) && a[axis] != b[axis] {
+                let t = (plane - a[axis]) / (b[axis] - a[axis]);
+                let mut p = a + (b - a) * t;
+                p[axis] = plane;
+                bounds = bounds.union(&Bounds::from(p));
+            }
+        }
+    }
+    bounds
+}
+
+fn leaf_of(trees: Vec<BvhTree>) -> BvhTree {
+    let mut leaf = BvhTree::new();
+    for tree in trees {
+        leaf.absorb(tree);
+    }
+    leaf
+}
+
+impl BvhForest {
+    // SAH construction that may also split space, clipping the primitives that straddle the
+    // plane into both children (Stich, Friedrich and Dietrich 2009). Each tree in the forest must
+    // be a single leaf of primitives; a primitive can then be referenced by several leaves.
+    pub fn sbvh<S: PrimitiveSet + ?Sized>(self, primitives: &S, config: &SbvhConfig) -> BvhTree {
+        let root_area = self.trees.iter().map(|x| *x.bounds()).collect::<Bounds<f64>>().surface_area();
+        let budget = (self.trees.len() as f64 * config.max_duplication) as usize;
+        self.sbvh_rec(primitives, config, root_area, budget, 0)
+    }
+    // Budget is the number of references spatial splits may still add within this subtree.
+    fn sbvh_rec<S: PrimitiveSet + ?Sized>(self, primitives: &S, config: &SbvhConfig, root_area: f64, budget: usize, depth: usize) -> BvhTree {
+        let sah = &config.sah;
+        match self.trees.len() {
+            0 => return BvhTree::new(),
+            1 => return self.trees.into_iter().next().unwrap(),
+            _ => {}
+        }
+        if depth >= config.max_depth {
+            return leaf_of(self.trees);
+        }
+        let bounds: Bounds<f64> = self.trees.iter().map(|x| *x.bounds()).collect();
+        let object = self.best_split(sah, &bounds);
+        let overlap = object.as_ref().map_or(bounds.surface_area(), |split| {
+            let overlap = split.left.intersect(&split.right);
+            if overlap.is_empty() { 0.0 } else { overlap.surface_area() }
+        });
+        let spatial = if overlap / root_area > config.alpha {
+            self.best_spatial_split(primitives, sah, &bounds, budget)
+        } else {
+            None
+        };
+        let object_cost = object.as_ref().map_or(f64::INFINITY, |x| x.cost);
+        let spatial_cost = spatial.as_ref().map_or(f64::INFINITY, |x| x.cost);
+        let leaf_cost = self.trees.len() as f64 * sah.intersection_cost;
+        if self.trees.len() <= sah.max_leaf_size && leaf_cost <= object_cost.min(spatial_cost) {
+            return leaf_of(self.trees);
+        }
+        let count = self.trees.len();
+        let (left, right) = match (spatial, object) {
+            (Some(spatial), _) if spatial_cost < object_cost => self.spatial_partition(primitives, spatial),
+            (_, Some(object)) => self.partition(sah, object),
+            _ => {
+                let mut left = self;
+                let right = left.trees.split_off(left.trees.len() / 2);
+                (left, BvhForest { trees: right })
+            }
+        };
+        // What is left of the budget is shared in proportion to the children's sizes.
+        let budget = budget.saturating_sub(left.trees.len() + right.trees.len() - count);
+        let left_budget = budget * left.trees.len() / (left.trees.len() + right.trees.len());
+        let build = |forest: BvhForest, budget| forest.sbvh_rec(primitives, config, root_area, budget, depth + 1);
+        let (left, right) = if left.trees.len() + right.trees.len() >= PARALLEL_THRESHOLD {
+            rayon::join(|| build(left, left_budget), || build(right, budget - left_budget))
+        } else {
+            (build(left, left_budget), build(right, budget - left_budget))
+        };
+        let mut node = BvhTree::new();
+        node.add_node(left);
+        node.add_node(right);
+        node
+    }
+    fn best_spatial_split<S: PrimitiveSet + ?Sized>(&self, primitives: &S, sah: &SahConfig, bounds: &Bounds<f64>, budget: usize) -> Option<SpatialSplit> {
+        let area = bounds.surface_area();
+        let mut best: Option<SpatialSplit> = None;
+        for axis in 0..3 {
+            let (min, width) = (bounds.min()[axis], bounds.dim(axis));
+            if !(width > 0.0) {
+                continue;
+            }
+            let n = sah.bins;
+            let plane = |i: usize| if i == n { bounds.max()[axis] } else { min + width * i as f64 / n as f64 };
+            let bin_of = |x: f64| (((x - min) / width * n as f64) as usize).min(n - 1);
+            let mut bins = vec![Bounds::empty(); n];
+            let mut entries = vec![0; n];
+            let mut exits = vec![0; n];
+            for tree in self.trees.iter() {
+                let reference = tree.bounds();
+                let (first, last) = (bin_of(reference.min()[axis]), bin_of(reference.max()[axis]));
+                entries[first] += 1;
+                exits[last] += 1;
+                for bin in first..=last {
+                    let clipped = if first == last {
+                        *reference
+                    } else {
+                        primitives.clip(tree.leaves()[0], axis, plane(bin), plane(bin + 1), reference)
+                    };
+                    bins[bin] = bins[bin].union(&clipped);
+                }
+            }
+            let mut right = vec![(Bounds::empty(), 0); n];
+            let mut acc = (Bounds::empty(), 0);
+            for i in (1..n).rev() {
+                acc = (acc.0.union(&bins[i]), acc.1 + exits[i]);
+                right[i] = acc;
+            }
+            let mut acc = (Bounds::empty(), 0);
+            for i in 1..n {
+                acc = (acc.0.union(&bins[i - 1]), acc.1 + entries[i - 1]);
+                let (right_bounds, right_count) = right[i];
+                // The binned counts can only overestimate the references the partition makes.
+                if acc.1 == 0 || right_count == 0 || acc.1 + right_count - self.trees.len() > budget {
+                    continue;
+                }
+                let cost = sah.traversal_cost + sah.intersection_cost
+                    * (acc.0.surface_area() * acc.1 as f64 + right_bounds.surface_area() * right_count as f64) / area;
+                if best.as_ref().map_or(true, |best| cost < best.cost) {
+                    best = Some(SpatialSplit { axis, position: plane(i), cost });
+                }
+            }
+        }
+        best
+    }
+    fn spatial_partition<S: PrimitiveSet + ?Sized>(self, primitives: &S, split: SpatialSplit) -> (Self, Self) {
+        let (axis, position) = (split.axis, split.position);
+        let (mut left, mut right) = (vec![], vec![]);
+        for tree in self.trees {
+            let reference = *tree.bounds();
+            if reference.min()[axis] >= position {
+                right.push(tree);
+            } else if reference.max()[axis] <= position {
+                left.push(tree);
+            } else {
+                let triangle = tree.leaves()[0];
+                for (side, lo, hi) in [(&mut left, f64::NEG_INFINITY, position), (&mut right, position, f64::INFINITY)] {
+                    let clipped = primitives.clip(triangle, axis, lo, hi, &reference);
+                    if !clipped.is_empty() {
+                        let mut reference = BvhTree::new();
+                        reference.add_leaf(triangle, clipped);
+                        side.push(reference);
+                    }
+                }
+            }
+        }
+        (BvhForest { trees: left }, BvhForest { trees: right })
+    }
+}
+
+#[test]
+fn test_sbvh() {
+    use std::sync::Arc;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use crate::geo::ray::Ray;
+    use crate::geo::triangle::{Sidedness, Triangle};
+    use crate::tree::bvh::Bvh;
+    let mut rng = SmallRng::seed_from_u64(11);
+    // Long diagonal slivers, whose boxes overlap heavily.
+    let tris: Vec<_> = (0..300).map(|_| {
+        let start = Vec3::new(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0), rng.gen_range(0.0..10.0));
+        let end = start + Vec3::new(10.0, 10.0, 0.0);
+        Triangle::new([start, end, end + Vec3::new(0.0, 0.05, 0.05)])
+    }).collect();
+    let mesh = Arc::new(IndexedMesh::from_triangles(tris));
+    let config = SbvhConfig::default();
+    let tree = BvhForest::new(&mesh).sbvh(&mesh, &config);
+    let sah_tree = BvhForest::new(&mesh).sah(&config.sah);
+    assert!(tree.sah_cost(&config.sah) < sah_tree.sah_cost(&config.sah));
+    let bvh = Bvh::new(mesh.clone(), &tree);
+    for _ in 0..300 {
+        let orig = Vec3::new(rng.gen_range(0.0..11.0), rng.gen_range(0.0..11.0), -1.0);
+        let ray = Ray::new(orig, Vec3::new(rng.gen_range(-0.3..0.3), rng.gen_range(-0.3..0.3), 1.0).normalize());
+        let expected = (0..300).filter_map(|i| IndexedMesh::raycast(&mesh, i, &ray, Sidedness::Both, false).map(|x| (x.time, i)))
+            .min_by(|x, y| x.0.total_cmp(&y.0));
+        let actual = bvh.raycast(&ray, Sidedness::Both, None);
+        assert_eq!(actual.as_ref().map(|x| x.time), expected.map(|x| x.0));
+        if let Some(actual) = actual {
+            assert_eq!(actual.manifold.pop().unwrap().1, expected.unwrap().1 as usize);
+        }
+    }
+}
+
+#[test]
+fn test_sbvh_references() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use crate::geo::triangle::Triangle;
+    let mut rng = SmallRng::seed_from_u64(12);
+    // Slivers all spanning the same long range, so that every spatial split along it straddles
+    // all of them.
+    let tris: Vec<_> = (0..64).map(|_| {
+        let start = Vec3::new(rng.gen_range(0.0..0.01), rng.gen_range(0.0..0.01), rng.gen_range(0.0..0.01));
+        Triangle::new([start, start + Vec3::new(100.0, 0.0, 0.0), start + Vec3::new(100.0, 0.01, 0.01)])
+    }).collect();
+    let mesh = IndexedMesh::from_triangles(tris);
+    let tree = BvhForest::new(&mesh).sbvh(&mesh, &SbvhConfig::default());
+    fn references(tree: &BvhTree) -> usize {
+        tree.leaves().len() + tree.nodes().iter().map(references).sum::<usize>()
+    }
+    assert!(references(&tree) <= 2 * 64, "{}", references(&tree));
+}
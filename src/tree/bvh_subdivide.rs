@@ -1,8 +1,10 @@
 use std::default::default;
 use std::mem;
 use ordered_float::NotNan;
+use rayon::slice::ParallelSliceMut;
 use crate::geo::bounds::Bounds;
 use crate::tree::bvh::{BvhForest, BvhTree};
+use crate::tree::bvh_build::PARALLEL_THRESHOLD;
 use crate::util::itertools2::Itertools2;
 
 impl BvhForest {
@@ -13,9 +15,14 @@ impl BvhForest {
             return self.trees.into_iter().next().unwrap()
         }
         let (left, right) = self.subdivide_once();
+        let (left, right) = if left.trees.len() + right.trees.len() >= PARALLEL_THRESHOLD {
+            rayon::join(|| left.subdivide(), || right.subdivide())
+        } else {
+            (left.subdivide(), right.subdivide())
+        };
         let mut node = BvhTree::new();
-        node.add_node(left.subdivide());
-        node.add_node(right.subdivide());
+        node.add_node(left);
+        node.add_node(right);
         node
     }
     pub fn subdivide_once(mut self) -> (Self, Self) {
@@ -25,10 +32,10 @@ impl BvhForest {
             .max_by_key(|(i, d)| *d)
             .unwrap().0;
         let split_coordinate = bounds.min()[axis] + bounds.dim(axis) / 2.0;
-        self.trees.sort_by_key(|x| NotNan::new(x.bounds().center()[axis]).unwrap());
+        self.trees.par_sort_by_key(|x| NotNan::new(x.centroid()[axis]).unwrap());
         let mut split = self.trees.binary_search_by_key(
             &NotNan::try_from(split_coordinate).unwrap(),
-            |x| NotNan::try_from(x.bounds().center()[axis]).unwrap()).map_or_else(|x| x, |x| x);
+            |x| NotNan::try_from(x.centroid()[axis]).unwrap()).map_or_else(|x| x, |x| x);
         const CLAMP: f64 = 0.01;
         let min_split = (((self.trees.len() as f64) * CLAMP) as usize).max(1);
         let max_split = self.trees.len() - min_split;
@@ -0,0 +1,298 @@
+use std::sync::Arc;
+use crate::geo::bounds::{Bounds, Interval};
+use crate::geo::indexed_mesh::IndexedMesh;
+use crate::geo::ray::Ray;
+use crate::geo::triangle::Sidedness;
+use crate::math::scalar::{gamma, Scalar};
+use crate::render::object::{Manifold, RaycastPoint, RaycastPointHolder};
+use crate::tree::bvh::BvhTree;
+use crate::tree::primitive::PrimitiveSet;
+
+#[derive(Copy, Clone, Debug)]
+enum WideChild {
+    Empty,
+    Node(u32),
+    // A range of the leaves array.
+    Leaves(u32, u32),
+}
+
+// Child bounds are stored as structure of arrays, one lane per child, so that a ray can be tested
+// against all children at once.
+struct WideNode<const W: usize> {
+    min: [[f64; W]; 3],
+    max: [[f64; W]; 3],
+    children: [WideChild; W],
+}
+
+// A BVH with up to W children per node, collapsed from a binary (or any) BvhTree.
+pub struct WideBvh<const W: usize, S = IndexedMesh> {
+    primitives: Arc<S>,
+    nodes: Vec<WideNode<W>>,
+    leaves: Vec<u32>,
+}
+
+enum Slot<'a> {
+    Tree(&'a BvhTree),
+    Leaves(&'a [u32], Bounds<f64>),
+    // Children that did not fit into one node, to be given a node of their own.
+    Group(Vec<Slot<'a>>, Bounds<f64>),
+}
+
+impl<'a> Slot<'a> {
+    fn bounds(&self) -> Bounds<f64> {
+        match self {
+            Slot::Tree(tree) => *tree.bounds(),
+            Slot::Leaves(_, bounds) | Slot::Group(_, bounds) => *bounds,
+        }
+    }
+    fn width(&self) -> Option<usize> {
+        match self {
+            Slot::Tree(tree) => Some(tree.nodes().len() + (!tree.leaves().is_empty()) as usize),
+            Slot::Group(slots, _) => Some(slots.len()),
+            Slot::Leaves(..) => None,
+        }
+    }
+    fn open<S: PrimitiveSet>(self, primitives: &S) -> Vec<Slot<'a>> {
+        match self {
+            Slot::Tree(tree) => {
+                let mut slots: Vec<_> = tree.nodes().iter().map(|node| {
+                    if node.nodes().is_empty() {
+                        Slot::Leaves(node.leaves(), *node.bounds())
+                    } else {
+                        Slot::Tree(node)
+                    }
+                }).collect();
+                if !tree.leaves().is_empty() {
+                    let bounds = tree.leaves().iter().map(|&x| primitives.bounds(x)).collect();
+                    slots.push(Slot::Leaves(tree.leaves(), bounds));
+                }
+                slots
+            }
+            Slot::Group(slots, _) => slots,
+            Slot::Leaves(..) => vec![self],
+        }
+    }
+}
+
+impl<const W: usize, S: PrimitiveSet> WideBvh<W, S> {
+    pub fn new(primitives: Arc<S>, tree: &BvhTree) -> Self {
+        let mut bvh = WideBvh { primitives, nodes: vec![], leaves: vec![] };
+        let slots = if tree.nodes().is_empty() && !tree.leaves().is_empty() {
+            vec![Slot::Leaves(tree.leaves(), *tree.bounds())]
+        } else {
+            Slot::Tree(tree).open(&*bvh.primitives)
+        };
+        bvh.add_node(slots);
+        bvh
+    }
+    fn add_node(&mut self, mut slots: Vec<Slot>) -> u32 {
+        // Repeatedly replace the child with the largest surface area by its own children, as long
+        // as they fit.
+        loop {
+            let best = slots.iter().enumerate()
+                .filter(|(_, slot)| slot.width().map_or(false, |width| slots.len() - 1 + width <= W))
+                .max_by(|(_, x), (_, y)| x.bounds().surface_area().total_cmp(&y.bounds().surface_area()))
+                .map(|(index, _)| index);
+            match best {
+                Some(index) => {
+                    let slot = slots.swap_remove(index);
+                    let opened = slot.open(&*self.primitives);
+                    slots.extend(opened);
+                }
+                None => break,
+            }
+        }
+        while slots.len() > W {
+            let size = (slots.len() + W - 1) / W;
+            let mut groups = vec![];
+            while !slots.is_empty() {
+                let group: Vec<_> = slots.drain(..size.min(slots.len())).collect();
+                let bounds = group.iter().map(|x| x.bounds()).collect();
+                groups.push(Slot::Group(group, bounds));
+            }
+            slots = groups;
+        }
+        let index = self.nodes.len();
+        self.nodes.push(WideNode {
+            min: [[f64::INFINITY; W]; 3],
+            max: [[f64::NEG_INFINITY; W]; 3],
+            children: [WideChild::Empty; W],
+        });
+        for (lane, slot) in slots.into_iter().enumerate() {
+            let bounds = slot.bounds();
+            let child = match slot {
+                Slot::Leaves(leaves, _) => {
+                    let start = self.leaves.len() as u32;
+                    self.leaves.extend_from_slice(leaves);
+                    WideChild::Leaves(start, self.leaves.len() as u32)
+                }
+                slot => {
+                    let opened = slot.open(&*self.primitives);
+                    WideChild::Node(self.add_node(opened))
+                }
+            };
+            let node = &mut self.nodes[index];
+            for axis in 0..3 {
+                node.min[axis][lane] = bounds.min()[axis];
+                node.max[axis][lane] = bounds.max()[axis];
+            }
+            node.children[lane] = child;
+        }
+        index as u32
+    }
+    pub fn raycast<T: Scalar>(&self, ray: &Ray<T>, sidedness: Sidedness, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
+        if let Some(manifold) = manifold {
+            let (inner, index) = manifold.pop().unwrap();
+            let point = self.primitives.raycast(index as u32, ray, sidedness, Some(inner))?;
+            Some(RaycastPoint { manifold: point.manifold.push(index), ..point })
+        } else {
+            let mut holder = RaycastPointHolder::new();
+            self.raycast_rec(ray, sidedness, 0, &mut holder);
+            holder.into_point()
+        }
+    }
+    fn raycast_rec<T: Scalar>(&self, ray: &Ray<T>, sidedness: Sidedness, node: u32, output: &mut RaycastPointHolder<T>) {
+        let node = &self.nodes[node as usize];
+        let interval = output.interval();
+        let hits = if T::is_plain() { node.hits(ray, &interval) } else { node.hits_scalar(ray, &interval) };
+        for lane in 0..W {
+            if !hits[lane] {
+                continue;
+            }
+            match node.children[lane] {
+                WideChild::Empty => {}
+                WideChild::Node(child) => self.raycast_rec(ray, sidedness, child, output),
+                WideChild::Leaves(start, end) => {
+                    for &primitive in &self.leaves[start as usize..end as usize] {
+                        output.add(self.primitives.raycast(primitive, ray, sidedness, None).map(|point| {
+                            RaycastPoint { manifold: point.manifold.push(primitive as usize), ..point }
+                        }))
+                    }
+                }
+            }
+        }
+    }
+    // Any-hit query, as Bvh::occluded.
+    pub fn occluded(&self, ray: &Ray<f64>, sidedness: Sidedness, max_time: f64, ignore_transmissive: bool) -> bool {
+        let interval = Interval::new(0.0, max_time);
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node as usize];
+            let hits = node.hits(ray, &interval);
+            for lane in 0..W {
+                if !hits[lane] {
+                    continue;
+                }
+                match node.children[lane] {
+                    WideChild::Empty => {}
+                    WideChild::Node(child) => stack.push(child),
+                    WideChild::Leaves(start, end) => {
+                        for &primitive in &self.leaves[start as usize..end as usize] {
+                            if self.primitives.occluded(primitive, ray, sidedness, max_time, ignore_transmissive) {
+                                return true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+    pub fn primitives(&self) -> &Arc<S> { &self.primitives }
+    pub fn node_count(&self) -> usize { self.nodes.len() }
+    pub fn bounds(&self) -> Bounds<f64> {
+        let root = &self.nodes[0];
+        (0..W).map(|lane| Bounds::new(
+            [0, 1, 2].map(|axis| root.min[axis][lane]).into(),
+            [0, 1, 2].map(|axis| root.max[axis][lane]).into(),
+        )).collect()
+    }
+}
+
+impl<const W: usize> WideNode<W> {
+    // Slab test of every lane in f64, written lane by lane so that it vectorizes.
+    fn hits<T: Scalar>(&self, ray: &Ray<T>, interval: &Interval<T>) -> [bool; W] {
+        let mut near = [interval.min().into_const(); W];
+        let mut far = [interval.max().into_const(); W];
+        // Multiplying by the inverse rounds differently from dividing, so leave some slack, as in
+        // Bounds::raycast_inverse.
+        let slack = 2.0 * gamma::<f64>(3);
+        for axis in 0..3 {
+            let orig = ray.orig()[axis].into_const();
+            let inv_dir = 1.0 / ray.dir()[axis].into_const();
+            // Like Bounds::raycast, axes the ray runs parallel to are not tested.
+            if !inv_dir.is_finite() {
+                continue;
+            }
+            for lane in 0..W {
+                let a = (self.min[axis][lane] - orig) * inv_dir;
+                let b = (self.max[axis][lane] - orig) * inv_dir;
+                near[lane] = near[lane].max(a.min(b));
+                let exit = a.max(b);
+                far[lane] = far[lane].min(exit + exit.abs() * slack);
+            }
+        }
+        let mut hits = [false; W];
+        for lane in 0..W {
+            hits[lane] = near[lane] < far[lane];
+        }
+        hits
+    }
+    // For scalars such as derivatives and intervals that must go through their own arithmetic.
+    fn hits_scalar<T: Scalar>(&self, ray: &Ray<T>, interval: &Interval<T>) -> [bool; W] {
+        let mut hits = [false; W];
+        for lane in 0..W {
+            if let WideChild::Empty = self.children[lane] {
+                continue;
+            }
+            let bounds = Bounds::new(
+                [0, 1, 2].map(|axis| self.min[axis][lane]).into(),
+                [0, 1, 2].map(|axis| self.max[axis][lane]).into(),
+            );
+            hits[lane] = bounds.convert::<T>().raycast(ray)
+                .map_or(false, |times| times.intersect(interval).is_some());
+        }
+        hits
+    }
+}
+
+#[test]
+fn test_wide_bvh() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use crate::geo::triangle::Triangle;
+    use crate::math::scalar::Der;
+    use crate::math::vec::Vec3;
+    use crate::tree::bvh::{Bvh, BvhForest};
+    let mut rng = SmallRng::seed_from_u64(7);
+    let point = |rng: &mut SmallRng| Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+    let tris: Vec<_> = (0..400).map(|_| {
+        let center = point(&mut rng) * 10.0;
+        Triangle::new([center + point(&mut rng), center + point(&mut rng), center + point(&mut rng)])
+    }).collect();
+    let mesh = Arc::new(IndexedMesh::from_triangles(tris));
+    let tree = BvhForest::new(&mesh).subdivide();
+    let bvh = Bvh::new(mesh.clone(), &tree);
+    let wide4 = WideBvh::<4>::new(mesh.clone(), &tree);
+    let wide8 = WideBvh::<8>::new(mesh.clone(), &tree);
+    assert!(wide8.node_count() < wide4.node_count());
+    // Collapsing the topology read back from the binary BVH gives the same tree.
+    let collapsed = WideBvh::<4>::new(mesh.clone(), &bvh.tree());
+    assert_eq!(collapsed.node_count(), wide4.node_count());
+    for axis in 0..3 {
+        assert_eq!(wide4.bounds().min()[axis], bvh.bounds().min()[axis]);
+        assert_eq!(wide4.bounds().max()[axis], bvh.bounds().max()[axis]);
+    }
+    for _ in 0..200 {
+        let ray = Ray::new(point(&mut rng) * 12.0, point(&mut rng).normalize());
+        let expected = bvh.raycast(&ray, Sidedness::Both, None);
+        for actual in [wide4.raycast(&ray, Sidedness::Both, None), wide8.raycast(&ray, Sidedness::Both, None), collapsed.raycast(&ray, Sidedness::Both, None)] {
+            assert_eq!(actual.as_ref().map(|x| (x.time, x.manifold)), expected.as_ref().map(|x| (x.time, x.manifold)));
+        }
+        let max_time = rng.gen_range(0.0..20.0);
+        assert_eq!(wide4.occluded(&ray, Sidedness::Both, max_time, false), bvh.occluded(&ray, Sidedness::Both, max_time, false));
+        let ray = Ray::new(ray.orig().map(|x| Der::<1, f64>::from(x)), ray.dir().map(|x| Der::<1, f64>::from(x)));
+        let actual = wide4.raycast(&ray, Sidedness::Both, None);
+        assert_eq!(actual.map(|x| x.time.into_const()), expected.map(|x| x.time));
+    }
+}
@@ -13,55 +13,60 @@ use rand::SeedableRng;
 use crate::geo::axis_plane::{AxisPlane};
 use crate::math::scalar::Scalar;
 use crate::math::scalar_key::ScalarKey;
+use crate::tree::bvh_build::PARALLEL_THRESHOLD;
 use crate::tree::seq_tree::{SeqTree, SeqTreeView, SeqTreeViewMut};
 
+// Positions and node bounds are stored at precision P and rounded to it on insertion; queries and
+// distances are in f64.
 #[derive(Copy, Clone, Debug)]
-pub struct KdEntry<T> {
+pub struct KdEntry<T, P = f64> {
     axis: u8,
-    bounds: Bounds<f64>,
-    position: Vector<3, f64>,
+    bounds: Bounds<P>,
+    position: Vector<3, P>,
     value: T,
 }
 
-pub struct KdTree<T> {
-    entries: Vec<KdEntry<T>>,
+pub struct KdTree<T, P = f64> {
+    entries: Vec<KdEntry<T, P>>,
 }
 
 #[derive(Debug, Copy, Clone)]
-pub struct KdNeighbor<'a, T> {
+pub struct KdNeighbor<'a, T, P = f64> {
     pub distance: f64,
-    pub entry: &'a KdEntry<T>,
+    pub entry: &'a KdEntry<T, P>,
 }
 
 #[derive(Debug)]
-enum KdIterNode<'a, T> {
-    KdEntry(&'a KdEntry<T>),
-    KdNode(&'a SeqTree<KdEntry<T>>),
+enum KdIterNode<'a, T, P> {
+    KdEntry(&'a KdEntry<T, P>),
+    KdNode(&'a SeqTree<KdEntry<T, P>>),
 }
 
 #[derive(Debug)]
-pub struct KdIter<'a, T, Q> {
+pub struct KdIter<'a, T, Q, P = f64> {
     query: Q,
-    heap: BinaryHeap<Reverse<ScalarKey<f64, KdIterNode<'a, T>>>>,
+    heap: BinaryHeap<Reverse<ScalarKey<f64, KdIterNode<'a, T, P>>>>,
 }
 
 pub trait KdQuery<T>: Debug {
     fn center(&self) -> Vec3<f64>;
-    fn distance(&self, node: &KdEntry<T>) -> f64;
+    fn distance<P: Scalar>(&self, node: &KdEntry<T, P>) -> f64;
     fn min_distance(&self, space: AxisPlane) -> f64;
     fn min_distance_bounds(&self, bounds: &Bounds<f64>) -> f64;
 }
 
 impl<T> KdQuery<T> for Vec3<f64> {
     fn center(&self) -> Vec3<f64> { *self }
-    fn distance(&self, node: &KdEntry<T>) -> f64 { Vec3::distance(*self, node.position) }
+    fn distance<P: Scalar>(&self, node: &KdEntry<T, P>) -> f64 { Vec3::distance(*self, node.position()) }
     fn min_distance(&self, plane: AxisPlane) -> f64 { (self[plane.axis] - plane.coordinate).abs() }
     fn min_distance_bounds(&self, bounds: &Bounds<f64>) -> f64 { bounds.distance(*self) }
 }
 
-impl<T> KdEntry<T> {
-    pub fn new(position: Vec3<f64>, value: T) -> Self { KdEntry { axis: 0, bounds: Bounds::new(Vec3::nan(), Vec3::nan()), position, value } }
-    pub fn position(&self) -> Vec3<f64> { self.position }
+impl<T, P: Scalar> KdEntry<T, P> {
+    pub fn new(position: Vec3<f64>, value: T) -> Self {
+        KdEntry { axis: 0, bounds: Bounds::new(Vec3::nan(), Vec3::nan()), position: position.map(P::from), value }
+    }
+    pub fn position(&self) -> Vec3<f64> { self.position.map(|x| x.into_const()) }
     pub fn value(&self) -> &T { &self.value }
 }
 
@@ -70,67 +75,73 @@ pub struct Stats {
     explored: BTreeMap<usize, usize>,
 }
 
-impl<T> KdTree<T> {
-    pub fn new(mut entries: Vec<KdEntry<T>>) -> Self {
+impl<T, P: Scalar + Send> KdTree<T, P> {
+    pub fn new(mut entries: Vec<KdEntry<T, P>>) -> Self where T: Send {
         let mut result = KdTree { entries };
         Self::build_rec(result.as_slice_mut());
         result
     }
-    pub fn build_rec(tree: &mut SeqTree<KdEntry<T>>) -> Bounds<f64> {
-        let bounds: Bounds<f64> = tree.iter().map(|x| Bounds::from(x.position)).collect();
+    // The bounds are of the rounded positions, so they hold exactly at P too.
+    pub fn build_rec(tree: &mut SeqTree<KdEntry<T, P>>) -> Bounds<f64> where T: Send {
+        let bounds: Bounds<f64> = tree.iter().map(|x| Bounds::from(x.position())).collect();
         let axis = (0..3u8).max_by_key(|i| NotNan::try_from(bounds.dim(*i as usize)).unwrap()).unwrap();
-        tree.build(|x| NotNan::try_from(x.position[axis as usize]).unwrap());
+        tree.build(|x| NotNan::try_from(x.position()[axis as usize]).unwrap());
         let mut bounds = Bounds::empty();
         match tree.as_view_mut() {
             SeqTreeViewMut::Node { mut left, center, mut right } => {
                 center.axis = axis;
-                bounds = bounds.union(&Self::build_rec(left));
-                bounds = bounds.union(&Self::build_rec(right));
-                bounds = bounds.union(&Bounds::new(center.position, center.position));
-                center.bounds = bounds
+                let (left, right) = if left.len() + right.len() >= PARALLEL_THRESHOLD {
+                    rayon::join(|| Self::build_rec(left), || Self::build_rec(right))
+                } else {
+                    (Self::build_rec(left), Self::build_rec(right))
+                };
+                bounds = bounds.union(&left);
+                bounds = bounds.union(&right);
+                bounds = bounds.union(&Bounds::from(center.position()));
+                center.bounds = bounds.convert()
             }
             SeqTreeViewMut::Empty => {}
         }
         bounds
     }
-    pub fn as_slice(&self) -> &SeqTree<KdEntry<T>> { SeqTree::new(self.entries.as_slice()) }
-    pub fn as_slice_mut(&mut self) -> &mut SeqTree<KdEntry<T>> { SeqTree::new_mut(self.entries.as_mut_slice()) }
+    pub fn as_slice(&self) -> &SeqTree<KdEntry<T, P>> { SeqTree::new(self.entries.as_slice()) }
+    pub fn as_slice_mut(&mut self) -> &mut SeqTree<KdEntry<T, P>> { SeqTree::new_mut(self.entries.as_mut_slice()) }
     fn assert_kd_tree(&self) {
         Self::assert_kd_tree_rec(self.as_slice())
     }
-    fn assert_kd_tree_rec(tree: &SeqTree<KdEntry<T>>) {
+    fn assert_kd_tree_rec(tree: &SeqTree<KdEntry<T, P>>) {
         match tree.as_view() {
             SeqTreeView::Node { left, center, right } => {
                 let axis = center.axis as usize;
                 for x in left.as_ref() {
-                    assert!(x.position[axis] <= center.position[axis]);
+                    assert!(x.position()[axis] <= center.position()[axis]);
                 }
                 for x in right.as_ref() {
-                    assert!(center.position[axis] <= x.position[axis]);
+                    assert!(center.position()[axis] <= x.position()[axis]);
                 }
             }
             SeqTreeView::Empty => {}
         }
     }
-    pub fn nearest<Q: KdQuery<T>>(&self, query: &Q, count: usize) -> Vec<KdNeighbor<T>> where T: Debug {
+    pub fn nearest<Q: KdQuery<T>>(&self, query: &Q, count: usize) -> Vec<KdNeighbor<T, P>> where T: Debug {
         let mut heap = CappedHeap::with_capacity(count);
         let mut stats = Stats { explored: BTreeMap::new() };
         Self::nearest_rec(self.as_slice(), query, &mut heap, &mut stats);
         // println!("{:?}", stats);
         heap.into_sorted_vec()
     }
-    pub fn nearest_rec<'a, Q: KdQuery<T>>(tree: &'a SeqTree<KdEntry<T>>, query: &Q, heap: &mut CappedHeap<KdNeighbor<'a, T>>, stats: &mut Stats) where T: Debug {
+    pub fn nearest_rec<'a, Q: KdQuery<T>>(tree: &'a SeqTree<KdEntry<T, P>>, query: &Q, heap: &mut CappedHeap<KdNeighbor<'a, T, P>>, stats: &mut Stats) where T: Debug {
         match tree.as_view() {
             SeqTreeView::Node { left, center, right } => {
                 if heap.len() == heap.capacity() {
                     let max_so_far = heap.peek().map_or(f64::NEG_INFINITY, |max| max.distance);
-                    let min_possible = query.min_distance_bounds(&center.bounds);
+                    let min_possible = query.min_distance_bounds(&center.bounds.convert());
                     if min_possible > max_so_far {
                         return;
                     }
                 }
                 let axis = center.axis as usize;
-                let (first, second) = if query.center()[axis] < center.position[axis] {
+                let (first, second) = if query.center()[axis] < center.position()[axis] {
                     (left, right)
                 } else {
                     (right, left)
@@ -142,7 +153,7 @@ impl<T> KdTree<T> {
                 let mut more = heap.len() < heap.capacity();
                 if !more {
                     let max_so_far = heap.peek().map_or(f64::NEG_INFINITY, |max| max.distance);
-                    let min_possible = query.min_distance(AxisPlane { axis, coordinate: center.position[axis] });
+                    let min_possible = query.min_distance(AxisPlane { axis, coordinate: center.position()[axis] });
                     more = min_possible < max_so_far;
                 }
                 if more {
@@ -153,15 +164,15 @@ impl<T> KdTree<T> {
         }
     }
 
-    pub fn nearest_iter<Q: KdQuery<T>>(&self, query: Q) -> KdIter<T, Q> {
+    pub fn nearest_iter<Q: KdQuery<T>>(&self, query: Q) -> KdIter<T, Q, P> {
         let mut heap = BinaryHeap::new();
         heap.push(Reverse(ScalarKey::new(0.0, KdIterNode::KdNode(self.as_slice()))));
         KdIter { query, heap }
     }
 }
 
-impl<'a, T, Q: KdQuery<T>> Iterator for KdIter<'a, T, Q> {
-    type Item = KdNeighbor<'a, T>;
+impl<'a, T, Q: KdQuery<T>, P: Scalar> Iterator for KdIter<'a, T, Q, P> {
+    type Item = KdNeighbor<'a, T, P>;
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             let kv = self.heap.pop()?.0;
@@ -171,13 +182,13 @@ impl<'a, T, Q: KdQuery<T>> Iterator for KdIter<'a, T, Q> {
                     match tree.as_view() {
                         SeqTreeView::Node { left, center, right } => {
                             let axis = center.axis as usize;
-                            let (first, second) = if self.query.center()[axis] < center.position[axis] {
+                            let (first, second) = if self.query.center()[axis] < center.position()[axis] {
                                 (left, right)
                             } else {
                                 (right, left)
                             };
                             self.heap.push(Reverse(ScalarKey::new(0.0, KdIterNode::KdNode(first))));
-                            self.heap.push(Reverse(ScalarKey::new(self.query.min_distance(AxisPlane { axis, coordinate: center.position[axis] }), KdIterNode::KdNode(second))));
+                            self.heap.push(Reverse(ScalarKey::new(self.query.min_distance(AxisPlane { axis, coordinate: center.position()[axis] }), KdIterNode::KdNode(second))));
                             self.heap.push(Reverse(ScalarKey::new(self.query.distance(center), KdIterNode::KdEntry(center))));
                         }
                         SeqTreeView::Empty => {}
@@ -188,31 +199,31 @@ impl<'a, T, Q: KdQuery<T>> Iterator for KdIter<'a, T, Q> {
     }
 }
 
-impl<T> Default for KdTree<T> {
+impl<T, P> Default for KdTree<T, P> {
     fn default() -> Self { KdTree { entries: vec![] } }
 }
 
-impl<'a, T> Eq for KdNeighbor<'a, T> {}
+impl<'a, T, P> Eq for KdNeighbor<'a, T, P> {}
 
-impl<'a, T> PartialEq<Self> for KdNeighbor<'a, T> {
+impl<'a, T, P> PartialEq<Self> for KdNeighbor<'a, T, P> {
     fn eq(&self, other: &Self) -> bool {
         self.distance.real_eq(other.distance)
     }
 }
 
-impl<'a, T> PartialOrd<Self> for KdNeighbor<'a, T> {
+impl<'a, T, P> PartialOrd<Self> for KdNeighbor<'a, T, P> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.distance.real_cmp(other.distance))
     }
 }
 
-impl<'a, T> Ord for KdNeighbor<'a, T> {
+impl<'a, T, P> Ord for KdNeighbor<'a, T, P> {
     fn cmp(&self, other: &Self) -> Ordering {
         self.distance.real_cmp(other.distance)
     }
 }
 
-impl<T: Debug> Debug for KdTree<T> {
+impl<T: Debug, P: Scalar + Send> Debug for KdTree<T, P> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         self.as_slice().fmt(f)
     }
@@ -6,3 +6,10 @@ pub mod bv_kd_tree;
 pub mod seq_tree;
 pub mod bvh_subdivide;
 pub mod bvh_joint;
+pub mod bvh_sah;
+pub mod bvh_build;
+pub mod bvh_sbvh;
+pub mod bvh_aac;
+pub mod bvh_wide;
+pub mod primitive;
+pub mod bvh_refit;
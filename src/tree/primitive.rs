@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use crate::geo::bounds::Bounds;
+use crate::geo::indexed_mesh::IndexedMesh;
+use crate::geo::ray::Ray;
+use crate::geo::triangle::Sidedness;
+use crate::math::scalar::Scalar;
+use crate::math::vec::Vec3;
+use crate::render::object::{Manifold, Object, RaycastPoint};
+use crate::tree::bvh_sbvh::clip_triangle;
+
+// Primitives addressed by index, which is what BVH leaves store. The BVH pushes the index onto
+// the manifold of every hit, and hands the rest of the manifold back when replaying it.
+pub trait PrimitiveSet: Send + Sync {
+    fn len(&self) -> usize;
+    fn bounds(&self, index: u32) -> Bounds<f64>;
+    // Where builders sort the primitive; the center of its bounds unless there is a better one.
+    fn centroid(&self, index: u32) -> Vec3<f64> {
+        self.bounds(index).center()
+    }
+    fn raycast<T: Scalar>(&self, index: u32, ray: &Ray<T>, sidedness: Sidedness, manifold: Option<Manifold>) -> Option<RaycastPoint<T>>;
+    // Whether the primitive is hit before max_time, for any-hit queries, as Object::occluded.
+    fn occluded(&self, index: u32, ray: &Ray<f64>, sidedness: Sidedness, max_time: f64, ignore_transmissive: bool) -> bool {
+        self.raycast(index, ray, sidedness, None).map_or(false, |point| {
+            point.time < max_time && !(ignore_transmissive && point.material.is_transmissive())
+        })
+    }
+    // Bounds of the part of the primitive between lo and hi along axis, limited to within, for
+    // spatial splits. Clipping the box is always conservative.
+    fn clip(&self, index: u32, axis: usize, lo: f64, hi: f64, within: &Bounds<f64>) -> Bounds<f64> {
+        let mut slab = Bounds::full();
+        slab.min_mut()[axis] = lo;
+        slab.max_mut()[axis] = hi;
+        within.intersect(&slab)
+    }
+}
+
+impl PrimitiveSet for IndexedMesh {
+    fn len(&self) -> usize { self.triangle_count() }
+    fn bounds(&self, index: u32) -> Bounds<f64> { self.triangle_bounds(index) }
+    fn centroid(&self, index: u32) -> Vec3<f64> {
+        self.triangle(index).vertices().iter().copied().sum::<Vec3<f64>>() / 3.0
+    }
+    fn raycast<T: Scalar>(&self, index: u32, ray: &Ray<T>, sidedness: Sidedness, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
+        IndexedMesh::raycast(self, index, ray, sidedness, manifold.is_some())
+    }
+    fn clip(&self, index: u32, axis: usize, lo: f64, hi: f64, within: &Bounds<f64>) -> Bounds<f64> {
+        clip_triangle(&self.triangle(index), axis, lo, hi).intersect(within)
+    }
+}
+
+// Any objects, such as analytic spheres or nested instances, in their own space. Sidedness only
+// applies to triangles, so it is ignored here.
+impl<O: Object + Send> PrimitiveSet for Vec<O> {
+    fn len(&self) -> usize { <[O]>::len(self) }
+    fn bounds(&self, index: u32) -> Bounds<f64> { self[index as usize].bounds() }
+    fn raycast<T: Scalar>(&self, index: u32, ray: &Ray<T>, _: Sidedness, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
+        self[index as usize].raycast(ray, manifold)
+    }
+    fn occluded(&self, index: u32, ray: &Ray<f64>, _: Sidedness, max_time: f64, ignore_transmissive: bool) -> bool {
+        self[index as usize].occluded(ray, max_time, ignore_transmissive)
+    }
+}
+
+// So that a set can be passed to the builders as it is shared with the BVH.
+impl<S: PrimitiveSet + ?Sized> PrimitiveSet for Arc<S> {
+    fn len(&self) -> usize { (**self).len() }
+    fn bounds(&self, index: u32) -> Bounds<f64> { (**self).bounds(index) }
+    fn centroid(&self, index: u32) -> Vec3<f64> { (**self).centroid(index) }
+    fn raycast<T: Scalar>(&self, index: u32, ray: &Ray<T>, sidedness: Sidedness, manifold: Option<Manifold>) -> Option<RaycastPoint<T>> {
+        (**self).raycast(index, ray, sidedness, manifold)
+    }
+    fn occluded(&self, index: u32, ray: &Ray<f64>, sidedness: Sidedness, max_time: f64, ignore_transmissive: bool) -> bool {
+        (**self).occluded(index, ray, sidedness, max_time, ignore_transmissive)
+    }
+    fn clip(&self, index: u32, axis: usize, lo: f64, hi: f64, within: &Bounds<f64>) -> Bounds<f64> {
+        (**self).clip(index, axis, lo, hi, within)
+    }
+}
+
+#[test]
+fn test_sphere_bvh() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use crate::geo::sphere::Sphere;
+    use crate::render::material::Material;
+    use crate::render::sphere_object::SphereObject;
+    use crate::tree::bvh::{Bvh, BvhForest};
+    use crate::tree::bvh_sah::SahConfig;
+    use crate::tree::bvh_wide::WideBvh;
+    let mut rng = SmallRng::seed_from_u64(17);
+    let point = |rng: &mut SmallRng| Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+    let spheres: Vec<_> = (0..300).map(|_| {
+        SphereObject::new(Sphere::new(point(&mut rng) * 10.0, rng.gen_range(0.1..0.5)), Material::nan())
+    }).collect();
+    let spheres = Arc::new(spheres);
+    let tree = BvhForest::new(&spheres).sah(&SahConfig::default());
+    let bvh: Bvh<f64, _> = Bvh::with_precision(spheres.clone(), &tree);
+    let wide = WideBvh::<4, _>::new(spheres.clone(), &tree);
+    for _ in 0..200 {
+        let ray = Ray::new(point(&mut rng) * 12.0, point(&mut rng).normalize());
+        let expected = (0..300).filter_map(|i| spheres[i].raycast(&ray, None).map(|x| (x.time, i))).min_by(|x, y| x.0.total_cmp(&y.0));
+        let actual = bvh.raycast(&ray, Sidedness::Front, None);
+        assert_eq!(actual.as_ref().map(|x| x.time), expected.map(|x| x.0));
+        assert_eq!(wide.raycast(&ray, Sidedness::Front, None).map(|x| x.time), expected.map(|x| x.0));
+        if let Some(actual) = actual {
+            assert_eq!(actual.manifold.pop().unwrap().1, expected.unwrap().1);
+            assert_eq!(bvh.raycast(&ray, Sidedness::Front, Some(actual.manifold)).unwrap().time, actual.time);
+        }
+    }
+}